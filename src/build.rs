@@ -1,24 +1,34 @@
 use std::{marker::PhantomData, time::Duration};
 
-use crate::{expire, layer::{AndThen, Layer, LayerNone, Shard}, sync::{self, SyncCacheBuilder}, Cache, Value};
+use crate::{evict, expire, layer::{AndThen, Layer, LayerNone, Shard}, listener::RemovalListener, local::{self, LocalCacheBuilder}, sync::{self, SyncCacheBuilder}, Cache, Value};
 
-pub struct BuildCache<T, L = LayerNone> {
+pub struct BuildCache<T, L = LayerNone, N = ()> {
     _target: PhantomData<T>,
     layer: L,
+    listener: Option<N>,
+    stats: bool,
 }
 
 impl<T, L: Default> Default for BuildCache<T, L> {
     fn default() -> Self {
-        Self { _target: PhantomData, layer: L::default() }
+        Self { _target: PhantomData, layer: L::default(), listener: None, stats: false }
     }
 }
 
-impl<T: Value, L> BuildCache<T, L> {
-    pub fn layer<M>(self, layer: M) -> BuildCache<T, AndThen<L, M>> {
-        BuildCache { _target: PhantomData, layer: AndThen::new(self.layer, layer) }
+impl<T: Value, L, N> BuildCache<T, L, N> {
+    pub fn layer<M>(self, layer: M) -> BuildCache<T, AndThen<L, M>, N> {
+        BuildCache { _target: PhantomData, layer: AndThen::new(self.layer, layer), listener: self.listener, stats: self.stats }
     }
 
-    pub fn expire(self) -> BuildCache<T, AndThen<L, expire::ExpireLayer>>
+    pub fn removal_listener<N2>(self, listener: N2) -> BuildCache<T, L, N2> {
+        BuildCache { _target: PhantomData, layer: self.layer, listener: Some(listener), stats: self.stats }
+    }
+
+    pub fn stats(self) -> Self {
+        Self { stats: true, ..self }
+    }
+
+    pub fn expire(self) -> BuildCache<T, AndThen<L, expire::ExpireLayer>, N>
     where
         Self: Sized,
         T: expire::Expire,
@@ -26,7 +36,7 @@ impl<T: Value, L> BuildCache<T, L> {
         self.layer(expire::ExpireLayer)
     }
 
-    pub fn expire_at(self) -> BuildCache<T, AndThen<L, expire::ExpireAtLayer>>
+    pub fn expire_at(self) -> BuildCache<T, AndThen<L, expire::ExpireAtLayer>, N>
     where
         Self: Sized,
         T: expire::ExpireAt,
@@ -34,6 +44,11 @@ impl<T: Value, L> BuildCache<T, L> {
         self.layer(expire::ExpireAtLayer::default())
     }
 
+    /// Evict with [W-TinyLFU](evict::tiny_lfu::EvictTinyLfu) rather than anything recency based.
+    pub fn evict_tiny_lfu(self) -> BuildCache<T, AndThen<L, evict::tiny_lfu::EvictTinyLfu>, N> {
+        self.layer(evict::tiny_lfu::EvictTinyLfu::default())
+    }
+
     pub fn build_custom<C>(self, cache: impl FnOnce(L) -> C) -> C 
     where 
         C: Cache<T>,
@@ -42,13 +57,63 @@ impl<T: Value, L> BuildCache<T, L> {
         cache(self.layer)
     }
 
-    pub fn build_sync<Lv, Ls>(self) -> sync::SyncCache<T, L::Value, L::Shard>
+    pub fn build_sync<Lv, Ls>(mut self) -> sync::SyncCache<T, L::Value, L::Shard>
+    where 
+        L: Layer<sync::Pointer<T, Lv>, Value = Lv, Shard = Ls>,
+        Ls: Shard<sync::Pointer<T, Lv>, Value = Lv>,
+        T: 'static,
+        N: RemovalListener<sync::Pointer<T, Lv>> + Send + Sync + 'static,
+    {
+        let listener = self.listener.take();
+        let stats = self.stats;
+        self.build_custom(|layer| {
+            let builder = SyncCacheBuilder::new();
+            let builder = if stats { builder.stats() } else { builder };
+            match listener {
+                Some(listener) => builder.removal_listener(listener).build_with_layer::<T, L, Lv, Ls>(layer),
+                None => builder.build_with_layer::<T, L, Lv, Ls>(layer),
+            }
+        })
+    }
+
+    pub fn build_scalable<Lv, Ls>(mut self) -> sync::ScalableCache<T, L::Value, L::Shard>
     where 
         L: Layer<sync::Pointer<T, Lv>, Value = Lv, Shard = Ls>,
         Ls: Shard<sync::Pointer<T, Lv>, Value = Lv>,
         T: 'static,
+        T::Key: Sized + Clone,
+        Lv: 'static,
+        N: RemovalListener<sync::Pointer<T, Lv>> + Send + Sync + 'static,
+    {
+        let listener = self.listener.take();
+        let stats = self.stats;
+        self.build_custom(|layer| {
+            let builder = SyncCacheBuilder::new();
+            let builder = if stats { builder.stats() } else { builder };
+            match listener {
+                Some(listener) => builder.removal_listener(listener).build_scalable_with_layer::<T, L, Lv, Ls>(layer),
+                None => builder.build_scalable_with_layer::<T, L, Lv, Ls>(layer),
+            }
+        })
+    }
+
+    pub fn build_local<Lv, Ls>(mut self) -> local::LocalCache<T, L::Value, L::Shard>
+    where 
+        L: Layer<local::Pointer<T, Lv>, Value = Lv, Shard = Ls>,
+        Ls: Shard<local::Pointer<T, Lv>, Value = Lv>,
+        T: 'static,
+        N: RemovalListener<local::Pointer<T, Lv>> + 'static,
     {
-        self.build_custom(|layer| SyncCacheBuilder::default().build_with_layer::<T, L, Lv, Ls>(layer))
+        let listener = self.listener.take();
+        let stats = self.stats;
+        self.build_custom(|layer| {
+            let builder = LocalCacheBuilder::new();
+            let builder = if stats { builder.stats() } else { builder };
+            match listener {
+                Some(listener) => builder.removal_listener(listener).build_with_layer::<T, L, Lv, Ls>(layer),
+                None => builder.build_with_layer::<T, L, Lv, Ls>(layer),
+            }
+        })
     }
 }
 
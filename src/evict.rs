@@ -1,6 +1,16 @@
 
+pub mod adaptive;
+pub mod clock;
+pub mod cost;
+pub mod frequency;
 pub mod generation;
+pub mod greedy_dual;
+pub mod lirs;
+pub mod lru_k;
 pub mod read;
+pub mod s3_fifo;
+pub mod sieve;
+pub mod tiny_lfu;
 pub mod write;
 
 #[cfg(feature = "rand")]
@@ -9,5 +19,54 @@ pub mod random;
 #[cfg(feature = "rand")]
 mod bag;
 
-mod index;
-mod list;
\ No newline at end of file
+mod ghost;
+mod heap;
+pub(crate) mod index;
+pub(crate) mod list;
+pub mod weight;
+
+#[cfg(test)]
+pub(crate) mod test {
+    use crate::{
+        build::BuildCache,
+        layer::{Layer, Shard},
+        sync::{Pointer, SyncCache, SyncCacheBuilder},
+        Cache, Value,
+    };
+
+    pub(crate) struct Entry(pub u32);
+
+    impl Value for Entry {
+        type Key = u32;
+
+        fn key(&self) -> &u32 {
+            &self.0
+        }
+    }
+
+    /// One shard, so that whatever the layer picks to evict is what goes.
+    pub(crate) fn single_shard<T, L, Lv, Ls>(build: BuildCache<T, L>, capacity: usize) -> SyncCache<T, Lv, Ls>
+    where
+        T: Value + 'static,
+        L: Layer<Pointer<T, Lv>, Value = Lv, Shard = Ls>,
+        Ls: Shard<Pointer<T, Lv>, Value = Lv>,
+    {
+        build.build_custom(|layer| {
+            SyncCacheBuilder::new()
+                .exact_shards(1)
+                .capacity(capacity)
+                .build_with_layer(layer)
+        })
+    }
+
+    /// Keys of everything cached, sorted.
+    pub(crate) fn cached<T>(cache: &impl Cache<T>) -> Vec<T::Key>
+    where
+        T: Value,
+        T::Key: Sized + Ord + Clone,
+    {
+        let mut cached: Vec<_> = cache.iter().map(|p| p.key().clone()).collect();
+        cached.sort();
+        cached
+    }
+}
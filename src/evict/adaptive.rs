@@ -0,0 +1,303 @@
+use std::{
+    hash::{BuildHasher, Hash},
+    ops::Deref,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use hashbrown::hash_map::DefaultHashBuilder;
+
+use crate::{layer, Value};
+
+use super::ghost::Ghosts;
+use super::index::AtomicKey;
+use super::list::List;
+
+/// Adaptive Replacement Cache. Each shard splits its entries between those read once (T1) and
+/// those read again since (T2), and remembers the hashes of what it recently evicted from each
+/// (B1 and B2). A read that misses on something in B1 grows T1's share of the capacity, one in
+/// B2 shrinks it, so the split follows whether recency or frequency is paying off.
+///
+/// Only reads that miss adapt the split, since plain inserts aren't a sign of demand.
+#[derive(Debug, Clone)]
+pub struct EvictAdaptive<S = DefaultHashBuilder> {
+    hasher: S,
+}
+
+impl Default for EvictAdaptive {
+    fn default() -> Self {
+        Self::with_hasher(DefaultHashBuilder::default())
+    }
+}
+
+impl<S> EvictAdaptive<S> {
+    pub fn with_hasher(hasher: S) -> Self {
+        Self { hasher }
+    }
+}
+
+/// Where an entry sits in its shard.
+#[doc(hidden)]
+#[derive(Debug)]
+pub struct AdaptiveKey {
+    hash: u64,
+    t2: AtomicBool,
+    key: AtomicKey,
+}
+
+pub struct Shard<P, S> {
+    hasher: S,
+    capacity: usize,
+    // Target length of t1
+    target: usize,
+    t1: List<P>,
+    t2: List<P>,
+    b1: Ghosts,
+    b2: Ghosts,
+}
+
+impl<P, S> layer::Layer<P> for EvictAdaptive<S>
+where
+    P: Deref + Clone,
+    P::Target: Value,
+    S: BuildHasher + Clone,
+{
+    type Value = AdaptiveKey;
+    type Shard = Shard<P, S>;
+
+    fn new_shard(&self, capacity: usize) -> Self::Shard {
+        Shard {
+            hasher: self.hasher.clone(),
+            capacity,
+            target: 0,
+            t1: List::with_capacity(capacity),
+            t2: List::with_capacity(0),
+            b1: Ghosts::new(),
+            b2: Ghosts::new(),
+        }
+    }
+}
+
+impl<P: Deref + Clone, S> Shard<P, S> {
+    fn push<R: layer::Resolve<P, AdaptiveKey>>(&mut self, pointer: P) {
+        R::resolve(&pointer).t2.store(true, Ordering::Relaxed);
+        self.t2.push_tail_with_key(|key| {
+            R::resolve(&pointer).key.store(key, Ordering::Relaxed);
+            pointer
+        });
+    }
+
+    /// Evict from t1 or t2 into its ghost list, depending on how t1 compares to its target.
+    fn replace<R: layer::Resolve<P, AdaptiveKey>>(&mut self, in_b2: bool, remove: &mut impl FnMut(&P)) {
+        let from_t1 = self.t1.len() > 0
+            && (self.t1.len() > self.target || (in_b2 && self.t1.len() == self.target));
+        let (evicted, ghosts) = match from_t1 || self.t2.len() == 0 {
+            true => (self.t1.pop_head(), &mut self.b1),
+            false => (self.t2.pop_head(), &mut self.b2),
+        };
+        if let Some(evicted) = evicted {
+            ghosts.push(R::resolve(&evicted).hash);
+            remove(&evicted);
+        }
+    }
+
+    /// Make room for a new entry if the shard's full.
+    fn make_room<R: layer::Resolve<P, AdaptiveKey>>(&mut self, in_b2: bool, remove: &mut impl FnMut(&P)) {
+        if self.t1.len() + self.t2.len() >= self.capacity {
+            self.replace::<R>(in_b2, remove);
+        }
+    }
+}
+
+impl<P, S> layer::Shard<P> for Shard<P, S>
+where
+    P: Deref + Clone,
+    P::Target: Value,
+    S: BuildHasher,
+{
+    type Value = AdaptiveKey;
+
+    fn write<R: layer::Resolve<P, Self::Value>>(
+        &mut self,
+        mut write: impl layer::Write<P, Self::Value>,
+    ) -> P {
+        let hash = self.hasher.hash_one(write.target().key());
+        let mut remove = |p: &P| write.remove(p);
+
+        let ghost_hit = if self.b1.remove(hash) {
+            self.make_room::<R>(false, &mut remove);
+            true
+        } else if self.b2.remove(hash) {
+            self.make_room::<R>(true, &mut remove);
+            true
+        } else {
+            if self.t1.len() + self.b1.len() >= self.capacity {
+                if self.t1.len() < self.capacity {
+                    self.b1.pop_oldest();
+                    self.make_room::<R>(false, &mut remove);
+                } else if let Some(evicted) = self.t1.pop_head() {
+                    remove(&evicted);
+                }
+            } else if self.t1.len() + self.t2.len() + self.b1.len() + self.b2.len()
+                >= self.capacity
+            {
+                if self.t1.len() + self.t2.len() + self.b1.len() + self.b2.len()
+                    >= 2 * self.capacity
+                {
+                    self.b2.pop_oldest();
+                }
+                self.make_room::<R>(false, &mut remove);
+            }
+            false
+        };
+
+        // Coming back soon after being evicted counts as being read again
+        let list = if ghost_hit { &mut self.t2 } else { &mut self.t1 };
+        list.push_tail_with_key(|key| {
+            write.write(AdaptiveKey {
+                hash,
+                t2: ghost_hit.into(),
+                key: key.into(),
+            })
+        })
+        .clone()
+    }
+
+    fn remove<R: layer::Resolve<P, Self::Value>>(&mut self, pointer: &P) {
+        let value = R::resolve(pointer);
+        let key = value.key.load(Ordering::Relaxed);
+        let removed = match value.t2.load(Ordering::Relaxed) {
+            true => self.t2.remove(key),
+            false => self.t1.remove(key),
+        };
+        debug_assert!(removed.is_some());
+    }
+
+    fn set_capacity<R: layer::Resolve<P, Self::Value>>(
+        &mut self,
+        capacity: usize,
+        mut remove: impl FnMut(&P),
+    ) {
+        self.capacity = capacity;
+        self.target = self.target.min(capacity);
+        while self.t1.len() + self.t2.len() > capacity {
+            self.replace::<R>(false, &mut remove);
+        }
+        while self.t1.len() + self.b1.len() > capacity && self.b1.len() > 0 {
+            self.b1.pop_oldest();
+        }
+        while self.t1.len() + self.t2.len() + self.b1.len() + self.b2.len() > 2 * capacity
+            && self.b2.len() > 0
+        {
+            self.b2.pop_oldest();
+        }
+    }
+
+    fn eviction_order<R: layer::Resolve<P, Self::Value>>(&self, f: impl FnMut(&P)) -> bool {
+        self.t1.iter().chain(self.t2.iter()).for_each(f);
+        true
+    }
+
+    const OBSERVES_MISSES: bool = true;
+
+    fn miss<R: layer::Resolve<P, Self::Value>>(&mut self, key: &(impl Hash + ?Sized)) {
+        let hash = self.hasher.hash_one(key);
+        if self.b1.contains(hash) {
+            let delta = (self.b2.len() / self.b1.len()).max(1);
+            self.target = (self.target + delta).min(self.capacity);
+        } else if self.b2.contains(hash) {
+            let delta = (self.b1.len() / self.b2.len()).max(1);
+            self.target = self.target.saturating_sub(delta);
+        }
+    }
+
+    const READ_LOCK: layer::ReadLock = layer::ReadLock::Mut;
+
+    fn read_ref<R: layer::Resolve<P, Self::Value>>(&self, _pointer: &P) -> layer::ReadResult {
+        unreachable!()
+    }
+
+    fn read_mut<R: layer::Resolve<P, Self::Value>>(&mut self, pointer: &P) -> layer::ReadResult {
+        let value = R::resolve(pointer);
+        let key = value.key.load(Ordering::Relaxed);
+        match value.t2.load(Ordering::Relaxed) {
+            true => self.t2.move_to_tail(key),
+            false => {
+                if let Some(pointer) = self.t1.remove(key) {
+                    self.push::<R>(pointer);
+                }
+            }
+        }
+        layer::ReadResult::Retain
+    }
+
+    const ITER_READ_LOCK: layer::ReadLock = layer::ReadLock::None;
+}
+
+#[test]
+fn adapts() {
+    use crate::{build::BuildCache, Cache};
+
+    use super::test::{cached, single_shard, Entry};
+
+    let cache = single_shard(BuildCache::<Entry>::default().layer(EvictAdaptive::default()), 4);
+
+    // Reading these moves them to t2, leaving the rest to churn through t1 while it has no
+    // room to grow
+    for hot in 0..2 {
+        cache.insert(Entry(hot));
+        cache.get(&hot);
+    }
+    for cold in 2..6 {
+        cache.insert(Entry(cold));
+    }
+    assert_eq!(cached(&cache), [0, 1, 4, 5]);
+
+    // Missing on something recently evicted from t1 gives t1 more room, and bringing it back
+    // puts it in t2
+    assert!(cache.get(&2).is_none());
+    cache.insert(Entry(2));
+    assert_eq!(cached(&cache), [0, 1, 2, 5]);
+    cache.insert(Entry(6));
+    assert_eq!(cached(&cache), [1, 2, 5, 6]);
+
+    // Then missing on something evicted from t2 takes it back
+    assert!(cache.get(&0).is_none());
+    cache.insert(Entry(0));
+    assert_eq!(cached(&cache), [0, 1, 2, 6]);
+
+    cache.set_capacity(2);
+    assert_eq!(cache.len(), 2);
+}
+
+#[test]
+fn remove_leaves_no_ghost() {
+    use crate::{build::BuildCache, Cache};
+
+    use super::test::{cached, single_shard, Entry};
+
+    let cache = single_shard(BuildCache::<Entry>::default().layer(EvictAdaptive::default()), 3);
+
+    cache.insert(Entry(0));
+    cache.get(&0);
+    cache.insert(Entry(1));
+    cache.insert(Entry(2));
+    cache.remove(&0);
+    cache.remove(&1);
+
+    // Removing 0 from t2 didn't leave it in b2, so it comes back into t1 and goes first
+    for key in [0, 3, 4, 5] {
+        cache.insert(Entry(key));
+    }
+    assert_eq!(cached(&cache), [3, 4, 5]);
+
+    // With room for one, whatever's read moves to t2 and still makes way for the next write
+    cache.set_capacity(1);
+    assert_eq!(cached(&cache), [5]);
+    cache.insert(Entry(3));
+    cache.get(&3);
+    cache.insert(Entry(6));
+    assert_eq!(cached(&cache), [6]);
+    assert!(cache.get(&3).is_none());
+    cache.insert(Entry(3));
+    assert_eq!(cached(&cache), [3]);
+}
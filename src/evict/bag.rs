@@ -25,12 +25,18 @@ impl<T> Bag<T> {
         }
     }
 
+    pub fn grow_to(&mut self, capacity: usize) {
+        assert!(capacity <= Index::MAX.into_usize());
+        self.values
+            .reserve(capacity.saturating_sub(self.values.len()));
+    }
+
     pub fn len(&self) -> usize {
         self.values.len()
     }
 
-    pub fn capacity(&self) -> usize {
-        self.values.capacity()
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
     }
 
     pub fn insert_with_key(&mut self, construct: impl FnOnce(Key) -> T) -> &T {
@@ -57,7 +63,7 @@ impl<T> Bag<T> {
         rand: impl FnOnce(usize) -> usize,
         deref: impl Fn(&T) -> &Key,
     ) -> Option<T> {
-        if self.len() == 0 {
+        if self.is_empty() {
             return None;
         }
 
@@ -91,6 +97,6 @@ impl<T> Bag<T> {
             (deref(value), value)
         })
         // Avoid evaluating repeat_with if we're empty
-        .take(if self.len() == 0 { 0 } else { usize::MAX })
+        .take(if self.is_empty() { 0 } else { usize::MAX })
     }
 }
@@ -0,0 +1,222 @@
+use std::{
+    ops::Deref,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use crate::layer;
+
+use super::index::Key;
+use super::list::List;
+use super::weight::{Budget, Unweighted, Weigher};
+
+/// CLOCK, aka second chance. Reading an entry only marks it, so unlike
+/// [`EvictLeastRecentlyRead`](super::read::EvictLeastRecentlyRead) reads just need the shard's
+/// read lock. Writes sweep a hand over the shard, evicting the first entry that hasn't been
+/// read since the hand last passed and unmarking the ones that have.
+#[derive(Debug, Clone)]
+pub struct EvictClock<W = Unweighted> {
+    weigher: W,
+    chances: u8,
+}
+
+impl Default for EvictClock {
+    fn default() -> Self {
+        Self::with_weigher(Unweighted)
+    }
+}
+
+impl<W> EvictClock<W> {
+    pub fn with_weigher(weigher: W) -> Self {
+        Self { weigher, chances: 1 }
+    }
+
+    /// How many passes of the hand an entry can survive without being read in between, one by
+    /// default. Each read earns another pass up to this many, so higher values lean towards
+    /// frequency.
+    pub fn chances(self, chances: u8) -> Self {
+        assert!(chances > 0, "must allow at least one chance");
+        Self { chances, ..self }
+    }
+}
+
+/// Where an entry is on the clock, and how many more times the hand can pass it.
+#[doc(hidden)]
+#[derive(Debug)]
+pub struct ClockKey {
+    chances: AtomicU8,
+    key: Key,
+}
+
+pub struct Shard<P, W> {
+    // The hand points at the head, and entries it passes go round to the tail
+    list: List<P>,
+    budget: Budget<W>,
+    chances: u8,
+}
+
+impl<P, W> layer::Layer<P> for EvictClock<W>
+where
+    P: Deref + Clone,
+    W: Weigher<P::Target> + Clone,
+{
+    type Value = ClockKey;
+    type Shard = Shard<P, W>;
+
+    fn new_shard(&self, capacity: usize) -> Self::Shard {
+        let budget = Budget::new(self.weigher.clone(), capacity);
+        Shard {
+            list: List::with_capacity(budget.expected_len::<P::Target>()),
+            budget,
+            chances: self.chances,
+        }
+    }
+
+    fn expected_len(&self, capacity: usize) -> usize {
+        self.weigher.expected_len(capacity)
+    }
+}
+
+impl<P: Deref, W: Weigher<P::Target>> Shard<P, W> {
+    fn evict<R: layer::Resolve<P, ClockKey>>(&mut self, incoming: usize, mut remove: impl FnMut(&P)) {
+        while self.budget.overflows(incoming) {
+            let Some(hand) = self.list.iter().next() else {
+                break;
+            };
+            let value = R::resolve(hand);
+            let chances = value.chances.load(Ordering::Relaxed);
+            if chances > 0 {
+                value.chances.store(chances - 1, Ordering::Relaxed);
+                let key = value.key;
+                self.list.move_to_tail(key);
+                continue;
+            }
+
+            let Some(removed) = self.list.pop_head() else {
+                break;
+            };
+            self.budget.sub(&*removed);
+            remove(&removed);
+        }
+    }
+}
+
+impl<P, W> layer::Shard<P> for Shard<P, W>
+where
+    P: Deref + Clone,
+    W: Weigher<P::Target>,
+{
+    type Value = ClockKey;
+
+    fn write<R: layer::Resolve<P, Self::Value>>(
+        &mut self,
+        mut write: impl layer::Write<P, Self::Value>,
+    ) -> P {
+        let weight = self.budget.weigh(write.target());
+        self.evict::<R>(weight, |p| write.remove(p));
+        self.budget.add(weight);
+        self.list
+            .push_tail_with_key(|key| {
+                write.write(ClockKey {
+                    chances: 0.into(),
+                    key,
+                })
+            })
+            .clone()
+    }
+
+    fn remove<R: layer::Resolve<P, Self::Value>>(&mut self, pointer: &P) {
+        if self.list.remove(R::resolve(pointer).key).is_some() {
+            self.budget.sub(&**pointer);
+        }
+    }
+
+    fn set_capacity<R: layer::Resolve<P, Self::Value>>(&mut self, capacity: usize, remove: impl FnMut(&P)) {
+        self.budget.set_capacity(capacity);
+        self.evict::<R>(0, remove);
+        self.list.grow_to(self.budget.expected_len::<P::Target>());
+    }
+
+    fn eviction_order<R: layer::Resolve<P, Self::Value>>(&self, mut f: impl FnMut(&P)) -> bool {
+        // Exact with one chance, close enough with more
+        for chances in 0..=self.chances {
+            self.list
+                .iter()
+                .filter(|p| R::resolve(p).chances.load(Ordering::Relaxed) == chances)
+                .for_each(&mut f);
+        }
+        true
+    }
+
+    const READ_LOCK: layer::ReadLock = layer::ReadLock::Ref;
+
+    fn read_ref<R: layer::Resolve<P, Self::Value>>(&self, pointer: &P) -> layer::ReadResult {
+        let chances = &R::resolve(pointer).chances;
+        // Racing readers can lose an increment, which only costs the entry a chance. Checking
+        // first keeps already marked entries from bouncing their cache line between readers.
+        let current = chances.load(Ordering::Relaxed);
+        if current < self.chances {
+            chances.store(current + 1, Ordering::Relaxed);
+        }
+        layer::ReadResult::Retain
+    }
+
+    const ITER_READ_LOCK: layer::ReadLock = layer::ReadLock::None;
+}
+
+#[test]
+fn second_chance() {
+    use crate::{build::BuildCache, Cache};
+
+    use super::test::{cached, single_shard, Entry};
+
+    let cache = single_shard(BuildCache::<Entry>::default().layer(EvictClock::default()), 3);
+
+    for key in 0..3 {
+        cache.insert(Entry(key));
+    }
+    cache.get(&0);
+    cache.get(&0);
+    cache.insert(Entry(3));
+    assert_eq!(cached(&cache), [0, 2, 3]);
+    cache.insert(Entry(4));
+    assert_eq!(cached(&cache), [0, 3, 4]);
+    // The hand unmarked 0 on its way past, and it hasn't been read since
+    cache.insert(Entry(5));
+    assert_eq!(cached(&cache), [3, 4, 5]);
+
+    cache.set_capacity(1);
+    assert_eq!(cached(&cache), [5]);
+}
+
+#[test]
+fn concurrent_reads() {
+    use crate::{build::BuildCache, Cache};
+
+    use super::test::{cached, single_shard, Entry};
+
+    let cache = single_shard(
+        BuildCache::<Entry>::default().layer(EvictClock::default().chances(2)),
+        4,
+    );
+    for key in 0..4 {
+        cache.insert(Entry(key));
+    }
+    std::thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                for _ in 0..1000 {
+                    cache.get(&0);
+                    cache.get(&1);
+                }
+            });
+        }
+    });
+
+    // However the readers raced, 0 and 1 come out with exactly two chances each
+    for key in 4..8 {
+        cache.insert(Entry(key));
+    }
+    assert_eq!(cached(&cache), [0, 1, 6, 7]);
+    cache.insert(Entry(8));
+    assert_eq!(cached(&cache), [1, 6, 7, 8]);
+}
@@ -0,0 +1,42 @@
+/// Measures how expensive a value would be to get back after it's been evicted, in whatever
+/// unit suits, as long as it's used consistently. Costs must be finite and non-negative.
+///
+/// Like weight, the cost of a value must not change while it's in the cache.
+pub trait Coster<T: ?Sized> {
+    fn cost(&self, value: &T) -> f64;
+}
+
+/// Every value costs the same to get back.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Uniform;
+
+impl<T: ?Sized> Coster<T> for Uniform {
+    #[inline]
+    fn cost(&self, _value: &T) -> f64 {
+        1.0
+    }
+}
+
+/// For values that know their own cost, like how long they took to compute. Loading caches'
+/// values implement it as how long the load took.
+pub trait Cost {
+    fn cost(&self) -> f64;
+}
+
+/// Costs values by their [`Cost`] impl.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CostIntrusive;
+
+impl<T: ?Sized + Cost> Coster<T> for CostIntrusive {
+    #[inline]
+    fn cost(&self, value: &T) -> f64 {
+        value.cost()
+    }
+}
+
+impl<T: ?Sized, F: Fn(&T) -> f64> Coster<T> for F {
+    #[inline]
+    fn cost(&self, value: &T) -> f64 {
+        self(value)
+    }
+}
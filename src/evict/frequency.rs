@@ -0,0 +1,275 @@
+use std::{
+    ops::Deref,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use crate::layer;
+
+use super::index::AtomicKey;
+use super::list::List;
+use super::weight::{Budget, Unweighted, Weigher};
+
+const MAX_FREQUENCY: u8 = 15;
+
+/// Evict whatever's been read the fewest times, least recently read first among equals. Read
+/// counts saturate at 15 and are halved every so often so that entries that were popular a
+/// while ago don't hang around forever.
+#[derive(Debug, Clone)]
+pub struct EvictLeastFrequentlyRead<W = Unweighted> {
+    weigher: W,
+    halve_every: Option<usize>,
+}
+
+impl Default for EvictLeastFrequentlyRead {
+    fn default() -> Self {
+        Self::with_weigher(Unweighted)
+    }
+}
+
+impl<W> EvictLeastFrequentlyRead<W> {
+    pub fn with_weigher(weigher: W) -> Self {
+        Self {
+            weigher,
+            halve_every: None,
+        }
+    }
+
+    /// Halve every count after this many reads in a shard, rather than after ten times the
+    /// shard's capacity.
+    pub fn halve_every(self, reads: usize) -> Self {
+        assert!(reads > 0, "must read at least once between halvings");
+        Self {
+            halve_every: Some(reads),
+            ..self
+        }
+    }
+}
+
+/// Which frequency bucket an entry is in.
+#[doc(hidden)]
+#[derive(Debug)]
+pub struct FrequencyKey {
+    frequency: AtomicU8,
+    key: AtomicKey,
+}
+
+pub struct Shard<P, W> {
+    // One list per read count, least recently read first
+    buckets: Box<[List<P>]>,
+    budget: Budget<W>,
+    halve_every: Option<usize>,
+    period: usize,
+    reads: usize,
+}
+
+impl<P, W> layer::Layer<P> for EvictLeastFrequentlyRead<W>
+where
+    P: Deref + Clone,
+    W: Weigher<P::Target> + Clone,
+{
+    type Value = FrequencyKey;
+    type Shard = Shard<P, W>;
+
+    fn new_shard(&self, capacity: usize) -> Self::Shard {
+        let budget = Budget::new(self.weigher.clone(), capacity);
+        let mut buckets: Box<[_]> = std::iter::repeat_with(|| List::with_capacity(0))
+            .take(usize::from(MAX_FREQUENCY) + 1)
+            .collect();
+        buckets[0].grow_to(budget.expected_len::<P::Target>());
+        Shard {
+            buckets,
+            budget,
+            halve_every: self.halve_every,
+            period: period(self.halve_every, capacity),
+            reads: 0,
+        }
+    }
+
+    fn expected_len(&self, capacity: usize) -> usize {
+        self.weigher.expected_len(capacity)
+    }
+}
+
+fn period(halve_every: Option<usize>, capacity: usize) -> usize {
+    halve_every.unwrap_or_else(|| capacity.saturating_mul(10).max(1))
+}
+
+impl<P: Deref + Clone, W: Weigher<P::Target>> Shard<P, W> {
+    fn evict(&mut self, incoming: usize, mut remove: impl FnMut(&P)) {
+        while self.budget.overflows(incoming) {
+            let Some(removed) = self.buckets.iter_mut().find_map(List::pop_head) else {
+                break;
+            };
+            self.budget.sub(&*removed);
+            remove(&removed);
+        }
+    }
+
+    fn push<R: layer::Resolve<P, FrequencyKey>>(&mut self, frequency: u8, pointer: P) {
+        R::resolve(&pointer).frequency.store(frequency, Ordering::Relaxed);
+        self.buckets[usize::from(frequency)].push_tail_with_key(|key| {
+            R::resolve(&pointer).key.store(key, Ordering::Relaxed);
+            pointer
+        });
+    }
+
+    /// Halve every count, keeping the order within what ends up sharing a bucket.
+    fn age<R: layer::Resolve<P, FrequencyKey>>(&mut self) {
+        for frequency in 1..=MAX_FREQUENCY {
+            let index = usize::from(frequency);
+            let mut bucket = std::mem::replace(&mut self.buckets[index], List::with_capacity(0));
+            for pointer in bucket.drain() {
+                self.push::<R>(frequency / 2, pointer);
+            }
+            // Nothing lands back in a bucket while it's being halved, so keep its allocation
+            self.buckets[index] = bucket;
+        }
+    }
+}
+
+impl<P, W> layer::Shard<P> for Shard<P, W>
+where
+    P: Deref + Clone,
+    W: Weigher<P::Target>,
+{
+    type Value = FrequencyKey;
+
+    fn write<R: layer::Resolve<P, Self::Value>>(
+        &mut self,
+        mut write: impl layer::Write<P, Self::Value>,
+    ) -> P {
+        let weight = self.budget.weigh(write.target());
+        self.evict(weight, |p| write.remove(p));
+        self.budget.add(weight);
+        self.buckets[0]
+            .push_tail_with_key(|key| {
+                write.write(FrequencyKey {
+                    frequency: 0.into(),
+                    key: key.into(),
+                })
+            })
+            .clone()
+    }
+
+    fn remove<R: layer::Resolve<P, Self::Value>>(&mut self, pointer: &P) {
+        let value = R::resolve(pointer);
+        let bucket = usize::from(value.frequency.load(Ordering::Relaxed));
+        if self.buckets[bucket]
+            .remove(value.key.load(Ordering::Relaxed))
+            .is_some()
+        {
+            self.budget.sub(&**pointer);
+        }
+    }
+
+    fn set_capacity<R: layer::Resolve<P, Self::Value>>(
+        &mut self,
+        capacity: usize,
+        remove: impl FnMut(&P),
+    ) {
+        self.budget.set_capacity(capacity);
+        self.period = period(self.halve_every, capacity);
+        self.evict(0, remove);
+    }
+
+    fn eviction_order<R: layer::Resolve<P, Self::Value>>(&self, f: impl FnMut(&P)) -> bool {
+        self.buckets.iter().flat_map(List::iter).for_each(f);
+        true
+    }
+
+    const READ_LOCK: layer::ReadLock = layer::ReadLock::Mut;
+
+    fn read_ref<R: layer::Resolve<P, Self::Value>>(&self, _pointer: &P) -> layer::ReadResult {
+        unreachable!()
+    }
+
+    fn read_mut<R: layer::Resolve<P, Self::Value>>(&mut self, pointer: &P) -> layer::ReadResult {
+        let value = R::resolve(pointer);
+        let frequency = value.frequency.load(Ordering::Relaxed);
+        let key = value.key.load(Ordering::Relaxed);
+        if frequency < MAX_FREQUENCY {
+            if let Some(pointer) = self.buckets[usize::from(frequency)].remove(key) {
+                self.push::<R>(frequency + 1, pointer);
+            }
+        } else {
+            self.buckets[usize::from(frequency)].move_to_tail(key);
+        }
+
+        self.reads += 1;
+        if self.reads >= self.period {
+            self.reads = 0;
+            self.age::<R>();
+        }
+        layer::ReadResult::Retain
+    }
+
+    const ITER_READ_LOCK: layer::ReadLock = layer::ReadLock::None;
+}
+
+#[test]
+fn least_frequently_read() {
+    use crate::{build::BuildCache, Cache};
+
+    use super::test::{cached, single_shard, Entry};
+
+    let cache = single_shard(
+        BuildCache::<Entry>::default().layer(EvictLeastFrequentlyRead::default()),
+        3,
+    );
+    for key in 0..3 {
+        cache.insert(Entry(key));
+    }
+    cache.get(&0);
+    cache.get(&0);
+    cache.get(&2);
+    cache.insert(Entry(3));
+    assert_eq!(cached(&cache), [0, 2, 3]);
+    // Never read loses out even to something only just written
+    cache.insert(Entry(4));
+    assert_eq!(cached(&cache), [0, 2, 4]);
+
+    // Halving on the fourth read leaves 0 looking less popular than 1
+    let cache = single_shard(
+        BuildCache::<Entry>::default().layer(EvictLeastFrequentlyRead::default().halve_every(4)),
+        2,
+    );
+    cache.insert(Entry(0));
+    for _ in 0..4 {
+        cache.get(&0);
+    }
+    cache.insert(Entry(1));
+    for _ in 0..3 {
+        cache.get(&1);
+    }
+    cache.insert(Entry(2));
+    assert_eq!(cached(&cache), [1, 2]);
+}
+
+#[test]
+fn weighted_frequency() {
+    use crate::{build::BuildCache, Cache};
+
+    use super::test::{cached, single_shard, Entry};
+
+    // Each entry weighs its key
+    let cache = single_shard(
+        BuildCache::<Entry>::default()
+            .layer(EvictLeastFrequentlyRead::with_weigher(|e: &Entry| e.0 as usize)),
+        10,
+    );
+    for key in [5, 3, 2] {
+        cache.insert(Entry(key));
+    }
+    cache.get(&5);
+    cache.get(&5);
+    cache.get(&3);
+    // Fitting 4 takes both of the less read ones
+    cache.insert(Entry(4));
+    assert_eq!(cached(&cache), [4, 5]);
+
+    // Replacing 5 starts its count over
+    cache.get(&4);
+    cache.insert(Entry(5));
+    cache.insert(Entry(2));
+    assert_eq!(cached(&cache), [2, 4]);
+}
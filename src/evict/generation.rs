@@ -1,24 +1,52 @@
 use std::{
     fmt::Debug,
+    ops::Deref,
     sync::atomic::{AtomicBool, AtomicU32, Ordering},
     time::{Duration, Instant},
 };
 
+use crate::{
+    layer,
+    time::{Clock, DefaultClock},
+};
 
-use crate::time::{Clock, DefaultClock};
+use super::index::AtomicKey;
+use super::list::List;
 
 pub trait Promote {
     type Value: 'static;
 
     fn new_value(&self) -> Self::Value;
     fn try_touch_promote(&self, value: &Self::Value) -> bool;
+
+    /// Promote once both would. Every touch counts towards each of them.
+    fn and<O: Promote>(self, other: O) -> PromoteAnd<Self, O>
+    where
+        Self: Sized,
+    {
+        PromoteAnd(self, other)
+    }
+
+    /// Promote once either would. Every touch counts towards each of them.
+    fn or<O: Promote>(self, other: O) -> PromoteOr<Self, O>
+    where
+        Self: Sized,
+    {
+        PromoteOr(self, other)
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct PromoteAfterTouchCount {
     required_touches: u32,
 }
 
+impl PromoteAfterTouchCount {
+    pub fn new(required_touches: u32) -> Self {
+        Self { required_touches }
+    }
+}
+
 impl Default for PromoteAfterTouchCount {
     fn default() -> Self {
         Self {
@@ -36,11 +64,12 @@ impl Promote for PromoteAfterTouchCount {
 
     fn try_touch_promote(&self, value: &Self::Value) -> bool {
         let update = value.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |t| t.checked_sub(1));
-        matches!(update, Ok(0) | Err(_))
+        // The touch that takes it to zero promotes, as does any after
+        matches!(update, Ok(1) | Err(_))
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct PromoteTouchedAfterDuration<C = DefaultClock> {
     duration: Duration,
     clock: C,
@@ -55,6 +84,12 @@ impl<C: Default> Default for PromoteTouchedAfterDuration<C> {
     }
 }
 
+impl<C> PromoteTouchedAfterDuration<C> {
+    pub fn with_clock(duration: Duration, clock: C) -> Self {
+        Self { duration, clock }
+    }
+}
+
 impl<C: Clock> Promote for PromoteTouchedAfterDuration<C> {
     type Value = Instant;
 
@@ -70,7 +105,39 @@ impl<C: Clock> Promote for PromoteTouchedAfterDuration<C> {
     }
 }
 
-// XX add Promote::and
+#[derive(Debug, Clone)]
+pub struct PromoteAnd<A, B>(A, B);
+
+impl<A: Promote, B: Promote> Promote for PromoteAnd<A, B> {
+    type Value = (A::Value, B::Value);
+
+    fn new_value(&self) -> Self::Value {
+        (self.0.new_value(), self.1.new_value())
+    }
+
+    fn try_touch_promote(&self, value: &Self::Value) -> bool {
+        let a = self.0.try_touch_promote(&value.0);
+        let b = self.1.try_touch_promote(&value.1);
+        a && b
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PromoteOr<A, B>(A, B);
+
+impl<A: Promote, B: Promote> Promote for PromoteOr<A, B> {
+    type Value = (A::Value, B::Value);
+
+    fn new_value(&self) -> Self::Value {
+        (self.0.new_value(), self.1.new_value())
+    }
+
+    fn try_touch_promote(&self, value: &Self::Value) -> bool {
+        let a = self.0.try_touch_promote(&value.0);
+        let b = self.1.try_touch_promote(&value.1);
+        a || b
+    }
+}
 
 // XX: rather than atomic transfer, just store the state as an enum inside a Bag on the queue
 
@@ -78,133 +145,279 @@ pub trait AtomicTransfer {
     fn atomic_transfer(self, other: &Self, order: Ordering);
 }
 
+/// Segmented LRU: entries start out in a probation generation (g0) and move up to a protected
+/// generation (g1) once `promo` says they've been touched enough. Evictions come out of g0
+/// while there's anything in it, with g1's least recently read entries falling back into it
+/// when g1 fills up. g0 can use whatever room g1 isn't.
 #[derive(Debug, Clone, Copy)]
-pub struct EvictGenerational<Promo, G0, G1> {
+pub struct EvictGenerational<Promo = PromoteAfterTouchCount> {
     promo: Promo,
-    g0: G0,
-    g1: G1,
     g0_fraction: f64,
 }
 
-pub struct Value<P, T> {
+impl Default for EvictGenerational {
+    fn default() -> Self {
+        Self::new(PromoteAfterTouchCount::default())
+    }
+}
+
+impl<Promo> EvictGenerational<Promo> {
+    pub fn new(promo: Promo) -> Self {
+        Self {
+            promo,
+            g0_fraction: 0.2,
+        }
+    }
+
+    /// Share of each shard's capacity kept for g0, 20% by default.
+    pub fn g0_fraction(self, fraction: f64) -> Self {
+        assert!((0.0..=1.0).contains(&fraction), "g0 fraction out of range");
+        Self {
+            g0_fraction: fraction,
+            ..self
+        }
+    }
+}
+
+#[doc(hidden)]
+pub struct Value<P> {
     g0: AtomicBool,
     promo: P,
-    inner: T,
-}
-
-pub struct Queue<P, Q0, Q1> {
-    touched_removed: Vec<P>,
-    q0: Q0,
-    q1: Q1,
-}
-
-// XX switch to funcs instead of Point?
-
-// impl<P, Promo, G0, G1> Evict<P> for EvictGenerational<Promo, G0, G1>
-// where
-//     P: CloneStableDeref,
-//     Promo: Promote,
-//     G0: Evict<P>,
-//     G1: Evict<P, Value = G0::Value>,
-//     G0::Value: AtomicTransfer,
-// {
-//     type Value = Value<Promo::Value, G0::Value>;
-//     type Queue = Queue<P, G0::Queue, G1::Queue>;
-
-//     const TOUCH_LOCK_HINT: TouchLockHint = match (G0::TOUCH_LOCK_HINT, G1::TOUCH_LOCK_HINT) {
-//         (TouchLockHint::RequireWrite, TouchLockHint::RequireWrite) => TouchLockHint::RequireWrite,
-//         _ => TouchLockHint::MayWrite,
-//     };
-
-//     fn new_queue(&mut self, capacity: usize) -> Self::Queue {
-//         let g0_cap = ((self.g0_fraction * (capacity as f64)).round() as usize).max(1);
-//         Queue {
-//             touched_removed: Vec::with_capacity(capacity),
-//             q0: self.g0.new_queue(g0_cap),
-//             q1: self.g1.new_queue(capacity),
-//         }
-//     }
-
-//     fn insert(
-//         &self,
-//         queue: &mut Self::Queue,
-//         construct: impl FnOnce(Self::Value) -> P,
-//         deref: impl Fn(&P) -> &Self::Value,
-//     ) -> (P, impl Iterator<Item = P>) {
-//         let (inserted, removed) = self.g0.insert(
-//             &mut queue.q0,
-//             |v0| {
-//                 construct(Value {
-//                     g0: true.into(),
-//                     promo: self.promo.new_value(),
-//                     inner: v0,
-//                 })
-//             },
-//             move |p| &deref(p).inner,
-//         );
-//         // XX: why 4
-//         (
-//             inserted,
-//             removed.chain(std::iter::from_fn(|| queue.touched_removed.pop()).take(4)),
-//         )
-//     }
-
-//     fn touch(
-//         &self,
-//         queue: impl UpgradeReadGuard<Target = Self::Queue>,
-//         pointer: &P,
-//         deref: impl Fn(&P) -> &Self::Value,
-//     ) {
-//         let value = deref(pointer);
-//         // XX: can use relaxed since we don't modify g0 except under &mut
-//         match value.g0.load(Ordering::Relaxed) {
-//             true => {
-//                 if self.promo.try_touch_promote(&value.promo) {
-//                     let mut queue = UpgradeReadGuard::upgrade(queue);
-//                     match value.g0.load(Ordering::Relaxed) {
-//                         true => {
-//                             value.g0.store(false, Ordering::Relaxed);
-//                             self.g0
-//                                 .remove(&mut queue.q0, pointer, |p| &(&deref)(p).inner);
-//                             let (_pointer, removed) = self.g1.insert(
-//                                 &mut queue.q1,
-//                                 |v1| {
-//                                     v1.atomic_transfer(&value.inner, Ordering::Relaxed);
-//                                     pointer.clone()
-//                                 },
-//                                 |p| &(&deref)(p).inner,
-//                             );
-//                             let removed = removed.collect::<SmallVec<[_; 8]>>();
-//                             queue.touched_removed.extend(removed);
-//                         }
-//                         false => {
-//                             // someone else beat us to it, but we still need to touch g1
-//                             self.g1
-//                                 .touch(&mut queue.q1, pointer, |p| &(&deref)(p).inner);
-//                         }
-//                     }
-//                 } else {
-//                     let queue = MapUpgradeReadGuard::new(queue, |q| &q.q0, |q| &mut q.q0);
-//                     self.g0.touch(queue, pointer, move |p| &deref(p).inner);
-//                 }
-//             }
-//             false => {
-//                 let queue = MapUpgradeReadGuard::new(queue, |q| &q.q1, |q| &mut q.q1);
-//                 self.g1.touch(queue, pointer, move |p| &deref(p).inner);
-//             }
-//         }
-//     }
-
-//     fn remove(&self, queue: &mut Self::Queue, pointer: &P, deref: impl Fn(&P) -> &Self::Value) {
-//         let value = deref(pointer);
-//         // XX relaxed
-//         match value.g0.load(Ordering::Relaxed) {
-//             true => self
-//                 .g0
-//                 .remove(&mut queue.q0, pointer, move |p| &deref(p).inner),
-//             false => self
-//                 .g1
-//                 .remove(&mut queue.q1, pointer, move |p| &deref(p).inner),
-//         }
-//     }
-// }
+    key: AtomicKey,
+}
+
+pub struct Shard<P, Promo> {
+    promo: Promo,
+    g0_fraction: f64,
+    g0: List<P>,
+    g1: List<P>,
+    g0_capacity: usize,
+    g1_capacity: usize,
+}
+
+impl<P, Promo> layer::Layer<P> for EvictGenerational<Promo>
+where
+    P: Deref + Clone,
+    Promo: Promote + Clone,
+{
+    type Value = Value<Promo::Value>;
+    type Shard = Shard<P, Promo>;
+
+    fn new_shard(&self, capacity: usize) -> Self::Shard {
+        let mut shard = Shard {
+            promo: self.promo.clone(),
+            g0_fraction: self.g0_fraction,
+            g0: List::with_capacity(0),
+            g1: List::with_capacity(0),
+            g0_capacity: 0,
+            g1_capacity: 0,
+        };
+        shard.resize(capacity);
+        shard
+    }
+}
+
+impl<P: Deref + Clone, Promo: Promote> Shard<P, Promo> {
+    fn resize(&mut self, capacity: usize) {
+        self.g0_capacity = ((capacity as f64 * self.g0_fraction).round() as usize)
+            .clamp(capacity.min(1), capacity);
+        self.g1_capacity = capacity - self.g0_capacity;
+        self.g0.grow_to(self.g0_capacity);
+        self.g1.grow_to(self.g1_capacity);
+    }
+
+    fn push<R: layer::Resolve<P, Value<Promo::Value>>>(&mut self, g0: bool, pointer: P) {
+        R::resolve(&pointer).g0.store(g0, Ordering::Relaxed);
+        let list = if g0 { &mut self.g0 } else { &mut self.g1 };
+        list.push_tail_with_key(|key| {
+            R::resolve(&pointer).key.store(key, Ordering::Relaxed);
+            pointer
+        });
+    }
+
+    /// Drop g1 back down to size, oldest first, into g0.
+    fn demote<R: layer::Resolve<P, Value<Promo::Value>>>(&mut self) {
+        while self.g1.len() > self.g1_capacity {
+            let Some(demoted) = self.g1.pop_head() else {
+                break;
+            };
+            self.push::<R>(true, demoted);
+        }
+    }
+
+    /// Evict down to `capacity` in total, from g0 unless there's nothing left there.
+    fn evict(&mut self, capacity: usize, mut remove: impl FnMut(&P)) {
+        while self.g0.len() + self.g1.len() > capacity {
+            let Some(removed) = self.g0.pop_head().or_else(|| self.g1.pop_head()) else {
+                break;
+            };
+            remove(&removed);
+        }
+    }
+}
+
+impl<P, Promo> layer::Shard<P> for Shard<P, Promo>
+where
+    P: Deref + Clone,
+    Promo: Promote,
+{
+    type Value = Value<Promo::Value>;
+
+    fn write<R: layer::Resolve<P, Self::Value>>(
+        &mut self,
+        mut write: impl layer::Write<P, Self::Value>,
+    ) -> P {
+        let capacity = self.g0_capacity + self.g1_capacity;
+        self.evict(capacity.saturating_sub(1), |p| write.remove(p));
+        let promo = self.promo.new_value();
+        self.g0
+            .push_tail_with_key(|key| {
+                write.write(Value {
+                    g0: true.into(),
+                    promo,
+                    key: key.into(),
+                })
+            })
+            .clone()
+    }
+
+    fn remove<R: layer::Resolve<P, Self::Value>>(&mut self, pointer: &P) {
+        let value = R::resolve(pointer);
+        let key = value.key.load(Ordering::Relaxed);
+        let removed = match value.g0.load(Ordering::Relaxed) {
+            true => self.g0.remove(key),
+            false => self.g1.remove(key),
+        };
+        debug_assert!(removed.is_some());
+    }
+
+    fn set_capacity<R: layer::Resolve<P, Self::Value>>(
+        &mut self,
+        capacity: usize,
+        remove: impl FnMut(&P),
+    ) {
+        self.resize(capacity);
+        self.demote::<R>();
+        self.evict(capacity, remove);
+    }
+
+    fn eviction_order<R: layer::Resolve<P, Self::Value>>(&self, f: impl FnMut(&P)) -> bool {
+        self.g0.iter().chain(self.g1.iter()).for_each(f);
+        true
+    }
+
+    const READ_LOCK: layer::ReadLock = layer::ReadLock::Mut;
+
+    fn read_ref<R: layer::Resolve<P, Self::Value>>(&self, _pointer: &P) -> layer::ReadResult {
+        unreachable!()
+    }
+
+    fn read_mut<R: layer::Resolve<P, Self::Value>>(&mut self, pointer: &P) -> layer::ReadResult {
+        let value = R::resolve(pointer);
+        let key = value.key.load(Ordering::Relaxed);
+        if !value.g0.load(Ordering::Relaxed) {
+            self.g1.move_to_tail(key);
+        } else if self.promo.try_touch_promote(&value.promo) {
+            if let Some(promoted) = self.g0.remove(key) {
+                self.push::<R>(false, promoted);
+                self.demote::<R>();
+            }
+        } else {
+            self.g0.move_to_tail(key);
+        }
+        layer::ReadResult::Retain
+    }
+
+    const ITER_READ_LOCK: layer::ReadLock = layer::ReadLock::None;
+}
+
+#[test]
+fn segmented() {
+    use crate::{build::BuildCache, Cache};
+
+    use super::test::{cached, single_shard, Entry};
+
+    let promo = PromoteAfterTouchCount::new(2).or(PromoteAfterTouchCount::new(5));
+    let cache = single_shard(
+        BuildCache::<Entry>::default().layer(EvictGenerational::new(promo).g0_fraction(0.5)),
+        4,
+    );
+
+    // Two reads each get 0 and 1 promoted
+    for key in 0..2 {
+        cache.insert(Entry(key));
+        cache.get(&key);
+        cache.get(&key);
+    }
+    for key in 2..10 {
+        cache.insert(Entry(key));
+    }
+    assert_eq!(cached(&cache), [0, 1, 8, 9]);
+
+    // Promoting 8 pushes the least recently read of g1 back down, where 9 is older
+    cache.get(&1);
+    cache.get(&8);
+    cache.get(&8);
+    cache.insert(Entry(10));
+    assert_eq!(cached(&cache), [0, 1, 8, 10]);
+
+    cache.set_capacity(2);
+    assert_eq!(cached(&cache), [1, 8]);
+
+    let both = PromoteAfterTouchCount::new(1).and(PromoteAfterTouchCount::new(3));
+    let value = both.new_value();
+    assert!(!both.try_touch_promote(&value));
+    assert!(!both.try_touch_promote(&value));
+    assert!(both.try_touch_promote(&value));
+}
+
+#[test]
+fn unpromoted_fill_capacity() {
+    use crate::{build::BuildCache, Cache};
+
+    use super::test::{single_shard, Entry};
+
+    let cache = single_shard(
+        BuildCache::<Entry>::default().layer(EvictGenerational::default()),
+        100,
+    );
+    for key in 0..1000 {
+        cache.insert(Entry(key));
+    }
+    assert_eq!(cache.len(), 100);
+}
+
+#[test]
+fn remove_promoted() {
+    use crate::{build::BuildCache, Cache};
+
+    use super::test::{cached, single_shard, Entry};
+
+    let promo = PromoteAfterTouchCount::new(1);
+    let cache = single_shard(
+        BuildCache::<Entry>::default().layer(EvictGenerational::new(promo).g0_fraction(0.5)),
+        4,
+    );
+
+    for key in 0..4 {
+        cache.insert(Entry(key));
+    }
+    // Promoting 2 overfills g1 and sends 0 back down
+    for key in 0..3 {
+        cache.get(&key);
+    }
+    cache.remove(&1);
+
+    // 1's room in g1 counts towards the total like any other
+    cache.insert(Entry(4));
+    assert_eq!(cached(&cache), [0, 2, 3, 4]);
+    cache.insert(Entry(5));
+    assert_eq!(cached(&cache), [0, 2, 4, 5]);
+
+    // With no room for g1 at all, 2 comes down last and so is kept
+    cache.set_capacity(1);
+    assert_eq!(cached(&cache), [2]);
+    cache.get(&2);
+    cache.insert(Entry(6));
+    assert_eq!(cached(&cache), [6]);
+}
@@ -0,0 +1,52 @@
+use hashbrown::HashMap;
+
+use super::index::Key;
+use super::list::List;
+
+/// Hashes of recently evicted entries, oldest first.
+pub(crate) struct Ghosts {
+    list: List<u64>,
+    keys: HashMap<u64, Key>,
+}
+
+impl Ghosts {
+    pub fn new() -> Self {
+        Self {
+            list: List::with_capacity(0),
+            keys: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    pub fn contains(&self, hash: u64) -> bool {
+        self.keys.contains_key(&hash)
+    }
+
+    pub fn push(&mut self, hash: u64) {
+        self.remove(hash);
+        let mut pushed = None;
+        self.list.push_tail_with_key(|key| {
+            pushed = Some(key);
+            hash
+        });
+        if let Some(key) = pushed {
+            self.keys.insert(hash, key);
+        }
+    }
+
+    pub fn remove(&mut self, hash: u64) -> bool {
+        match self.keys.remove(&hash) {
+            Some(key) => self.list.remove(key).is_some(),
+            None => false,
+        }
+    }
+
+    pub fn pop_oldest(&mut self) -> Option<u64> {
+        let hash = self.list.pop_head()?;
+        self.keys.remove(&hash);
+        Some(hash)
+    }
+}
@@ -0,0 +1,275 @@
+use std::{
+    ops::Deref,
+    sync::atomic::{AtomicU32, AtomicUsize, Ordering},
+};
+
+use crate::layer;
+
+use super::cost::{Coster, Uniform};
+use super::heap::{Heap, HeapPosition};
+use super::weight::{Budget, Unweighted, Weigher};
+
+/// GreedyDual-Size-Frequency. Each entry's priority is `L + reads * cost / weight`, where `L`
+/// is the priority of whatever was last evicted from its shard, and the lowest priority goes
+/// first. Entries that are expensive to get back, small, or often read outlive the rest, while
+/// `L` creeping up makes sure nothing hangs around forever on what it was worth long ago.
+#[derive(Debug, Clone)]
+pub struct EvictGreedyDual<C = Uniform, W = Unweighted> {
+    coster: C,
+    weigher: W,
+}
+
+impl Default for EvictGreedyDual {
+    fn default() -> Self {
+        Self::with_coster(Uniform)
+    }
+}
+
+impl<C> EvictGreedyDual<C> {
+    pub fn with_coster(coster: C) -> Self {
+        Self {
+            coster,
+            weigher: Unweighted,
+        }
+    }
+}
+
+impl<C, W> EvictGreedyDual<C, W> {
+    /// Bound shards by total weight rather than entry count. Weight is also the size that an
+    /// entry's cost is divided by.
+    pub fn weigher<W2>(self, weigher: W2) -> EvictGreedyDual<C, W2> {
+        EvictGreedyDual {
+            coster: self.coster,
+            weigher,
+        }
+    }
+}
+
+/// Where an entry is in its shard's heap, and what it's worth per read.
+#[doc(hidden)]
+#[derive(Debug)]
+pub struct GreedyDualKey {
+    position: AtomicUsize,
+    reads: AtomicU32,
+    cost_per_weight: f64,
+}
+
+impl HeapPosition for GreedyDualKey {
+    fn heap_position(&self) -> &AtomicUsize {
+        &self.position
+    }
+}
+
+pub struct Shard<P, C, W> {
+    coster: C,
+    budget: Budget<W>,
+    heap: Heap<f64, P>,
+    inflation: f64,
+}
+
+impl<P, C, W> layer::Layer<P> for EvictGreedyDual<C, W>
+where
+    P: Deref + Clone,
+    C: Coster<P::Target> + Clone,
+    W: Weigher<P::Target> + Clone,
+{
+    type Value = GreedyDualKey;
+    type Shard = Shard<P, C, W>;
+
+    fn new_shard(&self, capacity: usize) -> Self::Shard {
+        let budget = Budget::new(self.weigher.clone(), capacity);
+        Shard {
+            coster: self.coster.clone(),
+            heap: Heap::with_capacity(budget.expected_len::<P::Target>()),
+            budget,
+            inflation: 0.0,
+        }
+    }
+
+    fn expected_len(&self, capacity: usize) -> usize {
+        self.weigher.expected_len(capacity)
+    }
+}
+
+impl<P: Deref, C, W: Weigher<P::Target>> Shard<P, C, W> {
+    fn evict<R: layer::Resolve<P, GreedyDualKey>>(
+        &mut self,
+        incoming: usize,
+        mut remove: impl FnMut(&P),
+    ) {
+        while self.budget.overflows(incoming) {
+            let Some((priority, removed)) = self.heap.pop::<R, _>() else {
+                break;
+            };
+            self.inflation = priority;
+            self.budget.sub(&*removed);
+            remove(&removed);
+        }
+    }
+}
+
+impl<P, C, W> layer::Shard<P> for Shard<P, C, W>
+where
+    P: Deref + Clone,
+    C: Coster<P::Target>,
+    W: Weigher<P::Target>,
+{
+    type Value = GreedyDualKey;
+
+    fn write<R: layer::Resolve<P, Self::Value>>(
+        &mut self,
+        mut write: impl layer::Write<P, Self::Value>,
+    ) -> P {
+        let weight = self.budget.weigh(write.target());
+        let cost = self.coster.cost(write.target());
+        assert!(
+            cost.is_finite() && cost >= 0.0,
+            "cost must be finite and non-negative"
+        );
+        let cost_per_weight = cost / weight.max(1) as f64;
+        self.evict::<R>(weight, |p| write.remove(p));
+        self.budget.add(weight);
+
+        let pointer = write.write(GreedyDualKey {
+            position: 0.into(),
+            reads: 1.into(),
+            cost_per_weight,
+        });
+        self.heap
+            .push::<R, _>(self.inflation + cost_per_weight, pointer.clone());
+        pointer
+    }
+
+    fn remove<R: layer::Resolve<P, Self::Value>>(&mut self, pointer: &P) {
+        if self.heap.remove::<R, _>(pointer).is_some() {
+            self.budget.sub(&**pointer);
+        }
+    }
+
+    fn set_capacity<R: layer::Resolve<P, Self::Value>>(
+        &mut self,
+        capacity: usize,
+        remove: impl FnMut(&P),
+    ) {
+        self.budget.set_capacity(capacity);
+        self.evict::<R>(0, remove);
+        self.heap.grow_to(self.budget.expected_len::<P::Target>());
+    }
+
+    fn eviction_order<R: layer::Resolve<P, Self::Value>>(&self, f: impl FnMut(&P)) -> bool {
+        let mut order: Vec<_> = self.heap.iter().collect();
+        order.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+        order
+            .into_iter()
+            .map(|(_priority, pointer)| pointer)
+            .for_each(f);
+        true
+    }
+
+    const READ_LOCK: layer::ReadLock = layer::ReadLock::Mut;
+
+    fn read_ref<R: layer::Resolve<P, Self::Value>>(&self, _pointer: &P) -> layer::ReadResult {
+        unreachable!()
+    }
+
+    fn read_mut<R: layer::Resolve<P, Self::Value>>(&mut self, pointer: &P) -> layer::ReadResult {
+        let value = R::resolve(pointer);
+        let reads = value.reads.load(Ordering::Relaxed).saturating_add(1);
+        value.reads.store(reads, Ordering::Relaxed);
+        let priority = self.inflation + reads as f64 * value.cost_per_weight;
+        self.heap.set_priority::<R, _>(pointer, priority);
+        layer::ReadResult::Retain
+    }
+
+    const ITER_READ_LOCK: layer::ReadLock = layer::ReadLock::None;
+}
+
+#[test]
+fn greedy_dual() {
+    use crate::{build::BuildCache, Cache};
+
+    use super::cost::{Cost, CostIntrusive};
+    use super::test::{cached, single_shard};
+
+    struct Entry(u32, f64);
+
+    impl crate::Value for Entry {
+        type Key = u32;
+
+        fn key(&self) -> &u32 {
+            &self.0
+        }
+    }
+
+    impl Cost for Entry {
+        fn cost(&self) -> f64 {
+            self.1
+        }
+    }
+
+    let cache = single_shard(
+        BuildCache::<Entry>::default().layer(EvictGreedyDual::with_coster(CostIntrusive)),
+        3,
+    );
+
+    cache.insert(Entry(0, 100.0));
+    cache.insert(Entry(1, 2.0));
+    cache.insert(Entry(2, 1.0));
+    cache.insert(Entry(3, 5.0));
+    assert_eq!(cached(&cache), [0, 1, 3]);
+    cache.insert(Entry(4, 1.0));
+    assert_eq!(cached(&cache), [0, 3, 4]);
+
+    // Reads add up, but not to enough to outlast 3
+    cache.get(&4);
+    cache.get(&4);
+    cache.insert(Entry(5, 1.0));
+    assert_eq!(cached(&cache), [0, 3, 5]);
+
+    cache.set_capacity(1);
+    assert_eq!(cached(&cache), [0]);
+}
+
+#[test]
+fn weighted() {
+    use crate::{build::BuildCache, Cache};
+
+    use super::cost::{Cost, CostIntrusive};
+    use super::test::{cached, single_shard};
+
+    struct Blob(u32, f64, usize);
+
+    impl crate::Value for Blob {
+        type Key = u32;
+
+        fn key(&self) -> &u32 {
+            &self.0
+        }
+    }
+
+    impl Cost for Blob {
+        fn cost(&self) -> f64 {
+            self.1
+        }
+    }
+
+    let cache = single_shard(
+        BuildCache::<Blob>::default()
+            .layer(EvictGreedyDual::with_coster(CostIntrusive).weigher(|b: &Blob| b.2)),
+        10,
+    );
+
+    cache.insert(Blob(0, 8.0, 8));
+    cache.insert(Blob(1, 2.0, 1));
+    cache.insert(Blob(2, 1.5, 1));
+    // 0 costs the most to get back, but the least for the room it takes
+    cache.insert(Blob(3, 1.0, 1));
+    assert_eq!(cached(&cache), [1, 2, 3]);
+
+    // Removing 1 gives its weight back, so 4 fits without evicting anything
+    cache.remove(&1);
+    cache.insert(Blob(4, 80.0, 8));
+    assert_eq!(cached(&cache), [2, 3, 4]);
+    cache.insert(Blob(5, 1.0, 1));
+    assert_eq!(cached(&cache), [3, 4, 5]);
+}
@@ -0,0 +1,121 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::layer;
+
+/// A layer value that remembers where its entry is in a [`Heap`].
+pub(crate) trait HeapPosition {
+    fn heap_position(&self) -> &AtomicUsize;
+}
+
+/// Min-heap of pointers by priority, which keeps each entry's position up to date in its layer
+/// value so that it can be found again to be removed or reprioritised.
+pub(crate) struct Heap<O, P> {
+    entries: Vec<(O, P)>,
+}
+
+impl<O: PartialOrd, P> Heap<O, P> {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            entries: Vec::with_capacity(capacity),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn grow_to(&mut self, capacity: usize) {
+        self.entries
+            .reserve(capacity.saturating_sub(self.entries.len()));
+    }
+
+    /// In no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = &(O, P)> + '_ {
+        self.entries.iter()
+    }
+
+    pub fn push<R: layer::Resolve<P, V>, V: HeapPosition>(&mut self, priority: O, pointer: P) {
+        let position = self.entries.len();
+        R::resolve(&pointer)
+            .heap_position()
+            .store(position, Ordering::Relaxed);
+        self.entries.push((priority, pointer));
+        self.sift_up::<R, V>(position);
+    }
+
+    /// Take out whatever has the lowest priority.
+    pub fn pop<R: layer::Resolve<P, V>, V: HeapPosition>(&mut self) -> Option<(O, P)> {
+        (!self.is_empty()).then(|| self.take::<R, V>(0))
+    }
+
+    pub fn remove<R: layer::Resolve<P, V>, V: HeapPosition>(
+        &mut self,
+        pointer: &P,
+    ) -> Option<(O, P)> {
+        let position = R::resolve(pointer).heap_position().load(Ordering::Relaxed);
+        (position < self.entries.len()).then(|| self.take::<R, V>(position))
+    }
+
+    pub fn set_priority<R: layer::Resolve<P, V>, V: HeapPosition>(
+        &mut self,
+        pointer: &P,
+        priority: O,
+    ) {
+        let position = R::resolve(pointer).heap_position().load(Ordering::Relaxed);
+        self.entries[position].0 = priority;
+        self.sift_up::<R, V>(position);
+        self.sift_down::<R, V>(position);
+    }
+
+    fn take<R: layer::Resolve<P, V>, V: HeapPosition>(&mut self, position: usize) -> (O, P) {
+        let last = self.entries.len() - 1;
+        self.swap::<R, V>(position, last);
+        let taken = self.entries.pop().unwrap();
+        if position < self.entries.len() {
+            self.sift_up::<R, V>(position);
+            self.sift_down::<R, V>(position);
+        }
+        taken
+    }
+
+    fn swap<R: layer::Resolve<P, V>, V: HeapPosition>(&mut self, a: usize, b: usize) {
+        self.entries.swap(a, b);
+        R::resolve(&self.entries[a].1)
+            .heap_position()
+            .store(a, Ordering::Relaxed);
+        R::resolve(&self.entries[b].1)
+            .heap_position()
+            .store(b, Ordering::Relaxed);
+    }
+
+    fn sift_up<R: layer::Resolve<P, V>, V: HeapPosition>(&mut self, mut position: usize) {
+        while position > 0 {
+            let parent = (position - 1) / 2;
+            if self.entries[parent].0 <= self.entries[position].0 {
+                break;
+            }
+            self.swap::<R, V>(parent, position);
+            position = parent;
+        }
+    }
+
+    fn sift_down<R: layer::Resolve<P, V>, V: HeapPosition>(&mut self, mut position: usize) {
+        loop {
+            let mut least = position;
+            for child in [2 * position + 1, 2 * position + 2] {
+                if child < self.entries.len() && self.entries[child].0 < self.entries[least].0 {
+                    least = child;
+                }
+            }
+            if least == position {
+                break;
+            }
+            self.swap::<R, V>(position, least);
+            position = least;
+        }
+    }
+}
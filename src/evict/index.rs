@@ -53,6 +53,9 @@ pub struct Key {
     pub(super) gen: Generation,
 }
 
+// Layer values are shared with readers so anything a shard tracks in them has to be atomic, but
+// keys and the like are only ever touched with the shard held mutably, and marks that reads set
+// under the read lock are only taken down under the write lock, so relaxed is enough
 #[derive(Debug)]
 #[doc(hidden)]
 pub struct AtomicKey(AtomicU64);
@@ -67,8 +70,8 @@ fn key_to_u64(key: Key) -> u64 {
 fn u64_to_key(value: u64) -> Key {
     let as_bytes = value.to_ne_bytes();
     Key {
-        gen: Generation(NonZero::new(u32::from_be_bytes((&as_bytes[..4]).try_into().unwrap())).unwrap()),
-        index: Index(NonZero::new(u32::from_be_bytes((&as_bytes[4..]).try_into().unwrap())).unwrap()),
+        index: Index(NonZero::new(u32::from_ne_bytes((&as_bytes[..4]).try_into().unwrap())).unwrap()),
+        gen: Generation(NonZero::new(u32::from_ne_bytes((&as_bytes[4..]).try_into().unwrap())).unwrap()),
     }
 }
 
@@ -0,0 +1,417 @@
+use std::{
+    hash::BuildHasher,
+    ops::Deref,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use hashbrown::{hash_map::DefaultHashBuilder, HashMap};
+
+use crate::{layer, Value};
+
+use super::ghost::Ghosts;
+use super::index::{AtomicKey, Key};
+use super::list::List;
+
+const LIR: u8 = 1;
+const STACKED: u8 = 2;
+const QUEUED: u8 = 4;
+
+/// Low Inter-reference Recency Set. Most of each shard is kept for entries that have been read
+/// again sooner than the rest (LIR), and only a small queue of the others (HIR) is ever
+/// evicted from. A recency stack, which also remembers the hashes of recently evicted HIR
+/// entries, decides when an HIR entry has earned a place among the LIR ones. Unlike LRU this
+/// keeps most of its hits on loops over more entries than fit.
+#[derive(Debug, Clone)]
+pub struct EvictLirs<S = DefaultHashBuilder> {
+    hasher: S,
+    hir_fraction: f64,
+}
+
+impl Default for EvictLirs {
+    fn default() -> Self {
+        Self::with_hasher(DefaultHashBuilder::default())
+    }
+}
+
+impl<S> EvictLirs<S> {
+    pub fn with_hasher(hasher: S) -> Self {
+        Self {
+            hasher,
+            hir_fraction: 0.01,
+        }
+    }
+
+    /// Share of each shard's capacity given to HIR entries, 1% by default.
+    pub fn hir(self, fraction: f64) -> Self {
+        assert!((0.0..=1.0).contains(&fraction), "HIR fraction out of range");
+        Self {
+            hir_fraction: fraction,
+            ..self
+        }
+    }
+}
+
+/// Whether an entry is LIR, and where it is on the stack and queue.
+#[doc(hidden)]
+#[derive(Debug)]
+pub struct LirsKey {
+    hash: u64,
+    flags: AtomicU8,
+    stack: AtomicKey,
+    // Only meaningful while queued
+    queue: AtomicKey,
+}
+
+impl LirsKey {
+    fn flags(&self) -> u8 {
+        self.flags.load(Ordering::Relaxed)
+    }
+
+    fn set_flags(&self, flags: u8) {
+        self.flags.store(flags, Ordering::Relaxed);
+    }
+}
+
+enum Stacked<P> {
+    Resident(P),
+    // An evicted HIR entry's hash
+    NonResident(u64),
+}
+
+pub struct Shard<P, S> {
+    hasher: S,
+    hir_fraction: f64,
+    capacity: usize,
+    lir_capacity: usize,
+    lir_len: usize,
+    // Least recent first, always starting with an LIR entry
+    stack: List<Stacked<P>>,
+    // Resident HIR entries, next to be evicted first
+    queue: List<P>,
+    // Where each non-resident entry is on the stack, and the order to forget them in
+    non_resident: HashMap<u64, Key>,
+    ghosts: Ghosts,
+}
+
+impl<P, S> layer::Layer<P> for EvictLirs<S>
+where
+    P: Deref + Clone,
+    P::Target: Value,
+    S: BuildHasher + Clone,
+{
+    type Value = LirsKey;
+    type Shard = Shard<P, S>;
+
+    fn new_shard(&self, capacity: usize) -> Self::Shard {
+        let mut shard = Shard {
+            hasher: self.hasher.clone(),
+            hir_fraction: self.hir_fraction,
+            capacity: 0,
+            lir_capacity: 0,
+            lir_len: 0,
+            stack: List::with_capacity(0),
+            queue: List::with_capacity(0),
+            non_resident: HashMap::new(),
+            ghosts: Ghosts::new(),
+        };
+        shard.resize(capacity);
+        shard
+    }
+}
+
+impl<P: Deref + Clone, S> Shard<P, S> {
+    fn resize(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        let hir_capacity = ((capacity as f64 * self.hir_fraction).round() as usize)
+            .clamp(capacity.min(1), capacity);
+        self.lir_capacity = capacity - hir_capacity;
+
+        self.stack.grow_to(capacity);
+        self.queue.grow_to(hir_capacity);
+    }
+
+    fn resident_len(&self) -> usize {
+        self.lir_len + self.queue.len()
+    }
+
+    fn enqueue<R: layer::Resolve<P, LirsKey>>(&mut self, pointer: P) {
+        let value = R::resolve(&pointer);
+        value.set_flags(value.flags() | QUEUED);
+        self.queue.push_tail_with_key(|key| {
+            R::resolve(&pointer).queue.store(key, Ordering::Relaxed);
+            pointer
+        });
+    }
+
+    fn push<R: layer::Resolve<P, LirsKey>>(&mut self, pointer: P) {
+        let value = R::resolve(&pointer);
+        value.set_flags(value.flags() | STACKED);
+        self.stack.push_tail_with_key(|key| {
+            R::resolve(&pointer).stack.store(key, Ordering::Relaxed);
+            Stacked::Resident(pointer)
+        });
+    }
+
+    /// Drop HIR entries off the bottom of the stack until it starts with an LIR one.
+    fn prune<R: layer::Resolve<P, LirsKey>>(&mut self) {
+        while let Some(key) = self.stack.head_key() {
+            match self.stack.get(key) {
+                Some(Stacked::Resident(pointer)) => {
+                    let value = R::resolve(pointer);
+                    if value.flags() & LIR != 0 {
+                        break;
+                    }
+                    value.set_flags(value.flags() & !STACKED);
+                }
+                Some(&Stacked::NonResident(hash)) => {
+                    self.non_resident.remove(&hash);
+                    self.ghosts.remove(hash);
+                }
+                None => unreachable!(),
+            }
+            self.stack.remove(key);
+        }
+    }
+
+    /// Turn the least recent LIR entry into a resident HIR one.
+    fn demote<R: layer::Resolve<P, LirsKey>>(&mut self) {
+        self.prune::<R>();
+        let Some(Stacked::Resident(pointer)) = self.stack.pop_head() else {
+            return;
+        };
+        R::resolve(&pointer).set_flags(0);
+        self.lir_len -= 1;
+        self.enqueue::<R>(pointer);
+        self.prune::<R>();
+    }
+
+    /// Evict the next resident HIR entry, leaving its hash on the stack if it's there.
+    fn evict<R: layer::Resolve<P, LirsKey>>(&mut self, remove: &mut impl FnMut(&P)) -> bool {
+        if self.queue.len() == 0 && self.lir_len > 0 {
+            self.demote::<R>();
+        }
+        let Some(pointer) = self.queue.pop_head() else {
+            return false;
+        };
+        let value = R::resolve(&pointer);
+        if value.flags() & STACKED != 0 {
+            let key = value.stack.load(Ordering::Relaxed);
+            if let Some(stacked) = self.stack.get_mut(key) {
+                *stacked = Stacked::NonResident(value.hash);
+                self.non_resident.insert(value.hash, key);
+                self.ghosts.push(value.hash);
+                self.trim_ghosts();
+            }
+        }
+        value.set_flags(0);
+        remove(&pointer);
+        true
+    }
+
+    fn trim_ghosts(&mut self) {
+        while self.ghosts.len() > self.capacity {
+            let Some(hash) = self.ghosts.pop_oldest() else {
+                break;
+            };
+            if let Some(key) = self.non_resident.remove(&hash) {
+                self.stack.remove(key);
+            }
+        }
+    }
+}
+
+impl<P, S> layer::Shard<P> for Shard<P, S>
+where
+    P: Deref + Clone,
+    P::Target: Value,
+    S: BuildHasher,
+{
+    type Value = LirsKey;
+
+    fn write<R: layer::Resolve<P, Self::Value>>(
+        &mut self,
+        mut write: impl layer::Write<P, Self::Value>,
+    ) -> P {
+        let hash = self.hasher.hash_one(write.target().key());
+        while self.resident_len() >= self.capacity {
+            if !self.evict::<R>(&mut |p| write.remove(p)) {
+                break;
+            }
+        }
+
+        // Coming back while still on the stack means it was read again sooner than the least
+        // recent LIR entry
+        let non_resident = self.non_resident.remove(&hash);
+        if let Some(key) = non_resident {
+            self.ghosts.remove(hash);
+            self.stack.remove(key);
+        }
+        let lir = self.lir_capacity > 0
+            && (self.lir_len < self.lir_capacity || non_resident.is_some());
+
+        let flags = if lir { LIR | STACKED } else { STACKED };
+        let Stacked::Resident(pointer) = self.stack.push_tail_with_key(|key| {
+            Stacked::Resident(write.write(LirsKey {
+                hash,
+                flags: flags.into(),
+                stack: key.into(),
+                queue: key.into(),
+            }))
+        }) else {
+            unreachable!()
+        };
+        let pointer = pointer.clone();
+
+        if lir {
+            self.lir_len += 1;
+            if self.lir_len > self.lir_capacity {
+                self.demote::<R>();
+            }
+        } else {
+            self.enqueue::<R>(pointer.clone());
+        }
+        pointer
+    }
+
+    fn remove<R: layer::Resolve<P, Self::Value>>(&mut self, pointer: &P) {
+        let value = R::resolve(pointer);
+        let flags = value.flags();
+        if flags & QUEUED != 0 {
+            self.queue.remove(value.queue.load(Ordering::Relaxed));
+        }
+        if flags & STACKED != 0 {
+            self.stack.remove(value.stack.load(Ordering::Relaxed));
+        }
+        if flags & LIR != 0 {
+            self.lir_len -= 1;
+            self.prune::<R>();
+        }
+        value.set_flags(0);
+    }
+
+    fn set_capacity<R: layer::Resolve<P, Self::Value>>(
+        &mut self,
+        capacity: usize,
+        mut remove: impl FnMut(&P),
+    ) {
+        self.resize(capacity);
+        while self.lir_len > self.lir_capacity {
+            self.demote::<R>();
+        }
+        while self.resident_len() > capacity {
+            if !self.evict::<R>(&mut remove) {
+                break;
+            }
+        }
+        self.trim_ghosts();
+    }
+
+    fn eviction_order<R: layer::Resolve<P, Self::Value>>(&self, mut f: impl FnMut(&P)) -> bool {
+        self.queue.iter().for_each(&mut f);
+        for stacked in self.stack.iter() {
+            if let Stacked::Resident(pointer) = stacked {
+                if R::resolve(pointer).flags() & LIR != 0 {
+                    f(pointer);
+                }
+            }
+        }
+        true
+    }
+
+    const READ_LOCK: layer::ReadLock = layer::ReadLock::Mut;
+
+    fn read_ref<R: layer::Resolve<P, Self::Value>>(&self, _pointer: &P) -> layer::ReadResult {
+        unreachable!()
+    }
+
+    fn read_mut<R: layer::Resolve<P, Self::Value>>(&mut self, pointer: &P) -> layer::ReadResult {
+        let value = R::resolve(pointer);
+        let flags = value.flags();
+        let stack_key = value.stack.load(Ordering::Relaxed);
+
+        if flags & LIR != 0 {
+            let bottom = self.stack.head_key() == Some(stack_key);
+            self.stack.move_to_tail(stack_key);
+            if bottom {
+                self.prune::<R>();
+            }
+        } else if flags & STACKED != 0 {
+            // Read again before falling off the stack, so it's earned its place
+            self.queue.remove(value.queue.load(Ordering::Relaxed));
+            value.set_flags(LIR | STACKED);
+            self.stack.move_to_tail(stack_key);
+            self.lir_len += 1;
+            if self.lir_len > self.lir_capacity {
+                self.demote::<R>();
+            }
+        } else {
+            self.queue.move_to_tail(value.queue.load(Ordering::Relaxed));
+            self.push::<R>(pointer.clone());
+        }
+        layer::ReadResult::Retain
+    }
+
+    const ITER_READ_LOCK: layer::ReadLock = layer::ReadLock::None;
+}
+
+#[test]
+fn looping() {
+    use crate::{build::BuildCache, Cache};
+
+    use super::test::{single_shard, Entry};
+
+    let cache = single_shard(BuildCache::<Entry>::default().layer(EvictLirs::default()), 10);
+
+    // Looping over more than fits leaves LRU without a single hit
+    let mut hits = 0;
+    for _ in 0..5 {
+        for key in 0..15 {
+            match cache.get(&key) {
+                Some(_) => hits += 1,
+                None => {
+                    cache.insert(Entry(key));
+                }
+            }
+        }
+    }
+    assert_eq!(cache.len(), 10);
+    assert_eq!(hits, 4 * 9);
+
+    cache.set_capacity(4);
+    assert_eq!(cache.len(), 4);
+    assert_eq!((0..15).filter(|key| cache.get(key).is_some()).count(), 4);
+}
+
+#[test]
+fn remove_lir() {
+    use crate::{build::BuildCache, Cache};
+
+    use super::test::{cached, single_shard, Entry};
+
+    // Room for three LIR entries and one HIR
+    let cache = single_shard(
+        BuildCache::<Entry>::default().layer(EvictLirs::default().hir(0.25)),
+        4,
+    );
+    for key in 0..4 {
+        cache.insert(Entry(key));
+    }
+    // Removing an LIR entry opens up a place for the next write
+    cache.remove(&1);
+    cache.insert(Entry(4));
+    cache.insert(Entry(5));
+    cache.insert(Entry(6));
+    assert_eq!(cached(&cache), [0, 2, 4, 6]);
+
+    // 3 is still on the stack, so coming back pushes 0 down to HIR in its place
+    cache.insert(Entry(3));
+    cache.insert(Entry(7));
+    assert_eq!(cached(&cache), [2, 3, 4, 7]);
+
+    // Nothing's LIR with room for only one
+    cache.set_capacity(1);
+    assert_eq!(cache.len(), 1);
+    cache.get(&7);
+    cache.insert(Entry(8));
+    assert_eq!(cached(&cache), [8]);
+}
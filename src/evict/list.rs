@@ -27,7 +27,6 @@ enum NodeState<T> {
 #[doc(hidden)]
 impl<T> List<T> {
     pub fn with_capacity(capacity: usize) -> Self {
-        assert!(capacity > 0, "capacity must not be empty");
         assert!(capacity <= Index::MAX.into(), "capacity too large");
 
         Self {
@@ -39,23 +38,15 @@ impl<T> List<T> {
         }
     }
 
-    pub fn len(&self) -> usize {
-        self.len as usize
-    }
-
-    pub fn capacity(&self) -> usize {
-        self.nodes.capacity()
+    /// Make room for at least `capacity` nodes in total without reallocating.
+    pub fn grow_to(&mut self, capacity: usize) {
+        assert!(capacity <= Index::MAX.into(), "capacity too large");
+        self.nodes
+            .reserve(capacity.saturating_sub(self.nodes.len()));
     }
 
-    pub fn push_tail_with_key_and_pop_if_full(&mut self, value: impl FnOnce(Key) -> T) -> (&T, Option<T>) {
-        debug_assert!(self.capacity() > 0);
-        let removed = if self.len() == self.capacity() {
-            self.pop_head()
-        } else {
-            None
-        };
-        let pushed = self.push_tail_with_key(value);
-        (pushed, removed)
+    pub fn len(&self) -> usize {
+        self.len as usize
     }
 
     pub fn push_tail_with_key(&mut self, value: impl FnOnce(Key) -> T) -> &T {
@@ -89,12 +80,21 @@ impl<T> List<T> {
                     _ => unreachable!(),
                 };
 
-                node.gen.increment_mut();
                 node.state = node_state(value(key));
                 key
             }
         };
 
+        match tail {
+            Some(tail) => match &mut self.nodes[tail.into_usize()].state {
+                NodeState::Occupied { prev, .. } => *prev = Some(key.index),
+                _ => unreachable!(),
+            },
+            None => self.head = Some(key.index),
+        }
+        self.tail = Some(key.index);
+        self.len += 1;
+
         let NodeState::Occupied { value, .. } = &self.nodes[key.index.into_usize()].state else {
             unreachable!()
         };
@@ -102,11 +102,30 @@ impl<T> List<T> {
     }
 
     pub fn pop_head(&mut self) -> Option<T> {
-        let index = self.head?;
-        self.remove(Key {
+        self.remove(self.head_key()?)
+    }
+
+    pub fn head_key(&self) -> Option<Key> {
+        self.head.map(|index| self.key_at(index))
+    }
+
+    /// The key of whatever comes after `key` on the way to the tail.
+    pub fn next_key(&self, key: Key) -> Option<Key> {
+        let node = self
+            .nodes
+            .get(key.index.into_usize())
+            .filter(|node| node.gen == key.gen)?;
+        match node.state {
+            NodeState::Occupied { prev, .. } => prev.map(|index| self.key_at(index)),
+            NodeState::Vacant { .. } => None,
+        }
+    }
+
+    fn key_at(&self, index: Index) -> Key {
+        Key {
             index,
             gen: self.nodes[index.into_usize()].gen,
-        })
+        }
     }
 
     pub fn remove(&mut self, key: Key) -> Option<T> {
@@ -131,6 +150,7 @@ impl<T> List<T> {
         });
 
         self.prune_link(prev, next);
+        self.len -= 1;
 
         Some(value)
     }
@@ -186,6 +206,7 @@ impl<T> List<T> {
             }
             _ => unreachable!(),
         }
+        self.tail = Some(key.index);
     }
 
     pub fn get(&self, key: Key) -> Option<&T> {
@@ -198,6 +219,29 @@ impl<T> List<T> {
             })
     }
 
+    pub fn get_mut(&mut self, key: Key) -> Option<&mut T> {
+        self.nodes
+            .get_mut(key.index.into_usize())
+            .filter(|node| node.gen == key.gen)
+            .and_then(|node| match &mut node.state {
+                NodeState::Occupied { value, .. } => Some(value),
+                NodeState::Vacant { .. } => None,
+            })
+    }
+
+    /// Head to tail.
+    pub fn iter(&self) -> impl Iterator<Item = &T> + '_ {
+        let mut index = self.head;
+        std::iter::from_fn(move || {
+            let NodeState::Occupied { value, prev, .. } = &self.nodes[index?.into_usize()].state
+            else {
+                unreachable!()
+            };
+            index = *prev;
+            Some(value)
+        })
+    }
+
     pub fn drain(&mut self) -> impl Iterator<Item = T> + '_ {
         std::iter::from_fn(|| self.pop_head())
     }
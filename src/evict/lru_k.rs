@@ -0,0 +1,287 @@
+use std::{
+    hash::BuildHasher,
+    ops::Deref,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
+use hashbrown::{hash_map::DefaultHashBuilder, HashMap};
+
+use crate::{layer, Value};
+
+use super::ghost::Ghosts;
+use super::heap::{Heap, HeapPosition};
+
+/// LRU-K. Evicts whatever's K-th most recent access is oldest, where writes count as accesses
+/// too. Entries that haven't been accessed K times yet go first, least recently accessed
+/// first, so one-off accesses don't push out entries that keep being read. Each shard also
+/// remembers the access history of as many evicted entries as it can hold, so something
+/// written again soon after being evicted picks up where it left off.
+#[derive(Debug, Clone)]
+pub struct EvictLruK<S = DefaultHashBuilder> {
+    hasher: S,
+    k: usize,
+}
+
+impl Default for EvictLruK {
+    fn default() -> Self {
+        Self::with_hasher(DefaultHashBuilder::default())
+    }
+}
+
+impl<S> EvictLruK<S> {
+    pub fn with_hasher(hasher: S) -> Self {
+        Self { hasher, k: 2 }
+    }
+
+    /// How many accesses back to look, 2 by default. 1 is plain LRU.
+    pub fn k(self, k: usize) -> Self {
+        assert!(k > 0, "must look at least one access back");
+        Self { k, ..self }
+    }
+}
+
+/// The last K times an entry was accessed, and where it is in its shard's heap.
+#[doc(hidden)]
+#[derive(Debug)]
+pub struct LruKKey {
+    hash: u64,
+    position: AtomicUsize,
+    // Most recent first, 0 for accesses that never happened
+    history: Box<[AtomicU64]>,
+}
+
+impl HeapPosition for LruKKey {
+    fn heap_position(&self) -> &AtomicUsize {
+        &self.position
+    }
+}
+
+impl LruKKey {
+    fn access(&self, now: u64) {
+        for i in (1..self.history.len()).rev() {
+            let previous = self.history[i - 1].load(Ordering::Relaxed);
+            self.history[i].store(previous, Ordering::Relaxed);
+        }
+        self.history[0].store(now, Ordering::Relaxed);
+    }
+
+    /// K-th most recent access if there's been that many, then most recent.
+    fn priority(&self) -> (Option<u64>, u64) {
+        let kth = self.history[self.history.len() - 1].load(Ordering::Relaxed);
+        (
+            (kth != 0).then_some(kth),
+            self.history[0].load(Ordering::Relaxed),
+        )
+    }
+}
+
+pub struct Shard<P, S> {
+    hasher: S,
+    k: usize,
+    capacity: usize,
+    // Counts accesses rather than time, since only the order matters
+    now: u64,
+    heap: Heap<(Option<u64>, u64), P>,
+    // Histories of recently evicted entries, by hash
+    retained: HashMap<u64, Box<[u64]>>,
+    ghosts: Ghosts,
+}
+
+impl<P, S> layer::Layer<P> for EvictLruK<S>
+where
+    P: Deref + Clone,
+    P::Target: Value,
+    S: BuildHasher + Clone,
+{
+    type Value = LruKKey;
+    type Shard = Shard<P, S>;
+
+    fn new_shard(&self, capacity: usize) -> Self::Shard {
+        Shard {
+            hasher: self.hasher.clone(),
+            k: self.k,
+            capacity,
+            now: 0,
+            heap: Heap::with_capacity(capacity),
+            retained: HashMap::new(),
+            ghosts: Ghosts::new(),
+        }
+    }
+}
+
+impl<P: Deref, S> Shard<P, S> {
+    fn evict<R: layer::Resolve<P, LruKKey>>(&mut self, remove: &mut impl FnMut(&P)) -> bool {
+        let Some((_priority, removed)) = self.heap.pop::<R, _>() else {
+            return false;
+        };
+        let value = R::resolve(&removed);
+        let history = value
+            .history
+            .iter()
+            .map(|access| access.load(Ordering::Relaxed))
+            .collect();
+        self.retained.insert(value.hash, history);
+        self.ghosts.push(value.hash);
+        self.trim_retained();
+        remove(&removed);
+        true
+    }
+
+    fn trim_retained(&mut self) {
+        while self.ghosts.len() > self.capacity {
+            let Some(hash) = self.ghosts.pop_oldest() else {
+                break;
+            };
+            self.retained.remove(&hash);
+        }
+    }
+}
+
+impl<P, S> layer::Shard<P> for Shard<P, S>
+where
+    P: Deref + Clone,
+    P::Target: Value,
+    S: BuildHasher,
+{
+    type Value = LruKKey;
+
+    fn write<R: layer::Resolve<P, Self::Value>>(
+        &mut self,
+        mut write: impl layer::Write<P, Self::Value>,
+    ) -> P {
+        let hash = self.hasher.hash_one(write.target().key());
+        while self.heap.len() >= self.capacity {
+            if !self.evict::<R>(&mut |p| write.remove(p)) {
+                break;
+            }
+        }
+
+        let history = match self.retained.remove(&hash) {
+            Some(history) => {
+                self.ghosts.remove(hash);
+                history.iter().map(|&access| access.into()).collect()
+            }
+            None => (0..self.k).map(|_| 0.into()).collect(),
+        };
+        let pointer = write.write(LruKKey {
+            hash,
+            position: 0.into(),
+            history,
+        });
+        let value = R::resolve(&pointer);
+        self.now += 1;
+        value.access(self.now);
+        self.heap.push::<R, _>(value.priority(), pointer.clone());
+        pointer
+    }
+
+    fn remove<R: layer::Resolve<P, Self::Value>>(&mut self, pointer: &P) {
+        let removed = self.heap.remove::<R, _>(pointer);
+        debug_assert!(removed.is_some());
+    }
+
+    fn set_capacity<R: layer::Resolve<P, Self::Value>>(
+        &mut self,
+        capacity: usize,
+        mut remove: impl FnMut(&P),
+    ) {
+        self.capacity = capacity;
+        while self.heap.len() > capacity {
+            if !self.evict::<R>(&mut remove) {
+                break;
+            }
+        }
+        self.trim_retained();
+        self.heap.grow_to(capacity);
+    }
+
+    fn eviction_order<R: layer::Resolve<P, Self::Value>>(&self, f: impl FnMut(&P)) -> bool {
+        let mut order: Vec<_> = self.heap.iter().collect();
+        order.sort_by_key(|(priority, _)| *priority);
+        order
+            .into_iter()
+            .map(|(_priority, pointer)| pointer)
+            .for_each(f);
+        true
+    }
+
+    const READ_LOCK: layer::ReadLock = layer::ReadLock::Mut;
+
+    fn read_ref<R: layer::Resolve<P, Self::Value>>(&self, _pointer: &P) -> layer::ReadResult {
+        unreachable!()
+    }
+
+    fn read_mut<R: layer::Resolve<P, Self::Value>>(&mut self, pointer: &P) -> layer::ReadResult {
+        let value = R::resolve(pointer);
+        self.now += 1;
+        value.access(self.now);
+        self.heap.set_priority::<R, _>(pointer, value.priority());
+        layer::ReadResult::Retain
+    }
+
+    const ITER_READ_LOCK: layer::ReadLock = layer::ReadLock::None;
+}
+
+#[test]
+fn lru_k() {
+    use crate::{build::BuildCache, Cache};
+
+    use super::test::{cached, single_shard, Entry};
+
+    let cache = single_shard(
+        BuildCache::<Entry>::default().layer(EvictLruK::default()),
+        3,
+    );
+
+    cache.insert(Entry(0));
+    cache.get(&0);
+    cache.insert(Entry(1));
+    cache.insert(Entry(2));
+    cache.insert(Entry(3));
+    assert_eq!(cached(&cache), [0, 2, 3]);
+    // 1's first access is remembered, so this makes two
+    cache.insert(Entry(1));
+    assert_eq!(cached(&cache), [0, 1, 3]);
+    cache.insert(Entry(4));
+    cache.insert(Entry(5));
+    assert_eq!(cached(&cache), [0, 1, 5]);
+
+    // Now everything's been accessed twice, and 0's second most recent access is the oldest
+    cache.get(&5);
+    cache.get(&0);
+    cache.insert(Entry(6));
+    assert_eq!(cached(&cache), [1, 5, 6]);
+
+    cache.set_capacity(1);
+    assert_eq!(cached(&cache), [5]);
+}
+
+#[test]
+fn remove_forgets() {
+    use crate::{build::BuildCache, Cache};
+
+    use super::test::{cached, single_shard, Entry};
+
+    let cache = single_shard(
+        BuildCache::<Entry>::default().layer(EvictLruK::default()),
+        3,
+    );
+
+    for key in 0..2 {
+        cache.insert(Entry(key));
+        cache.get(&key);
+    }
+    // Only evicted entries' history is kept, so 0 comes back with just the one access
+    cache.remove(&0);
+    cache.insert(Entry(0));
+    cache.insert(Entry(2));
+    cache.insert(Entry(3));
+    assert_eq!(cached(&cache), [1, 2, 3]);
+
+    // With no room at all, each write still replaces the last
+    cache.set_capacity(0);
+    assert_eq!(cached(&cache), []);
+    cache.insert(Entry(4));
+    cache.insert(Entry(5));
+    assert_eq!(cached(&cache), [5]);
+}
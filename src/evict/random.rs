@@ -13,46 +13,91 @@ use crate::{
 };
 
 use super::bag::{Bag, Key};
+use super::weight::{Budget, Unweighted, Weigher};
 
-pub struct EvictRandom<G = rand::rngs::SmallRng>(PhantomData<G>);
+pub struct EvictRandom<G = rand::rngs::SmallRng, W = Unweighted> {
+    weigher: W,
+    _random: PhantomData<G>,
+}
 
 impl<G> Default for EvictRandom<G> {
     fn default() -> Self {
-        Self(PhantomData)
+        Self::with_weigher(Unweighted)
+    }
+}
+
+impl<G, W> EvictRandom<G, W> {
+    pub fn with_weigher(weigher: W) -> Self {
+        Self {
+            weigher,
+            _random: PhantomData,
+        }
     }
 }
 
 #[doc(hidden)]
-pub struct RandomShard<P, G> {
+pub struct RandomShard<P, G, W> {
     bag: Bag<P>,
+    budget: Budget<W>,
     rng: G,
 }
 
-impl<P: Deref + Clone, G: Rng + SeedableRng> layer::Layer<P> for EvictRandom<G> {
+impl<P, G, W> layer::Layer<P> for EvictRandom<G, W>
+where
+    P: Deref + Clone,
+    G: Rng + SeedableRng,
+    W: Weigher<P::Target> + Clone,
+{
     type Value = Key;
-    type Shard = RandomShard<P, G>;
+    type Shard = RandomShard<P, G, W>;
 
     fn new_shard(&self, capacity: usize) -> Self::Shard {
         assert!(capacity > 0);
+        let budget = Budget::new(self.weigher.clone(), capacity);
         RandomShard {
-            bag: Bag::with_capacity(capacity),
+            bag: Bag::with_capacity(budget.expected_len::<P::Target>()),
+            budget,
             rng: G::from_rng(thread_rng()).unwrap(),
         }
     }
+
+    fn expected_len(&self, capacity: usize) -> usize {
+        self.weigher.expected_len(capacity)
+    }
 }
 
-impl<P: Deref + Clone, G: Rng + SeedableRng> layer::Shard<P> for RandomShard<P, G> {
+impl<P, G, W> RandomShard<P, G, W>
+where
+    P: Deref,
+    G: Rng,
+    W: Weigher<P::Target>,
+{
+    fn evict<R: layer::Resolve<P, Key>>(&mut self, incoming: usize, mut remove: impl FnMut(&P)) {
+        while self.budget.overflows(incoming) {
+            let Some(removed) = self.bag.pop(|len| self.rng.gen_range(0..len), R::resolve) else {
+                break;
+            };
+            self.budget.sub(&*removed);
+            remove(&removed);
+        }
+    }
+}
+
+impl<P, G, W> layer::Shard<P> for RandomShard<P, G, W>
+where
+    P: Deref + Clone,
+    G: Rng + SeedableRng,
+    W: Weigher<P::Target>,
+{
     type Value = Key;
 
     fn write<R: layer::Resolve<P, Self::Value>>(
         &mut self,
         mut write: impl layer::Write<P, Self::Value>,
     ) -> P {
-        if self.bag.len() == self.bag.capacity() {
-            if let Some(removed) = self.bag.pop(|len| self.rng.gen_range(0..len), R::resolve) {
-                write.remove(&removed);
-            }
-        }
+        let weight = self.budget.weigh(write.target());
+        self.evict::<R>(weight, |p| write.remove(p));
+        self.budget.add(weight);
         self.bag
             .insert_with_key(move |key| write.write(key))
             .clone()
@@ -60,15 +105,23 @@ impl<P: Deref + Clone, G: Rng + SeedableRng> layer::Shard<P> for RandomShard<P,
 
     fn remove<R: layer::Resolve<P, Self::Value>>(&mut self, pointer: &P) {
         self.bag.remove_by_value(pointer, R::resolve);
+        self.budget.sub(&**pointer);
+    }
+
+    fn set_capacity<R: layer::Resolve<P, Self::Value>>(&mut self, capacity: usize, remove: impl FnMut(&P)) {
+        self.budget.set_capacity(capacity);
+        self.evict::<R>(0, remove);
+        self.bag.grow_to(self.budget.expected_len::<P::Target>());
     }
 
     const READ_LOCK: layer::ReadLock = layer::ReadLock::None;
     const ITER_READ_LOCK: layer::ReadLock = layer::ReadLock::None;
 }
 
-pub struct EvictLeastOfN<S, G = rand::rngs::SmallRng> {
+pub struct EvictLeastOfN<S, G = rand::rngs::SmallRng, W = Unweighted> {
     strategy: Arc<S>,
     n: u32,
+    weigher: W,
     _random: PhantomData<G>,
 }
 
@@ -77,6 +130,7 @@ impl<S: Default, G> Default for EvictLeastOfN<S, G> {
         Self {
             strategy: Default::default(),
             n: 2,
+            weigher: Unweighted,
             _random: PhantomData,
         }
     }
@@ -94,6 +148,18 @@ impl<S, G> EvictLeastOfN<S, G> {
         Self {
             strategy: Arc::new(strategy),
             n,
+            weigher: Unweighted,
+            _random: PhantomData,
+        }
+    }
+}
+
+impl<S, G, W> EvictLeastOfN<S, G, W> {
+    pub fn with_weigher<W2>(self, weigher: W2) -> EvictLeastOfN<S, G, W2> {
+        EvictLeastOfN {
+            strategy: self.strategy,
+            n: self.n,
+            weigher,
             _random: PhantomData,
         }
     }
@@ -116,38 +182,47 @@ pub trait LeastOfNStrategy<T: ?Sized> {
     ) -> std::cmp::Ordering;
 }
 
-impl<P, S, G> layer::Layer<P> for EvictLeastOfN<S, G>
+impl<P, S, G, W> layer::Layer<P> for EvictLeastOfN<S, G, W>
 where
     P: Deref + Clone,
     S: LeastOfNStrategy<P::Target>,
     G: Rng + SeedableRng,
+    W: Weigher<P::Target> + Clone,
 {
     type Value = Key;
-    type Shard = BestOfNShard<P, S, G>;
+    type Shard = BestOfNShard<P, S, G, W>;
 
     fn new_shard(&self, capacity: usize) -> Self::Shard {
         assert!(capacity > 0);
+        let budget = Budget::new(self.weigher.clone(), capacity);
         BestOfNShard {
-            bag: Bag::with_capacity(capacity),
+            bag: Bag::with_capacity(budget.expected_len::<P::Target>()),
+            budget,
             strategy: Arc::clone(&self.strategy),
             n: self.n,
             rng: G::from_rng(thread_rng()).unwrap(),
         }
     }
+
+    fn expected_len(&self, capacity: usize) -> usize {
+        self.weigher.expected_len(capacity)
+    }
 }
 
-pub struct BestOfNShard<P: Deref, S: LeastOfNStrategy<P::Target>, G> {
+pub struct BestOfNShard<P: Deref, S: LeastOfNStrategy<P::Target>, G, W> {
     bag: Bag<(P, S::Value)>,
+    budget: Budget<W>,
     strategy: Arc<S>,
     n: u32,
     rng: G,
 }
 
-impl<P, S, G> layer::Shard<P> for BestOfNShard<P, S, G>
+impl<P, S, G, W> layer::Shard<P> for BestOfNShard<P, S, G, W>
 where
     P: Deref + Clone,
     S: LeastOfNStrategy<P::Target>,
     G: Rng + SeedableRng,
+    W: Weigher<P::Target>,
 {
     type Value = Key;
 
@@ -155,21 +230,9 @@ where
         &mut self,
         mut write: impl layer::Write<P, Self::Value>,
     ) -> P {
-        if self.bag.len() == self.bag.capacity() {
-            let (_key, (pointer, _value)) = self
-                .bag
-                .iter_random(|len| self.rng.gen_range(0..len), |(p, _v)| R::resolve(p))
-                .take(self.n.try_into().unwrap())
-                .min_by(|(_k0, (p0, v0)), (_k1, (p1, v1))| self.strategy.compare(&p0, v0, &p1, v1))
-                .expect("bag isn't empty");
-            let pointer = pointer.clone(); // XX: needed to stop borrowing &bag
-
-            let (pointer, _value) = self
-                .bag
-                .remove_by_key(R::resolve(&pointer), |(p, _v)| R::resolve(p));
-            write.remove(&pointer);
-        }
-
+        let weight = self.budget.weigh(write.target());
+        self.evict::<R>(weight, |p| write.remove(p));
+        self.budget.add(weight);
         let value = self.strategy.new_value(write.target());
         let (pointer, _value) = self
             .bag
@@ -180,6 +243,13 @@ where
     fn remove<R: layer::Resolve<P, Self::Value>>(&mut self, pointer: &P) {
         self.bag
             .remove_by_key(R::resolve(&pointer), |(p, _v)| R::resolve(p));
+        self.budget.sub(&**pointer);
+    }
+
+    fn set_capacity<R: layer::Resolve<P, Self::Value>>(&mut self, capacity: usize, remove: impl FnMut(&P)) {
+        self.budget.set_capacity(capacity);
+        self.evict::<R>(0, remove);
+        self.bag.grow_to(self.budget.expected_len::<P::Target>());
     }
 
     const READ_LOCK: layer::ReadLock = ReadLock::Ref;
@@ -193,6 +263,32 @@ where
     const ITER_READ_LOCK: layer::ReadLock = ReadLock::None;
 }
 
+impl<P, S, G, W> BestOfNShard<P, S, G, W>
+where
+    P: Deref + Clone,
+    S: LeastOfNStrategy<P::Target>,
+    G: Rng,
+    W: Weigher<P::Target>,
+{
+    fn evict<R: layer::Resolve<P, Key>>(&mut self, incoming: usize, mut remove: impl FnMut(&P)) {
+        while self.budget.overflows(incoming) && !self.bag.is_empty() {
+            let (_key, (pointer, _value)) = self
+                .bag
+                .iter_random(|len| self.rng.gen_range(0..len), |(p, _v)| R::resolve(p))
+                .take(self.n.try_into().unwrap())
+                .min_by(|(_k0, (p0, v0)), (_k1, (p1, v1))| self.strategy.compare(&p0, v0, &p1, v1))
+                .expect("bag isn't empty");
+            let pointer = pointer.clone(); // XX: needed to stop borrowing &bag
+
+            let (pointer, _value) = self
+                .bag
+                .remove_by_key(R::resolve(&pointer), |(p, _v)| R::resolve(p));
+            self.budget.sub(&*pointer);
+            remove(&pointer);
+        }
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct LeastRecentlyWritten<C = DefaultClock>(C);
 
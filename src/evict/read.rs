@@ -4,42 +4,98 @@ use crate::layer;
 
 use super::index::Key;
 use super::list::List;
+use super::weight::{Budget, Unweighted, Weigher};
 
-#[derive(Debug)]
-pub struct EvictLeastRecentlyRead;
+#[derive(Debug, Clone)]
+pub struct EvictLeastRecentlyRead<W = Unweighted> {
+    weigher: W,
+}
+
+impl Default for EvictLeastRecentlyRead {
+    fn default() -> Self {
+        Self::with_weigher(Unweighted)
+    }
+}
 
-pub struct Shard<P>(List<P>);
+impl<W> EvictLeastRecentlyRead<W> {
+    pub fn with_weigher(weigher: W) -> Self {
+        Self { weigher }
+    }
+}
 
-impl<P: Deref + Clone> layer::Layer<P> for EvictLeastRecentlyRead {
+pub struct Shard<P, W> {
+    list: List<P>,
+    budget: Budget<W>,
+}
+
+impl<P, W> layer::Layer<P> for EvictLeastRecentlyRead<W>
+where
+    P: Deref + Clone,
+    W: Weigher<P::Target> + Clone,
+{
     type Value = Key;
-    type Shard = Shard<P>;
+    type Shard = Shard<P, W>;
 
     fn new_shard(&self, capacity: usize) -> Self::Shard {
-        Shard(List::with_capacity(capacity))
+        let budget = Budget::new(self.weigher.clone(), capacity);
+        Shard {
+            list: List::with_capacity(budget.expected_len::<P::Target>()),
+            budget,
+        }
+    }
+
+    fn expected_len(&self, capacity: usize) -> usize {
+        self.weigher.expected_len(capacity)
     }
 }
 
-impl<P: Clone + Deref> layer::Shard<P> for Shard<P> {
+impl<P: Deref, W: Weigher<P::Target>> Shard<P, W> {
+    fn evict(&mut self, incoming: usize, mut remove: impl FnMut(&P)) {
+        while self.budget.overflows(incoming) {
+            let Some(removed) = self.list.pop_head() else {
+                break;
+            };
+            self.budget.sub(&*removed);
+            remove(&removed);
+        }
+    }
+}
+
+impl<P, W> layer::Shard<P> for Shard<P, W>
+where
+    P: Deref + Clone,
+    W: Weigher<P::Target>,
+{
     type Value = Key;
 
     fn write<R: layer::Resolve<P, Self::Value>>(
         &mut self,
         mut write: impl layer::Write<P, Self::Value>,
     ) -> P {
-        if self.0.len() == self.0.capacity() {
-            if let Some(removed) = self.0.pop_head() {
-                write.remove(&removed);
-            }
-        }
-
-        self.0.push_tail_with_key(|key| write.write(key)).clone()
+        let weight = self.budget.weigh(write.target());
+        self.evict(weight, |p| write.remove(p));
+        self.budget.add(weight);
+        self.list.push_tail_with_key(|key| write.write(key)).clone()
     }
 
     fn remove<R: layer::Resolve<P, Self::Value>>(&mut self, pointer: &P) {
-        let _ = self.0.remove(*R::resolve(pointer));
+        if self.list.remove(*R::resolve(pointer)).is_some() {
+            self.budget.sub(&**pointer);
+        }
         // XX: debug assert?
     }
 
+    fn set_capacity<R: layer::Resolve<P, Self::Value>>(&mut self, capacity: usize, remove: impl FnMut(&P)) {
+        self.budget.set_capacity(capacity);
+        self.evict(0, remove);
+        self.list.grow_to(self.budget.expected_len::<P::Target>());
+    }
+
+    fn eviction_order<R: layer::Resolve<P, Self::Value>>(&self, f: impl FnMut(&P)) -> bool {
+        self.list.iter().for_each(f);
+        true
+    }
+
     const READ_LOCK: layer::ReadLock = layer::ReadLock::Mut;
 
     fn read_ref<R: layer::Resolve<P, Self::Value>>(&self, _pointer: &P) -> layer::ReadResult {
@@ -47,7 +103,7 @@ impl<P: Clone + Deref> layer::Shard<P> for Shard<P> {
     }
 
     fn read_mut<R: layer::Resolve<P, Self::Value>>(&mut self, pointer: &P) -> layer::ReadResult {
-        self.0.move_to_tail(*R::resolve(pointer));
+        self.list.move_to_tail(*R::resolve(pointer));
         layer::ReadResult::Retain
     }
 
@@ -0,0 +1,331 @@
+use std::{
+    hash::BuildHasher,
+    ops::Deref,
+    sync::atomic::{AtomicBool, AtomicU8, Ordering},
+};
+
+use hashbrown::hash_map::DefaultHashBuilder;
+
+use crate::{layer, Value};
+
+use super::ghost::Ghosts;
+use super::index::AtomicKey;
+use super::list::List;
+
+const MAX_FREQUENCY: u8 = 3;
+
+/// S3-FIFO. New entries go into a small FIFO queue, and only move on to the main FIFO queue if
+/// they're read more than once before reaching its head. Anything evicted from the small queue
+/// leaves its hash in a ghost queue, and comes straight back into the main queue if written
+/// again while it's there. Reads only bump a saturating counter, so they just need the shard's
+/// read lock.
+#[derive(Debug, Clone)]
+pub struct EvictS3Fifo<S = DefaultHashBuilder> {
+    hasher: S,
+    small_fraction: f64,
+}
+
+impl Default for EvictS3Fifo {
+    fn default() -> Self {
+        Self::with_hasher(DefaultHashBuilder::default())
+    }
+}
+
+impl<S> EvictS3Fifo<S> {
+    pub fn with_hasher(hasher: S) -> Self {
+        Self {
+            hasher,
+            small_fraction: 0.1,
+        }
+    }
+
+    /// Share of each shard's capacity given to the small queue, 10% by default.
+    pub fn small(self, fraction: f64) -> Self {
+        assert!((0.0..=1.0).contains(&fraction), "small fraction out of range");
+        Self {
+            small_fraction: fraction,
+            ..self
+        }
+    }
+}
+
+/// Which queue an entry is in, and how often it's been read lately.
+#[doc(hidden)]
+#[derive(Debug)]
+pub struct S3FifoKey {
+    hash: u64,
+    main: AtomicBool,
+    frequency: AtomicU8,
+    key: AtomicKey,
+}
+
+pub struct Shard<P, S> {
+    hasher: S,
+    small_fraction: f64,
+    capacity: usize,
+    small_capacity: usize,
+    small: List<P>,
+    main: List<P>,
+    // As long as the main queue
+    ghosts: Ghosts,
+}
+
+impl<P, S> layer::Layer<P> for EvictS3Fifo<S>
+where
+    P: Deref + Clone,
+    P::Target: Value,
+    S: BuildHasher + Clone,
+{
+    type Value = S3FifoKey;
+    type Shard = Shard<P, S>;
+
+    fn new_shard(&self, capacity: usize) -> Self::Shard {
+        let mut shard = Shard {
+            hasher: self.hasher.clone(),
+            small_fraction: self.small_fraction,
+            capacity: 0,
+            small_capacity: 0,
+            small: List::with_capacity(0),
+            main: List::with_capacity(0),
+            ghosts: Ghosts::new(),
+        };
+        shard.resize(capacity);
+        shard
+    }
+}
+
+impl<P: Deref + Clone, S> Shard<P, S> {
+    fn resize(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.small_capacity = ((capacity as f64 * self.small_fraction).round() as usize)
+            .clamp(capacity.min(1), capacity);
+
+        self.small.grow_to(self.small_capacity);
+        self.main.grow_to(capacity - self.small_capacity);
+    }
+
+    fn len(&self) -> usize {
+        self.small.len() + self.main.len()
+    }
+
+    fn push<R: layer::Resolve<P, S3FifoKey>>(&mut self, pointer: P) {
+        R::resolve(&pointer).main.store(true, Ordering::Relaxed);
+        self.main.push_tail_with_key(|key| {
+            R::resolve(&pointer).key.store(key, Ordering::Relaxed);
+            pointer
+        });
+    }
+
+    /// Evict one entry, moving along whatever's been read enough to earn it. Returns false if
+    /// there was nothing to evict.
+    fn evict<R: layer::Resolve<P, S3FifoKey>>(&mut self, remove: &mut impl FnMut(&P)) -> bool {
+        loop {
+            let from_small = self.small.len() > 0
+                && (self.small.len() >= self.small_capacity || self.main.len() == 0);
+            if from_small {
+                let Some(pointer) = self.small.pop_head() else {
+                    return false;
+                };
+                let value = R::resolve(&pointer);
+                if value.frequency.load(Ordering::Relaxed) > 1 {
+                    value.frequency.store(0, Ordering::Relaxed);
+                    self.push::<R>(pointer);
+                    continue;
+                }
+
+                self.ghosts.push(value.hash);
+                self.trim_ghosts();
+                remove(&pointer);
+                return true;
+            }
+
+            let Some(pointer) = self.main.pop_head() else {
+                return false;
+            };
+            let frequency = &R::resolve(&pointer).frequency;
+            match frequency.load(Ordering::Relaxed) {
+                0 => {
+                    remove(&pointer);
+                    return true;
+                }
+                n => {
+                    frequency.store(n - 1, Ordering::Relaxed);
+                    self.push::<R>(pointer);
+                }
+            }
+        }
+    }
+
+    fn trim_ghosts(&mut self) {
+        while self.ghosts.len() > self.capacity - self.small_capacity {
+            self.ghosts.pop_oldest();
+        }
+    }
+}
+
+impl<P, S> layer::Shard<P> for Shard<P, S>
+where
+    P: Deref + Clone,
+    P::Target: Value,
+    S: BuildHasher,
+{
+    type Value = S3FifoKey;
+
+    fn write<R: layer::Resolve<P, Self::Value>>(
+        &mut self,
+        mut write: impl layer::Write<P, Self::Value>,
+    ) -> P {
+        let hash = self.hasher.hash_one(write.target().key());
+        let ghost_hit = self.ghosts.remove(hash);
+        while self.len() >= self.capacity {
+            if !self.evict::<R>(&mut |p| write.remove(p)) {
+                break;
+            }
+        }
+
+        let list = if ghost_hit { &mut self.main } else { &mut self.small };
+        list.push_tail_with_key(|key| {
+            write.write(S3FifoKey {
+                hash,
+                main: ghost_hit.into(),
+                frequency: 0.into(),
+                key: key.into(),
+            })
+        })
+        .clone()
+    }
+
+    fn remove<R: layer::Resolve<P, Self::Value>>(&mut self, pointer: &P) {
+        let value = R::resolve(pointer);
+        let key = value.key.load(Ordering::Relaxed);
+        let removed = match value.main.load(Ordering::Relaxed) {
+            true => self.main.remove(key),
+            false => self.small.remove(key),
+        };
+        debug_assert!(removed.is_some());
+    }
+
+    fn set_capacity<R: layer::Resolve<P, Self::Value>>(
+        &mut self,
+        capacity: usize,
+        mut remove: impl FnMut(&P),
+    ) {
+        self.resize(capacity);
+        while self.len() > capacity {
+            if !self.evict::<R>(&mut remove) {
+                break;
+            }
+        }
+        self.trim_ghosts();
+    }
+
+    fn eviction_order<R: layer::Resolve<P, Self::Value>>(&self, f: impl FnMut(&P)) -> bool {
+        self.small.iter().chain(self.main.iter()).for_each(f);
+        true
+    }
+
+    const READ_LOCK: layer::ReadLock = layer::ReadLock::Ref;
+
+    fn read_ref<R: layer::Resolve<P, Self::Value>>(&self, pointer: &P) -> layer::ReadResult {
+        let _ = R::resolve(pointer).frequency.fetch_update(
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+            |frequency| (frequency < MAX_FREQUENCY).then_some(frequency + 1),
+        );
+        layer::ReadResult::Retain
+    }
+
+    const ITER_READ_LOCK: layer::ReadLock = layer::ReadLock::None;
+}
+
+#[test]
+fn s3_fifo() {
+    use std::time::{Duration, Instant};
+
+    use crate::{build::BuildCache, expire::ExpireAt, Cache};
+
+    use super::test::{cached, single_shard};
+
+    struct Entry(u32, Instant);
+
+    impl Value for Entry {
+        type Key = u32;
+
+        fn key(&self) -> &u32 {
+            &self.0
+        }
+    }
+
+    impl ExpireAt for Entry {
+        fn expire_at(&self) -> Instant {
+            self.1
+        }
+    }
+
+    let later = Instant::now() + Duration::from_secs(3600);
+    let cache = single_shard(
+        BuildCache::<Entry>::default().expire_at().layer(EvictS3Fifo::default().small(0.5)),
+        4,
+    );
+
+    for key in 0..4 {
+        cache.insert(Entry(key, later));
+    }
+    cache.get(&0);
+    cache.get(&0);
+    // 0 moves on to the main queue, leaving 1 to go
+    cache.insert(Entry(4, later));
+    assert_eq!(cached(&cache), [0, 2, 3, 4]);
+    // 1's hash is still in the ghost queue, so it comes back into the main queue
+    cache.insert(Entry(1, later));
+    assert_eq!(cached(&cache), [0, 1, 3, 4]);
+
+    // A scan only churns through the small queue
+    for cold in 5..8 {
+        cache.insert(Entry(cold, later));
+    }
+    assert_eq!(cached(&cache), [0, 1, 6, 7]);
+
+    // And the layer below still gets a say
+    cache.insert(Entry(8, Instant::now()));
+    assert!(cache.get(&8).is_none());
+    assert_eq!(cached(&cache), [0, 1, 7]);
+
+    cache.set_capacity(2);
+    assert_eq!(cache.len(), 2);
+}
+
+#[test]
+fn concurrent_reads() {
+    use crate::{build::BuildCache, Cache};
+
+    use super::test::{cached, single_shard, Entry};
+
+    let cache = single_shard(
+        BuildCache::<Entry>::default().layer(EvictS3Fifo::default().small(0.5)),
+        4,
+    );
+    for key in 0..4 {
+        cache.insert(Entry(key));
+    }
+    std::thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                for _ in 0..1000 {
+                    cache.get(&0);
+                    cache.get(&1);
+                }
+            });
+        }
+    });
+
+    // Both were read enough to move on to the main queue, and start over there at zero
+    cache.insert(Entry(4));
+    assert_eq!(cached(&cache), [0, 1, 3, 4]);
+
+    // With room for just the small queue, the main queue empties out oldest first
+    cache.set_capacity(1);
+    assert_eq!(cached(&cache), [1]);
+    cache.insert(Entry(5));
+    assert_eq!(cached(&cache), [5]);
+}
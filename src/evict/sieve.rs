@@ -0,0 +1,227 @@
+use std::{
+    ops::Deref,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::layer;
+
+use super::index::Key;
+use super::list::List;
+use super::weight::{Budget, Unweighted, Weigher};
+
+/// SIEVE. Entries stay in the order they were written, and reading one only marks it as
+/// visited, so reads just need the shard's read lock. Writes move a hand from the oldest entry
+/// towards the newest, unmarking visited entries and evicting the first unvisited one. Unlike
+/// [CLOCK](super::clock::EvictClock), survivors keep their place rather than going round to
+/// the back, so new entries that are never read again go quickly.
+#[derive(Debug, Clone)]
+pub struct EvictSieve<W = Unweighted> {
+    weigher: W,
+}
+
+impl Default for EvictSieve {
+    fn default() -> Self {
+        Self::with_weigher(Unweighted)
+    }
+}
+
+impl<W> EvictSieve<W> {
+    pub fn with_weigher(weigher: W) -> Self {
+        Self { weigher }
+    }
+}
+
+/// Where an entry is in the queue, and whether it's been read since the hand last passed.
+#[doc(hidden)]
+#[derive(Debug)]
+pub struct SieveKey {
+    visited: AtomicBool,
+    key: Key,
+}
+
+pub struct Shard<P, W> {
+    // Oldest first
+    list: List<P>,
+    budget: Budget<W>,
+    // Next to be looked at, starting over from the head when None
+    hand: Option<Key>,
+}
+
+impl<P, W> layer::Layer<P> for EvictSieve<W>
+where
+    P: Deref + Clone,
+    W: Weigher<P::Target> + Clone,
+{
+    type Value = SieveKey;
+    type Shard = Shard<P, W>;
+
+    fn new_shard(&self, capacity: usize) -> Self::Shard {
+        let budget = Budget::new(self.weigher.clone(), capacity);
+        Shard {
+            list: List::with_capacity(budget.expected_len::<P::Target>()),
+            budget,
+            hand: None,
+        }
+    }
+
+    fn expected_len(&self, capacity: usize) -> usize {
+        self.weigher.expected_len(capacity)
+    }
+}
+
+impl<P: Deref, W: Weigher<P::Target>> Shard<P, W> {
+    fn evict<R: layer::Resolve<P, SieveKey>>(&mut self, incoming: usize, mut remove: impl FnMut(&P)) {
+        while self.budget.overflows(incoming) {
+            let Some(key) = self.hand.or_else(|| self.list.head_key()) else {
+                break;
+            };
+            self.hand = self.list.next_key(key);
+
+            let Some(pointer) = self.list.get(key) else {
+                continue;
+            };
+            if R::resolve(pointer).visited.swap(false, Ordering::Relaxed) {
+                continue;
+            }
+
+            let Some(removed) = self.list.remove(key) else {
+                break;
+            };
+            self.budget.sub(&*removed);
+            remove(&removed);
+        }
+    }
+}
+
+impl<P, W> layer::Shard<P> for Shard<P, W>
+where
+    P: Deref + Clone,
+    W: Weigher<P::Target>,
+{
+    type Value = SieveKey;
+
+    fn write<R: layer::Resolve<P, Self::Value>>(
+        &mut self,
+        mut write: impl layer::Write<P, Self::Value>,
+    ) -> P {
+        let weight = self.budget.weigh(write.target());
+        self.evict::<R>(weight, |p| write.remove(p));
+        self.budget.add(weight);
+        self.list
+            .push_tail_with_key(|key| {
+                write.write(SieveKey {
+                    visited: false.into(),
+                    key,
+                })
+            })
+            .clone()
+    }
+
+    fn remove<R: layer::Resolve<P, Self::Value>>(&mut self, pointer: &P) {
+        let key = R::resolve(pointer).key;
+        if self.hand == Some(key) {
+            self.hand = self.list.next_key(key);
+        }
+        if self.list.remove(key).is_some() {
+            self.budget.sub(&**pointer);
+        }
+    }
+
+    fn set_capacity<R: layer::Resolve<P, Self::Value>>(&mut self, capacity: usize, remove: impl FnMut(&P)) {
+        self.budget.set_capacity(capacity);
+        self.evict::<R>(0, remove);
+        self.list.grow_to(self.budget.expected_len::<P::Target>());
+    }
+
+    fn eviction_order<R: layer::Resolve<P, Self::Value>>(&self, mut f: impl FnMut(&P)) -> bool {
+        // From the hand round to just before it, unvisited entries first
+        let hand = self
+            .list
+            .iter()
+            .position(|p| Some(R::resolve(p).key) == self.hand)
+            .unwrap_or(0);
+        for visited in [false, true] {
+            self.list
+                .iter()
+                .skip(hand)
+                .chain(self.list.iter().take(hand))
+                .filter(|p| R::resolve(p).visited.load(Ordering::Relaxed) == visited)
+                .for_each(&mut f);
+        }
+        true
+    }
+
+    const READ_LOCK: layer::ReadLock = layer::ReadLock::Ref;
+
+    fn read_ref<R: layer::Resolve<P, Self::Value>>(&self, pointer: &P) -> layer::ReadResult {
+        let visited = &R::resolve(pointer).visited;
+        // Checking first keeps already visited entries from bouncing their cache line between
+        // readers
+        if !visited.load(Ordering::Relaxed) {
+            visited.store(true, Ordering::Relaxed);
+        }
+        layer::ReadResult::Retain
+    }
+
+    const ITER_READ_LOCK: layer::ReadLock = layer::ReadLock::None;
+}
+
+#[test]
+fn sieve() {
+    use crate::{build::BuildCache, Cache};
+
+    use super::test::{cached, single_shard, Entry};
+
+    let cache = single_shard(BuildCache::<Entry>::default().layer(EvictSieve::default()), 3);
+
+    for key in 0..3 {
+        cache.insert(Entry(key));
+    }
+    cache.get(&0);
+    cache.insert(Entry(3));
+    assert_eq!(cached(&cache), [0, 2, 3]);
+    cache.get(&3);
+    cache.insert(Entry(4));
+    assert_eq!(cached(&cache), [0, 3, 4]);
+    cache.insert(Entry(5));
+    assert_eq!(cached(&cache), [0, 3, 5]);
+    // The hand's come back round to 0, which hasn't been read since it last passed
+    cache.insert(Entry(6));
+    assert_eq!(cached(&cache), [3, 5, 6]);
+
+    cache.remove(&5);
+    cache.set_capacity(1);
+    assert_eq!(cached(&cache), [6]);
+}
+
+#[test]
+fn concurrent_reads() {
+    use crate::{build::BuildCache, Cache};
+
+    use super::test::{cached, single_shard, Entry};
+
+    let cache = single_shard(BuildCache::<Entry>::default().layer(EvictSieve::default()), 4);
+    for key in 0..4 {
+        cache.insert(Entry(key));
+    }
+    std::thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                for _ in 0..1000 {
+                    cache.get(&0);
+                    cache.get(&2);
+                }
+            });
+        }
+    });
+
+    cache.insert(Entry(4));
+    cache.insert(Entry(5));
+    assert_eq!(cached(&cache), [0, 2, 4, 5]);
+
+    // Removing what the hand points at moves it on rather than leaving it dangling
+    cache.remove(&4);
+    cache.insert(Entry(6));
+    cache.insert(Entry(7));
+    assert_eq!(cached(&cache), [0, 2, 6, 7]);
+}
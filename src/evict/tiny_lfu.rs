@@ -0,0 +1,406 @@
+use std::{
+    hash::BuildHasher,
+    ops::Deref,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use hashbrown::hash_map::DefaultHashBuilder;
+
+use crate::{layer, Value};
+
+use super::index::AtomicKey;
+use super::list::List;
+
+const WINDOW: u8 = 0;
+const PROBATION: u8 = 1;
+const PROTECTED: u8 = 2;
+
+/// W-TinyLFU: new entries go through a small LRU window, and only make it into the main
+/// segmented LRU if a per-shard frequency sketch says they're used more often than whatever
+/// they'd push out. Keeps one-hit wonders and scans from flushing out the entries that matter.
+#[derive(Debug, Clone)]
+pub struct EvictTinyLfu<S = DefaultHashBuilder> {
+    hasher: S,
+    window_fraction: f64,
+}
+
+impl Default for EvictTinyLfu {
+    fn default() -> Self {
+        Self::with_hasher(DefaultHashBuilder::default())
+    }
+}
+
+impl<S> EvictTinyLfu<S> {
+    pub fn with_hasher(hasher: S) -> Self {
+        Self {
+            hasher,
+            window_fraction: 0.01,
+        }
+    }
+
+    /// Share of each shard's capacity given to the admission window, 1% by default. Larger
+    /// windows favour recency over frequency.
+    pub fn window(self, fraction: f64) -> Self {
+        assert!((0.0..=1.0).contains(&fraction), "window fraction out of range");
+        Self {
+            window_fraction: fraction,
+            ..self
+        }
+    }
+}
+
+/// Which of its shard's lists an entry is on.
+#[doc(hidden)]
+#[derive(Debug)]
+pub struct TinyLfuKey {
+    hash: u64,
+    region: AtomicU8,
+    key: AtomicKey,
+}
+
+pub struct Shard<P, S> {
+    hasher: S,
+    window_fraction: f64,
+    window: List<P>,
+    probation: List<P>,
+    protected: List<P>,
+    window_capacity: usize,
+    main_capacity: usize,
+    protected_capacity: usize,
+    sketch: FrequencySketch,
+}
+
+impl<P, S> layer::Layer<P> for EvictTinyLfu<S>
+where
+    P: Deref + Clone,
+    P::Target: Value,
+    S: BuildHasher + Clone,
+{
+    type Value = TinyLfuKey;
+    type Shard = Shard<P, S>;
+
+    fn new_shard(&self, capacity: usize) -> Self::Shard {
+        let mut shard = Shard {
+            hasher: self.hasher.clone(),
+            window_fraction: self.window_fraction,
+            window: List::with_capacity(0),
+            probation: List::with_capacity(0),
+            protected: List::with_capacity(0),
+            window_capacity: 0,
+            main_capacity: 0,
+            protected_capacity: 0,
+            sketch: FrequencySketch::new(capacity),
+        };
+        shard.resize(capacity);
+        shard
+    }
+}
+
+impl<P: Deref + Clone, S> Shard<P, S> {
+    fn resize(&mut self, capacity: usize) {
+        self.window_capacity = ((capacity as f64 * self.window_fraction).round() as usize)
+            .clamp(capacity.min(1), capacity);
+        self.main_capacity = capacity - self.window_capacity;
+        self.protected_capacity = self.main_capacity * 4 / 5;
+        self.sketch.resize(capacity);
+
+        self.window.grow_to(self.window_capacity);
+        self.probation.grow_to(self.main_capacity);
+        self.protected.grow_to(self.protected_capacity);
+    }
+
+    fn list(&mut self, region: u8) -> &mut List<P> {
+        match region {
+            WINDOW => &mut self.window,
+            PROBATION => &mut self.probation,
+            _ => &mut self.protected,
+        }
+    }
+
+    fn push<R: layer::Resolve<P, TinyLfuKey>>(&mut self, region: u8, pointer: P) {
+        R::resolve(&pointer).region.store(region, Ordering::Relaxed);
+        self.list(region).push_tail_with_key(|key| {
+            R::resolve(&pointer).key.store(key, Ordering::Relaxed);
+            pointer
+        });
+    }
+
+    fn take<R: layer::Resolve<P, TinyLfuKey>>(&mut self, pointer: &P) -> Option<P> {
+        let entry = R::resolve(pointer);
+        let key = entry.key.load(Ordering::Relaxed);
+        self.list(entry.region.load(Ordering::Relaxed)).remove(key)
+    }
+
+    /// Move the window's oldest entry into the main region if it's used more often than the
+    /// main region's next victim, evicting whichever of the two loses.
+    fn admit<R: layer::Resolve<P, TinyLfuKey>>(&mut self, remove: &mut impl FnMut(&P)) {
+        let Some(candidate) = self.window.pop_head() else {
+            return;
+        };
+        if self.probation.len() + self.protected.len() < self.main_capacity {
+            self.push::<R>(PROBATION, candidate);
+            return;
+        }
+
+        let victim = self
+            .probation
+            .iter()
+            .next()
+            .or_else(|| self.protected.iter().next())
+            .cloned();
+        match victim {
+            Some(victim)
+                if self.sketch.frequency(R::resolve(&candidate).hash)
+                    > self.sketch.frequency(R::resolve(&victim).hash) =>
+            {
+                self.take::<R>(&victim);
+                remove(&victim);
+                self.push::<R>(PROBATION, candidate);
+            }
+            _ => remove(&candidate),
+        }
+    }
+
+    fn demote_protected<R: layer::Resolve<P, TinyLfuKey>>(&mut self) {
+        while self.protected.len() > self.protected_capacity {
+            let Some(demoted) = self.protected.pop_head() else {
+                break;
+            };
+            self.push::<R>(PROBATION, demoted);
+        }
+    }
+}
+
+impl<P, S> layer::Shard<P> for Shard<P, S>
+where
+    P: Deref + Clone,
+    P::Target: Value,
+    S: BuildHasher,
+{
+    type Value = TinyLfuKey;
+
+    fn write<R: layer::Resolve<P, Self::Value>>(
+        &mut self,
+        mut write: impl layer::Write<P, Self::Value>,
+    ) -> P {
+        let hash = self.hasher.hash_one(write.target().key());
+        self.sketch.increment(hash);
+        if self.window.len() >= self.window_capacity {
+            self.admit::<R>(&mut |p| write.remove(p));
+        }
+        self.window
+            .push_tail_with_key(|key| {
+                write.write(TinyLfuKey {
+                    hash,
+                    region: WINDOW.into(),
+                    key: key.into(),
+                })
+            })
+            .clone()
+    }
+
+    fn remove<R: layer::Resolve<P, Self::Value>>(&mut self, pointer: &P) {
+        let removed = self.take::<R>(pointer);
+        debug_assert!(removed.is_some());
+    }
+
+    fn set_capacity<R: layer::Resolve<P, Self::Value>>(
+        &mut self,
+        capacity: usize,
+        mut remove: impl FnMut(&P),
+    ) {
+        self.resize(capacity);
+        while self.probation.len() + self.protected.len() > self.main_capacity {
+            let Some(victim) = self.probation.pop_head().or_else(|| self.protected.pop_head())
+            else {
+                break;
+            };
+            remove(&victim);
+        }
+        while self.window.len() > self.window_capacity {
+            self.admit::<R>(&mut remove);
+        }
+        self.demote_protected::<R>();
+    }
+
+    fn eviction_order<R: layer::Resolve<P, Self::Value>>(&self, f: impl FnMut(&P)) -> bool {
+        self.probation
+            .iter()
+            .chain(self.window.iter())
+            .chain(self.protected.iter())
+            .for_each(f);
+        true
+    }
+
+    const READ_LOCK: layer::ReadLock = layer::ReadLock::Mut;
+
+    fn read_ref<R: layer::Resolve<P, Self::Value>>(&self, _pointer: &P) -> layer::ReadResult {
+        unreachable!()
+    }
+
+    fn read_mut<R: layer::Resolve<P, Self::Value>>(&mut self, pointer: &P) -> layer::ReadResult {
+        let entry = R::resolve(pointer);
+        self.sketch.increment(entry.hash);
+        let key = entry.key.load(Ordering::Relaxed);
+        match entry.region.load(Ordering::Relaxed) {
+            PROBATION => {
+                if let Some(pointer) = self.probation.remove(key) {
+                    self.push::<R>(PROTECTED, pointer);
+                    self.demote_protected::<R>();
+                }
+            }
+            region => self.list(region).move_to_tail(key),
+        }
+        layer::ReadResult::Retain
+    }
+
+    const ITER_READ_LOCK: layer::ReadLock = layer::ReadLock::None;
+}
+
+const SKETCH_DEPTH: usize = 4;
+const SKETCH_SEEDS: [u64; SKETCH_DEPTH] = [
+    0xc3a5c85c97cb3127,
+    0xb492b66fbe98f273,
+    0x9ae16a3b2f90404f,
+    0xcbf29ce484222325,
+];
+const MAX_FREQUENCY: u8 = 15;
+
+/// Count-min sketch of how often each hash has been seen lately. Every counter is halved once
+/// enough has been counted so that old popularity fades.
+struct FrequencySketch {
+    counters: Box<[u8]>,
+    mask: usize,
+    additions: usize,
+    sample_size: usize,
+}
+
+impl FrequencySketch {
+    fn new(capacity: usize) -> Self {
+        let width = Self::width(capacity);
+        Self {
+            counters: vec![0; width * SKETCH_DEPTH].into(),
+            mask: width - 1,
+            additions: 0,
+            sample_size: width.saturating_mul(10),
+        }
+    }
+
+    fn width(capacity: usize) -> usize {
+        capacity.max(16).next_power_of_two()
+    }
+
+    /// Start over at a size to suit the new capacity, if it needs a different one.
+    fn resize(&mut self, capacity: usize) {
+        if Self::width(capacity) != self.mask + 1 {
+            *self = Self::new(capacity);
+        }
+    }
+
+    fn index(&self, hash: u64, row: usize) -> usize {
+        let mixed = (hash ^ SKETCH_SEEDS[row]).wrapping_mul(0x9e3779b97f4a7c15);
+        row * (self.mask + 1) + ((mixed >> 32) as usize & self.mask)
+    }
+
+    fn frequency(&self, hash: u64) -> u8 {
+        (0..SKETCH_DEPTH)
+            .map(|row| self.counters[self.index(hash, row)])
+            .min()
+            .unwrap_or(0)
+    }
+
+    fn increment(&mut self, hash: u64) {
+        let mut added = false;
+        for row in 0..SKETCH_DEPTH {
+            let index = self.index(hash, row);
+            let counter = &mut self.counters[index];
+            if *counter < MAX_FREQUENCY {
+                *counter += 1;
+                added = true;
+            }
+        }
+
+        if added {
+            self.additions += 1;
+            if self.additions >= self.sample_size {
+                self.counters.iter_mut().for_each(|counter| *counter /= 2);
+                self.additions /= 2;
+            }
+        }
+    }
+}
+
+#[test]
+fn scan_resistant() {
+    use crate::{build::BuildCache, Cache};
+
+    use super::test::{single_shard, Entry};
+
+    let cache = single_shard(BuildCache::<Entry>::default().evict_tiny_lfu(), 100);
+
+    for hot in 0..50 {
+        cache.insert(Entry(hot));
+    }
+    // Push the last of them out of the window so that reading promotes every one
+    cache.insert(Entry(1000));
+    for _ in 0..3 {
+        for hot in 0..50 {
+            cache.get(&hot);
+        }
+    }
+
+    // A long scan of one-hit wonders only churns through the probation segment
+    for cold in 1001..11_000 {
+        cache.insert(Entry(cold));
+    }
+    assert_eq!(cache.len(), 100);
+    assert!((0..50).all(|hot| cache.get(&hot).is_some()));
+
+    cache.set_capacity(20);
+    assert_eq!(cache.len(), 20);
+    // Only the window's newest entry isn't one of the hot ones
+    assert_eq!((0..50).filter(|hot| cache.get(hot).is_some()).count(), 19);
+}
+
+#[test]
+fn remove_from_each_region() {
+    use crate::{build::BuildCache, Cache};
+
+    use super::test::{cached, single_shard, Entry};
+
+    // A window of 2 in front of 8, 6 of them protected
+    let cache = single_shard(
+        BuildCache::<Entry>::default().layer(EvictTinyLfu::default().window(0.2)),
+        10,
+    );
+
+    for key in 0..10 {
+        cache.insert(Entry(key));
+    }
+    cache.get(&0);
+    cache.get(&1);
+    // One from each of protected, probation and the window
+    cache.remove(&0);
+    cache.remove(&2);
+    cache.remove(&9);
+
+    // Their room is free again, so the main region takes three more without a contest
+    for key in 10..13 {
+        cache.insert(Entry(key));
+    }
+    assert_eq!(cached(&cache), [1, 3, 4, 5, 6, 7, 8, 10, 11, 12]);
+    // Now full, and 11 is no more popular than probation's oldest
+    cache.insert(Entry(13));
+    assert_eq!(cached(&cache), [1, 3, 4, 5, 6, 7, 8, 10, 12, 13]);
+
+    // Down to just a window of one, which then still holds the newest
+    cache.set_capacity(1);
+    assert_eq!(cached(&cache), [13]);
+    cache.insert(Entry(14));
+    assert_eq!(cached(&cache), [14]);
+    cache.set_capacity(0);
+    assert_eq!(cached(&cache), []);
+    cache.insert(Entry(15));
+    cache.insert(Entry(16));
+    assert_eq!(cached(&cache), [16]);
+}
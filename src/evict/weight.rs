@@ -0,0 +1,155 @@
+/// Measures how much of a shard's capacity a value takes up.
+///
+/// The weight of a value must not change while it's in the cache, since it's re-measured on
+/// removal to release its share of the capacity.
+pub trait Weigher<T: ?Sized> {
+    fn weigh(&self, value: &T) -> usize;
+
+    /// Number of entries worth preallocating for a shard bounded to `capacity` total weight.
+    #[inline]
+    fn expected_len(&self, _capacity: usize) -> usize {
+        0
+    }
+}
+
+/// Every value weighs 1, so capacity is an entry count.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Unweighted;
+
+impl<T: ?Sized> Weigher<T> for Unweighted {
+    #[inline]
+    fn weigh(&self, _value: &T) -> usize {
+        1
+    }
+
+    #[inline]
+    fn expected_len(&self, capacity: usize) -> usize {
+        capacity
+    }
+}
+
+pub trait Weight {
+    fn weight(&self) -> usize;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WeightIntrusive;
+
+impl<T: ?Sized + Weight> Weigher<T> for WeightIntrusive {
+    #[inline]
+    fn weigh(&self, value: &T) -> usize {
+        value.weight()
+    }
+}
+
+impl<T: ?Sized, F: Fn(&T) -> usize> Weigher<T> for F {
+    #[inline]
+    fn weigh(&self, value: &T) -> usize {
+        self(value)
+    }
+}
+
+/// Tracks the total weight held by an eviction shard against its capacity.
+#[derive(Debug)]
+pub(crate) struct Budget<W> {
+    weigher: W,
+    capacity: usize,
+    weight: usize,
+}
+
+impl<W> Budget<W> {
+    pub fn new(weigher: W, capacity: usize) -> Self {
+        Self {
+            weigher,
+            capacity,
+            weight: 0,
+        }
+    }
+
+    pub fn weigh<T: ?Sized>(&self, value: &T) -> usize
+    where
+        W: Weigher<T>,
+    {
+        self.weigher.weigh(value)
+    }
+
+    pub fn expected_len<T: ?Sized>(&self) -> usize
+    where
+        W: Weigher<T>,
+    {
+        self.weigher.expected_len(self.capacity)
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+    }
+
+    /// Whether adding `incoming` weight would put us over capacity.
+    pub fn overflows(&self, incoming: usize) -> bool {
+        self.weight.saturating_add(incoming) > self.capacity
+    }
+
+    pub fn add(&mut self, weight: usize) {
+        self.weight = self.weight.saturating_add(weight);
+    }
+
+    pub fn sub<T: ?Sized>(&mut self, value: &T)
+    where
+        W: Weigher<T>,
+    {
+        let weight = self.weigher.weigh(value);
+        debug_assert!(weight <= self.weight, "weight changed while cached");
+        self.weight = self.weight.saturating_sub(weight);
+    }
+}
+
+#[test]
+fn weighted_capacity() {
+    use crate::{build::BuildCache, evict::read::EvictLeastRecentlyRead, Cache};
+
+    use super::test::single_shard;
+
+    struct Blob(&'static str, usize);
+
+    impl crate::Value for Blob {
+        type Key = str;
+
+        fn key(&self) -> &str {
+            self.0
+        }
+    }
+
+    let cache = single_shard(
+        BuildCache::<Blob>::default().layer(EvictLeastRecentlyRead::with_weigher(|b: &Blob| b.1)),
+        100,
+    );
+
+    cache.insert(Blob("a", 40));
+    cache.insert(Blob("b", 40));
+    cache.insert(Blob("c", 10));
+    assert_eq!(cache.len(), 3);
+
+    // needs to evict both a and b to fit
+    cache.insert(Blob("d", 90));
+    assert!(cache.get("a").is_none());
+    assert!(cache.get("b").is_none());
+    assert!(cache.get("c").is_some());
+    assert!(cache.get("d").is_some());
+
+    cache.insert(Blob("e", 10));
+    assert!(cache.get("c").is_none());
+    assert_eq!(cache.len(), 2);
+
+    // Replacing d gives back the weight it took up
+    cache.insert(Blob("d", 20));
+    cache.insert(Blob("f", 70));
+    assert_eq!(cache.len(), 3);
+
+    // Anything heavier than the whole capacity still goes in, alone
+    cache.insert(Blob("g", 150));
+    assert!(cache.get("g").is_some());
+    assert_eq!(cache.len(), 1);
+    cache.insert(Blob("h", 10));
+    assert!(cache.get("g").is_none());
+    assert_eq!(cache.len(), 1);
+}
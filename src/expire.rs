@@ -10,6 +10,9 @@ use crate::{
     Clock, DefaultClock,
 };
 
+mod wheel;
+pub use wheel::{ExpireTimerWheelLayer, Timer, TimerWheelShard};
+
 pub trait Expire {
     fn is_expired(&self) -> bool;
 }
@@ -43,6 +46,10 @@ where
 
     fn remove<R>(&mut self, _pointer: &P) {}
 
+    fn is_expired<R: Resolve<P, Self::Value>>(&self, pointer: &P) -> bool {
+        pointer.is_expired()
+    }
+
     const READ_LOCK: ReadLock = ReadLock::Ref;
 
     fn read_ref<R: Resolve<P, Self::Value>>(&self, pointer: &P) -> ReadResult {
@@ -101,6 +108,10 @@ where
 
     fn remove<R: Resolve<P, Self::Value>>(&mut self, _pointer: &P) {}
 
+    fn is_expired<R: Resolve<P, Self::Value>>(&self, pointer: &P) -> bool {
+        pointer.expire_at() <= self.0.now()
+    }
+
     const READ_LOCK: ReadLock = ReadLock::Ref;
 
     fn read_ref<R: Resolve<P, Self::Value>>(&self, pointer: &P) -> ReadResult {
@@ -171,6 +182,10 @@ where
 
     fn remove<R>(&mut self, _pointer: &P) {}
 
+    fn is_expired<R: Resolve<P, Self::Value>>(&self, pointer: &P) -> bool {
+        *R::resolve(pointer) <= self.0.clock.now()
+    }
+
     const READ_LOCK: ReadLock = ReadLock::Ref;
 
     fn read_ref<R: Resolve<P, Self::Value>>(&self, pointer: &P) -> ReadResult {
@@ -241,6 +256,11 @@ where
 
     fn remove<R>(&mut self, _pointer: &P) {}
 
+    // Unlike a read, checking doesn't push the deadline back
+    fn is_expired<R: Resolve<P, Self::Value>>(&self, pointer: &P) -> bool {
+        R::resolve(pointer).load(Ordering::Relaxed) <= self.0.clock.now()
+    }
+
     const READ_LOCK: ReadLock = ReadLock::Ref;
 
     fn read_ref<R: Resolve<P, Self::Value>>(&self, pointer: &P) -> ReadResult {
@@ -0,0 +1,318 @@
+use std::{
+    ops::Deref,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    evict::{
+        index::AtomicKey,
+        list::List,
+    },
+    layer::{Layer, ReadLock, ReadResult, Resolve, Shard, Write},
+    Clock, DefaultClock,
+};
+
+// Same layout as Caffeine's wheel: 64 ~1.07s slots, 64 ~1.14m slots, 32 ~1.22h slots, 4 ~1.63d
+// slots and one slot for everything further out than that.
+const BUCKETS: [usize; 5] = [64, 64, 32, 4, 1];
+const SHIFTS: [u32; 5] = [30, 36, 42, 47, 49];
+const OFFSETS: [usize; 5] = [0, 64, 128, 160, 164];
+const SLOTS: usize = 165;
+
+/// Expire after write, like [`ExpireAfterWriteLayer`](super::ExpireAfterWriteLayer), but with
+/// each shard bucketing its entries by deadline in a hierarchical timer wheel. Expired entries
+/// are removed as time moves on during writes and maintenance rather than waiting to be read.
+#[derive(Debug)]
+pub struct ExpireTimerWheelLayer<F, C = DefaultClock>(Arc<ExpireTimerWheelInner<F, C>>);
+
+#[derive(Debug)]
+struct ExpireTimerWheelInner<F, C> {
+    expire_at_fn: F,
+    clock: C,
+}
+
+impl<F, C: Default> ExpireTimerWheelLayer<F, C> {
+    pub fn new(expire_at_fn: F) -> Self {
+        Self::with_clock(expire_at_fn, C::default())
+    }
+}
+
+impl<F, C> ExpireTimerWheelLayer<F, C> {
+    pub fn with_clock(expire_at_fn: F, clock: C) -> Self {
+        Self(Arc::new(ExpireTimerWheelInner {
+            expire_at_fn,
+            clock,
+        }))
+    }
+}
+
+/// Where an entry sits in its shard's wheel.
+#[doc(hidden)]
+#[derive(Debug)]
+pub struct Timer {
+    deadline: Instant,
+    slot: AtomicUsize,
+    key: AtomicKey,
+}
+
+pub struct TimerWheelShard<P, F, C> {
+    inner: Arc<ExpireTimerWheelInner<F, C>>,
+    zero: Instant,
+    // Nanos since `zero` the wheel was last advanced to
+    now: u64,
+    slots: Box<[List<P>]>,
+}
+
+impl<P, F, C> Layer<P> for ExpireTimerWheelLayer<F, C>
+where
+    P: Deref + Clone,
+    F: Fn(Instant, &P::Target) -> Instant,
+    C: Clock,
+{
+    type Value = Timer;
+    type Shard = TimerWheelShard<P, F, C>;
+
+    fn new_shard(&self, _capacity: usize) -> Self::Shard {
+        TimerWheelShard {
+            inner: Arc::clone(&self.0),
+            zero: self.0.clock.now(),
+            now: 0,
+            slots: std::iter::repeat_with(|| List::with_capacity(0))
+                .take(SLOTS)
+                .collect(),
+        }
+    }
+}
+
+impl<P: Deref + Clone, F, C: Clock> TimerWheelShard<P, F, C> {
+    fn nanos(&self, instant: Instant) -> u64 {
+        instant.saturating_duration_since(self.zero).as_nanos() as u64
+    }
+
+    fn slot_for(&self, deadline: Instant) -> usize {
+        // Already due goes in the current slot so the next tick picks it up
+        let deadline = self.nanos(deadline).max(self.now);
+        let duration = deadline.saturating_sub(self.now);
+        for level in 0..BUCKETS.len() - 1 {
+            if duration < 1 << SHIFTS[level + 1] {
+                let ticks = (deadline >> SHIFTS[level]) as usize;
+                return OFFSETS[level] + (ticks & (BUCKETS[level] - 1));
+            }
+        }
+        OFFSETS[BUCKETS.len() - 1]
+    }
+
+    fn schedule<R: Resolve<P, Timer>>(&mut self, pointer: P) {
+        let slot = self.slot_for(R::resolve(&pointer).deadline);
+        R::resolve(&pointer).slot.store(slot, Ordering::Relaxed);
+        self.slots[slot].push_tail_with_key(|key| {
+            R::resolve(&pointer).key.store(key, Ordering::Relaxed);
+            pointer
+        });
+    }
+
+    /// Move the wheel up to the clock, expiring or cascading down everything in the slots it
+    /// passes over.
+    fn advance<R: Resolve<P, Timer>>(&mut self, mut remove: impl FnMut(&P)) {
+        let now = self.nanos(self.inner.clock.now());
+        if now <= self.now {
+            return;
+        }
+        let previous = std::mem::replace(&mut self.now, now);
+
+        for level in 0..BUCKETS.len() {
+            let previous_ticks = previous >> SHIFTS[level];
+            let ticks = now >> SHIFTS[level];
+            if ticks <= previous_ticks {
+                break;
+            }
+
+            let mask = BUCKETS[level] - 1;
+            let steps = (ticks - previous_ticks + 1).min(BUCKETS[level] as u64) as usize;
+            for step in 0..steps {
+                let slot = OFFSETS[level] + ((previous_ticks as usize + step) & mask);
+                self.expire_slot::<R>(slot, &mut remove);
+            }
+        }
+    }
+
+    fn expire_slot<R: Resolve<P, Timer>>(&mut self, slot: usize, remove: &mut impl FnMut(&P)) {
+        // Taken out first since anything not yet due may well land back in the same slot
+        let mut list = std::mem::replace(&mut self.slots[slot], List::with_capacity(0));
+        for pointer in list.drain() {
+            if self.nanos(R::resolve(&pointer).deadline) <= self.now {
+                remove(&pointer);
+            } else {
+                self.schedule::<R>(pointer);
+            }
+        }
+        // Keep the allocation unless the slot's been handed out keys in the meantime
+        if self.slots[slot].len() == 0 {
+            self.slots[slot] = list;
+        }
+    }
+}
+
+impl<P, F, C> Shard<P> for TimerWheelShard<P, F, C>
+where
+    P: Deref + Clone,
+    F: Fn(Instant, &P::Target) -> Instant,
+    C: Clock,
+{
+    type Value = Timer;
+
+    fn write<R: Resolve<P, Self::Value>>(&mut self, mut write: impl Write<P, Self::Value>) -> P {
+        self.advance::<R>(|p| write.expire(p));
+
+        let deadline = (self.inner.expire_at_fn)(self.inner.clock.now(), write.target());
+        let slot = self.slot_for(deadline);
+        self.slots[slot]
+            .push_tail_with_key(|key| {
+                write.write(Timer {
+                    deadline,
+                    slot: slot.into(),
+                    key: key.into(),
+                })
+            })
+            .clone()
+    }
+
+    fn remove<R: Resolve<P, Self::Value>>(&mut self, pointer: &P) {
+        let timer = R::resolve(pointer);
+        let slot = timer.slot.load(Ordering::Relaxed);
+        let removed = self.slots[slot].remove(timer.key.load(Ordering::Relaxed));
+        debug_assert!(removed.is_some());
+    }
+
+    fn is_expired<R: Resolve<P, Self::Value>>(&self, pointer: &P) -> bool {
+        R::resolve(pointer).deadline <= self.inner.clock.now()
+    }
+
+    fn maintain<R: Resolve<P, Self::Value>>(&mut self, remove: impl FnMut(&P)) {
+        self.advance::<R>(remove);
+    }
+
+    fn next_expiration<R: Resolve<P, Self::Value>>(&self) -> Option<Instant> {
+        let mut next: Option<Instant> = None;
+        for level in 0..BUCKETS.len() {
+            let mask = BUCKETS[level] - 1;
+            let ticks = self.now >> SHIFTS[level];
+            for step in 0..BUCKETS[level] as u64 {
+                // Nothing in this slot or any after it on this level is due before it starts
+                let starts = self.zero + Duration::from_nanos((ticks + step) << SHIFTS[level]);
+                if next.is_some_and(|next| next <= starts) {
+                    break;
+                }
+                let slot = OFFSETS[level] + ((ticks + step) as usize & mask);
+                for pointer in self.slots[slot].iter() {
+                    let deadline = R::resolve(pointer).deadline;
+                    next = Some(next.map_or(deadline, |next| next.min(deadline)));
+                }
+            }
+        }
+        next
+    }
+
+    const READ_LOCK: ReadLock = ReadLock::Ref;
+
+    fn read_ref<R: Resolve<P, Self::Value>>(&self, pointer: &P) -> ReadResult {
+        if self.is_expired::<R>(pointer) {
+            ReadResult::Remove
+        } else {
+            ReadResult::Retain
+        }
+    }
+
+    const ITER_READ_LOCK: ReadLock = ReadLock::Ref;
+
+    fn iter_read_ref<R: Resolve<P, Self::Value>>(&self, pointer: &P) -> ReadResult {
+        self.read_ref::<R>(pointer)
+    }
+}
+
+#[test]
+fn timer_wheel() {
+    use parking_lot::Mutex;
+
+    use crate::{build::BuildCache, sync::SyncCacheBuilder, Cache};
+
+    struct Entry(u32, Duration);
+
+    impl crate::Value for Entry {
+        type Key = u32;
+
+        fn key(&self) -> &u32 {
+            &self.0
+        }
+    }
+
+    #[derive(Clone)]
+    struct ManualClock(Arc<Mutex<Instant>>);
+
+    impl Clock for ManualClock {
+        fn now(&self) -> Instant {
+            *self.0.lock()
+        }
+    }
+
+    let start = Instant::now();
+    let clock = ManualClock(Arc::new(Mutex::new(start)));
+    let advance = |by: Duration| *clock.0.lock() += by;
+    let minutes = |m: u64| Duration::from_secs(m * 60);
+
+    let cache = BuildCache::<Entry>::default()
+        .layer(ExpireTimerWheelLayer::with_clock(
+            |now, entry: &Entry| now + entry.1,
+            clock.clone(),
+        ))
+        .build_custom(|layer| {
+            SyncCacheBuilder::new()
+                .exact_shards(1)
+                .stats()
+                .build_with_layer(layer)
+        });
+
+    cache.insert(Entry(0, Duration::from_secs(1)));
+    cache.insert(Entry(1, Duration::from_secs(30)));
+    cache.insert(Entry(2, minutes(10)));
+    cache.insert(Entry(3, minutes(3 * 60)));
+    cache.insert(Entry(4, minutes(3 * 24 * 60)));
+    assert_eq!(cache.next_expiration(), Some(start + Duration::from_secs(1)));
+
+    // Writes move the wheel along
+    advance(Duration::from_secs(5));
+    cache.insert(Entry(5, minutes(10 * 24 * 60)));
+    assert!(cache.get(&0).is_none());
+    assert_eq!(cache.len(), 5);
+    assert_eq!(cache.next_expiration(), Some(start + Duration::from_secs(30)));
+
+    // As does maintenance, cascading entries down from the higher levels as they get closer
+    for (by, len) in [(minutes(1), 4), (minutes(15), 3), (minutes(4 * 60), 2)] {
+        advance(by);
+        cache.run_maintenance(Duration::ZERO);
+        assert_eq!(cache.len(), len);
+    }
+    advance(minutes(3 * 24 * 60));
+    cache.run_maintenance(Duration::ZERO);
+    assert_eq!(cache.len(), 1);
+    assert!(cache.get(&5).is_some());
+
+    // Days out, but not far enough for the last level
+    let deadline = clock.now() + minutes(5 * 24 * 60);
+    cache.insert(Entry(6, minutes(5 * 24 * 60)));
+    assert_eq!(cache.next_expiration(), Some(deadline));
+    for (by, len) in [(minutes(4 * 24 * 60), 2), (minutes(24 * 60 - 1), 2), (minutes(2), 1)] {
+        advance(by);
+        cache.run_maintenance(Duration::ZERO);
+        assert_eq!(cache.len(), len);
+    }
+    assert!(cache.get(&5).is_some());
+
+    let stats = cache.stats().unwrap();
+    assert_eq!(stats.expirations, 6);
+    assert_eq!(stats.hits, 2);
+}
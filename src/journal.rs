@@ -0,0 +1,702 @@
+use std::{
+    borrow::Borrow,
+    fs::{self, File, OpenOptions},
+    hash::Hash,
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    marker::PhantomData,
+    path::{Path, PathBuf},
+    sync::{Arc, Weak},
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use parking_lot::{Condvar, Mutex, MutexGuard};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{stats::CacheStats, Cache, Entry, OccupiedEntry, VacantEntry, Value};
+
+// A journal is the magic bytes and a little endian u32 format version, then records framed as a
+// u32 length and the CRC32 of the bincode encoded record that follows. Replay stops at the first
+// frame that doesn't check out, which is where a crash mid-append leaves it.
+const MAGIC: &[u8; 8] = b"CACHJRNL";
+
+pub const FORMAT_VERSION: u32 = 1;
+
+#[derive(Deserialize)]
+enum Record<T, K> {
+    Insert(T),
+    Remove(K),
+}
+
+// Encodes the same as `Record`
+#[derive(Serialize)]
+enum RecordRef<'a, T, K: ?Sized> {
+    Insert(&'a T),
+    Remove(&'a K),
+}
+
+#[derive(Debug, Clone)]
+pub struct JournalBuilder {
+    path: PathBuf,
+    compact_after: u64,
+    flush_every: Duration,
+}
+
+impl JournalBuilder {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            compact_after: 1 << 16,
+            flush_every: Duration::from_secs(1),
+        }
+    }
+
+    /// Rewrite the journal down to the cache's contents once it holds this many records, or
+    /// twice as many as the last rewrite left if that's more. A rewrite that fails is tried again
+    /// this many records later, with the journal carrying on as it was meanwhile.
+    pub fn compact_after(self, records: u64) -> Self {
+        Self {
+            compact_after: records,
+            ..self
+        }
+    }
+
+    /// Longest records can sit buffered in process before the background thread hands them to
+    /// the OS, which bounds what a crashed process loses.
+    pub fn flush_every(self, every: Duration) -> Self {
+        Self {
+            flush_every: every,
+            ..self
+        }
+    }
+
+    /// Replay whatever journal is already at the path into `cache`, then carry on journaling
+    /// everything written through the returned wrapper.
+    pub fn open<T, C>(self, cache: C) -> io::Result<JournaledCache<C, T>>
+    where
+        T: Value + Serialize + DeserializeOwned + 'static,
+        T::Key: Sized + Serialize + DeserializeOwned,
+        C: Cache<T> + Send + Sync + 'static,
+    {
+        let file = match OpenOptions::new().read(true).append(true).open(&self.path) {
+            Ok(file) => {
+                replay(&cache, BufReader::new(&file))?;
+                file
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => create(&self.path)?,
+            Err(err) => return Err(err),
+        };
+
+        let shared = Arc::new(Shared {
+            cache,
+            journal: Mutex::new(Journal {
+                written: file.metadata()?.len(),
+                writer: BufWriter::new(file),
+                records: 0,
+                compact_at: self.compact_after,
+                compacting: false,
+                error: None,
+            }),
+            options: self,
+            background: Default::default(),
+            _value: PhantomData,
+        });
+        // Leaves the journal holding exactly what was replayed, minus any torn tail, without ever
+        // being without a whole journal on disk
+        shared.compact()?;
+
+        let every = shared.options.flush_every;
+        let weak = Arc::downgrade(&shared);
+        let background = Arc::clone(&shared.background);
+        let thread = std::thread::Builder::new()
+            .name("cach-journal".into())
+            .spawn(move || {
+                let (state, wake) = &*background;
+                let mut guard = state.lock();
+                while !guard.stopped {
+                    if !guard.compact {
+                        wake.wait_for(&mut guard, every);
+                    }
+                    if guard.stopped {
+                        break;
+                    }
+                    // Only hold on to the cache while working, so it goes along with the wrapper
+                    let Some(shared) = Weak::upgrade(&weak) else {
+                        break;
+                    };
+                    let compact = std::mem::take(&mut guard.compact);
+                    MutexGuard::unlocked(&mut guard, || shared.maintain(compact));
+                }
+            })?;
+        Ok(JournaledCache {
+            shared,
+            thread: Some(thread),
+        })
+    }
+}
+
+fn create(path: &Path) -> io::Result<File> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)?;
+    file.write_all(MAGIC)?;
+    file.write_all(&FORMAT_VERSION.to_le_bytes())?;
+    Ok(file)
+}
+
+fn replay<T, C>(cache: &C, mut reader: impl Read) -> io::Result<()>
+where
+    T: Value + DeserializeOwned,
+    T::Key: Sized + DeserializeOwned,
+    C: Cache<T>,
+{
+    let mut header = [0; MAGIC.len() + size_of::<u32>()];
+    reader.read_exact(&mut header)?;
+    if &header[..MAGIC.len()] != MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a cache journal"));
+    }
+    let version = u32::from_le_bytes(header[MAGIC.len()..].try_into().unwrap());
+    if version != FORMAT_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unsupported journal format version {version}"),
+        ));
+    }
+
+    let mut payload = Vec::new();
+    loop {
+        let mut frame = [0; 8];
+        if reader.read_exact(&mut frame).is_err() {
+            break;
+        }
+        let len = u32::from_le_bytes(frame[..4].try_into().unwrap());
+        let checksum = u32::from_le_bytes(frame[4..].try_into().unwrap());
+
+        payload.clear();
+        let read = (&mut reader).take(len.into()).read_to_end(&mut payload)?;
+        if read != len as usize || crc32fast::hash(&payload) != checksum {
+            break;
+        }
+        match bincode::deserialize::<Record<T, T::Key>>(&payload) {
+            Ok(Record::Insert(value)) => {
+                cache.insert(value);
+            }
+            Ok(Record::Remove(key)) => {
+                cache.remove(&key);
+            }
+            Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+        }
+    }
+    Ok(())
+}
+
+struct Journal {
+    writer: BufWriter<File>,
+    // Length of the file once the writer is flushed
+    written: u64,
+    records: u64,
+    compact_at: u64,
+    compacting: bool,
+    // Journaling stops at the first failed write, which is then reported by `flush`
+    error: Option<io::Error>,
+}
+
+impl Journal {
+    fn append(&mut self, record: &impl Serialize) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(err) = self.try_append(record) {
+            self.error = Some(err);
+        }
+    }
+
+    fn try_append(&mut self, record: &impl Serialize) -> io::Result<()> {
+        let payload =
+            bincode::serialize(record).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let len = u32::try_from(payload.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "record too large"))?;
+
+        self.writer.write_all(&len.to_le_bytes())?;
+        self.writer.write_all(&crc32fast::hash(&payload).to_le_bytes())?;
+        self.writer.write_all(&payload)?;
+        self.written += 8 + u64::from(len);
+        self.records += 1;
+        Ok(())
+    }
+
+    fn check(&self) -> io::Result<()> {
+        match &self.error {
+            Some(err) => Err(io::Error::new(err.kind(), err.to_string())),
+            None => Ok(()),
+        }
+    }
+
+    fn compaction_due(&self) -> bool {
+        !self.compacting && self.error.is_none() && self.records >= self.compact_at
+    }
+
+    fn compacted_path(path: &Path) -> PathBuf {
+        let mut compacted = path.to_owned().into_os_string();
+        compacted.push(".compact");
+        PathBuf::from(compacted)
+    }
+}
+
+/// Cache wrapper that appends every insert, replace and remove made through it to a journal
+/// file, so a crashed process can get its cache back by opening the same path. Evictions and
+/// expirations aren't journaled; the cache works those out again as the journal is replayed.
+///
+/// A background thread flushes the journal and compacts it once it's due, so writers only ever
+/// pay for buffering their own record. Dropping the cache waits for a compaction in progress, so
+/// the path can be opened again straight away.
+pub struct JournaledCache<C, T> {
+    shared: Arc<Shared<C, T>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+struct Shared<C, T> {
+    cache: C,
+    journal: Mutex<Journal>,
+    options: JournalBuilder,
+    // Shared with the background thread, which flushes and compacts
+    background: Arc<(Mutex<Background>, Condvar)>,
+    _value: PhantomData<fn(T) -> T>,
+}
+
+#[derive(Default)]
+struct Background {
+    stopped: bool,
+    compact: bool,
+}
+
+// Anything more than this that's been journaled since compaction started is copied over
+// before taking the journal's lock for the rest
+const LOCKED_COPY: u64 = 64 * 1024;
+
+impl<C, T> Shared<C, T>
+where
+    T: Value + Serialize,
+    T::Key: Sized + Serialize,
+    C: Cache<T>,
+{
+    fn append(&self, record: RecordRef<'_, T, T::Key>) {
+        let due = {
+            let mut journal = self.journal.lock();
+            journal.append(&record);
+            journal.compaction_due()
+        };
+        if due {
+            let (state, wake) = &*self.background;
+            state.lock().compact = true;
+            wake.notify_one();
+        }
+    }
+
+    /// Run on the background thread every `flush_every`, or sooner once compaction is due.
+    fn maintain(&self, compact: bool) {
+        if compact {
+            // Already set up to be retried, and anything wrong with the journal itself shows up
+            // flushing it below
+            let _ = self.compact();
+        }
+
+        let mut journal = self.journal.lock();
+        if journal.error.is_none() && !journal.writer.buffer().is_empty() {
+            if let Err(err) = journal.writer.flush() {
+                journal.error = Some(err);
+            }
+        }
+    }
+
+    fn compact(&self) -> io::Result<()> {
+        let start = {
+            let mut journal = self.journal.lock();
+            if journal.compacting {
+                return Ok(());
+            }
+            journal.check()?;
+            journal.writer.flush()?;
+            journal.compacting = true;
+            (journal.written, journal.records)
+        };
+
+        let compacted = Journal::compacted_path(&self.options.path);
+        let result = self.rewrite(&compacted, start);
+        if result.is_err() {
+            let _ = fs::remove_file(&compacted);
+            let mut journal = self.journal.lock();
+            journal.compacting = false;
+            journal.compact_at = journal
+                .compact_at
+                .max(journal.records + self.options.compact_after);
+        }
+        result
+    }
+
+    /// Rewrite the journal as one insert per cached value. Writes carry on while the cache is
+    /// read, going to the old journal, and are copied over before it's swapped out. Replaying
+    /// them on top of the rewrite gives the same result as they're all last write wins.
+    fn rewrite(&self, compacted: &Path, (mut start, mut start_records): (u64, u64)) -> io::Result<()> {
+        let path = &self.options.path;
+        let mut writer = BufWriter::new(create(compacted)?);
+        let mut written = (MAGIC.len() + size_of::<u32>()) as u64;
+        let mut records = 0;
+        for pointer in self.cache.iter() {
+            let payload = bincode::serialize(&RecordRef::<T, T::Key>::Insert(&pointer))
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            writer.write_all(&(payload.len() as u32).to_le_bytes())?;
+            writer.write_all(&crc32fast::hash(&payload).to_le_bytes())?;
+            writer.write_all(&payload)?;
+            written += 8 + payload.len() as u64;
+            records += 1;
+        }
+
+        // Catch up on what's been journaled meanwhile without holding up writers
+        let mut old = File::open(path)?;
+        loop {
+            let (end, end_records) = {
+                let mut journal = self.journal.lock();
+                journal.check()?;
+                journal.writer.flush()?;
+                (journal.written, journal.records)
+            };
+            old.seek(SeekFrom::Start(start))?;
+            written += io::copy(&mut (&mut old).take(end - start), &mut writer)?;
+            records += end_records - start_records;
+            let behind = end - start;
+            (start, start_records) = (end, end_records);
+            if behind <= LOCKED_COPY {
+                break;
+            }
+        }
+        writer.flush()?;
+        writer.get_ref().sync_data()?;
+
+        // Hold the journal for the last few records and the swap, so nothing is appended to the
+        // old file in between. What's copied here is only handed to the OS, like any append.
+        let mut journal = self.journal.lock();
+        journal.check()?;
+        journal.writer.flush()?;
+        old.seek(SeekFrom::Start(start))?;
+        written += io::copy(&mut (&mut old).take(journal.written - start), &mut writer)?;
+        records += journal.records - start_records;
+        writer.flush()?;
+        fs::rename(compacted, path)?;
+
+        journal.writer = writer;
+        journal.written = written;
+        journal.records = records;
+        journal.compact_at = self.options.compact_after.max(records * 2);
+        journal.compacting = false;
+        drop(journal);
+
+        sync_parent(path)
+    }
+}
+
+/// Make a rename into `path` durable.
+fn sync_parent(path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        File::open(parent)?.sync_all()?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+impl<C, T> JournaledCache<C, T>
+where
+    T: Value + Serialize,
+    T::Key: Sized + Serialize,
+    C: Cache<T>,
+{
+    /// Rewrite the journal down to one insert per cached value now, rather than waiting for the
+    /// background thread to get round to it.
+    pub fn compact(&self) -> io::Result<()> {
+        self.shared.compact()
+    }
+
+    /// Hand everything journaled so far to the OS and wait for it to reach disk. Also where a
+    /// failed write shows up, after which nothing more is journaled.
+    pub fn flush(&self) -> io::Result<()> {
+        let mut journal = self.shared.journal.lock();
+        journal.check()?;
+        journal.writer.flush()?;
+        journal.writer.get_ref().sync_data()
+    }
+}
+
+impl<C, T> Drop for JournaledCache<C, T> {
+    fn drop(&mut self) {
+        let (state, wake) = &*self.shared.background;
+        state.lock().stopped = true;
+        wake.notify_one();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl<C, T> Drop for Shared<C, T> {
+    fn drop(&mut self) {
+        let _ = self.journal.get_mut().writer.flush();
+    }
+}
+
+impl<C, T> Cache<T> for JournaledCache<C, T>
+where
+    T: Value + Serialize,
+    T::Key: Sized + Serialize,
+    C: Cache<T>,
+{
+    type Pointer = C::Pointer;
+
+    fn len(&self) -> usize {
+        self.shared.cache.len()
+    }
+
+    fn iter(&self) -> impl Iterator<Item = Self::Pointer> {
+        self.shared.cache.iter()
+    }
+
+    fn entry<'c, K>(
+        &'c self,
+        key: &K,
+    ) -> Entry<
+        impl OccupiedEntry<Pointer = Self::Pointer> + 'c,
+        impl VacantEntry<Pointer = Self::Pointer> + 'c,
+    >
+    where
+        T::Key: Borrow<K>,
+        K: ?Sized + Hash + Eq,
+    {
+        match self.shared.cache.entry(key) {
+            Entry::Occupied(occupied) => Entry::Occupied(Journaled {
+                entry: occupied,
+                shared: &self.shared,
+            }),
+            Entry::Vacant(vacant) => Entry::Vacant(Journaled {
+                entry: vacant,
+                shared: &self.shared,
+            }),
+        }
+    }
+
+    fn get<K>(&self, key: &K) -> Option<Self::Pointer>
+    where
+        T::Key: Borrow<K>,
+        K: ?Sized + Hash + Eq,
+    {
+        self.shared.cache.get(key)
+    }
+
+    fn get_many<'k, K>(&self, keys: impl IntoIterator<Item = &'k K>) -> Vec<Option<Self::Pointer>>
+    where
+        T::Key: Borrow<K>,
+        K: ?Sized + Hash + Eq + 'k,
+    {
+        self.shared.cache.get_many(keys)
+    }
+
+    fn stats(&self) -> Option<CacheStats> {
+        self.shared.cache.stats()
+    }
+}
+
+struct Journaled<'c, E, C, T> {
+    entry: E,
+    shared: &'c Shared<C, T>,
+}
+
+impl<E, C, T> OccupiedEntry for Journaled<'_, E, C, T>
+where
+    T: Value + Serialize,
+    T::Key: Sized + Serialize,
+    C: Cache<T>,
+    E: OccupiedEntry<Pointer = C::Pointer>,
+{
+    type Pointer = C::Pointer;
+
+    fn value(&self) -> &T {
+        self.entry.value()
+    }
+
+    fn pointer(&self) -> Self::Pointer {
+        self.entry.pointer()
+    }
+
+    fn into_pointer(self) -> Self::Pointer {
+        self.entry.into_pointer()
+    }
+
+    // Journaled while the entry is still held so records for a key go in the order they happen
+    fn replace(self, value: T) -> Self::Pointer {
+        self.shared.append(RecordRef::Insert(&value));
+        self.entry.replace(value)
+    }
+
+    fn remove(self) -> Self::Pointer {
+        self.shared.append(RecordRef::Remove(self.entry.value().key()));
+        self.entry.remove()
+    }
+}
+
+impl<E, C, T> VacantEntry for Journaled<'_, E, C, T>
+where
+    T: Value + Serialize,
+    T::Key: Sized + Serialize,
+    C: Cache<T>,
+    E: VacantEntry<Pointer = C::Pointer>,
+{
+    type Pointer = C::Pointer;
+
+    fn insert(self, value: T) -> Self::Pointer {
+        self.shared.append(RecordRef::Insert(&value));
+        self.entry.insert(value)
+    }
+}
+
+#[test]
+fn journal() {
+    use crate::{build::BuildCache, sync::SyncCacheBuilder};
+
+    #[derive(Serialize, Deserialize)]
+    struct Entry(u32, String);
+
+    impl Value for Entry {
+        type Key = u32;
+
+        fn key(&self) -> &u32 {
+            &self.0
+        }
+    }
+
+    let path = std::env::temp_dir().join(format!("cach-journal-{}", std::process::id()));
+    let _ = fs::remove_file(&path);
+    let open = || {
+        let cache = BuildCache::<Entry>::default()
+            .build_custom(|layer| SyncCacheBuilder::new().capacity(64).build_with_layer(layer));
+        JournalBuilder::new(&path)
+            .compact_after(16)
+            .flush_every(Duration::from_millis(10))
+            .open(cache)
+            .unwrap()
+    };
+    let journal_len = || fs::metadata(&path).unwrap().len();
+
+    let cache = open();
+    for i in 0..10 {
+        cache.insert(Entry(i, i.to_string()));
+    }
+    cache.insert(Entry(3, "three".into()));
+    cache.remove(&5);
+    // Reaches the file without being asked to
+    std::thread::sleep(Duration::from_millis(200));
+    let flushed = journal_len();
+    cache.flush().unwrap();
+    assert_eq!(journal_len(), flushed);
+    drop(cache);
+
+    // Half written record from a crash mid-append
+    let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(&[200, 0, 0, 0, 1, 2]).unwrap();
+    drop(file);
+
+    let cache = open();
+    assert_eq!(cache.len(), 9);
+    assert_eq!(cache.get(&3).unwrap().1, "three");
+    assert!(cache.get(&5).is_none());
+
+    // Compaction keeps the journal from growing with the number of writes, in the background
+    for round in 0.. {
+        if round >= 100 && journal_len() < 1024 {
+            break;
+        }
+        assert!(round < 10_000, "never compacted");
+        cache.insert(Entry(round % 4, round.to_string()));
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    cache.insert(Entry(3, "last".into()));
+    cache.remove(&0);
+    cache.flush().unwrap();
+    drop(cache);
+
+    let cache = open();
+    assert_eq!(cache.len(), 8);
+    assert_eq!(cache.get(&3).unwrap().1, "last");
+    assert!(cache.get(&0).is_none());
+    drop(cache);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn compaction_failure() {
+    use crate::{build::BuildCache, sync::SyncCacheBuilder};
+
+    #[derive(Serialize, Deserialize)]
+    struct Entry(u32, String);
+
+    impl Value for Entry {
+        type Key = u32;
+
+        fn key(&self) -> &u32 {
+            &self.0
+        }
+    }
+
+    let path = std::env::temp_dir().join(format!("cach-journal-failure-{}", std::process::id()));
+    let compacted = Journal::compacted_path(&path);
+    let _ = fs::remove_file(&path);
+    let _ = fs::remove_dir(&compacted);
+    let open = || {
+        let cache = BuildCache::<Entry>::default()
+            .build_custom(|layer| SyncCacheBuilder::new().capacity(64).build_with_layer(layer));
+        JournalBuilder::new(&path)
+            .compact_after(16)
+            .flush_every(Duration::from_millis(10))
+            .open(cache)
+            .unwrap()
+    };
+
+    // Nothing can be written where the rewrite goes
+    let cache = open();
+    fs::create_dir(&compacted).unwrap();
+    assert!(cache.compact().is_err());
+    for round in 0..100 {
+        cache.insert(Entry(round % 4, round.to_string()));
+    }
+    // Which leaves appending alone
+    cache.flush().unwrap();
+
+    // And compaction picks up again as writes carry on
+    fs::remove_dir(&compacted).unwrap();
+    for round in 100.. {
+        if fs::metadata(&path).unwrap().len() < 1024 {
+            break;
+        }
+        assert!(round < 10_000, "never compacted");
+        cache.insert(Entry(round % 4, round.to_string()));
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    cache.insert(Entry(3, "last".into()));
+    cache.remove(&0);
+    cache.flush().unwrap();
+    drop(cache);
+
+    let cache = open();
+    assert_eq!(cache.len(), 3);
+    assert_eq!(cache.get(&3).unwrap().1, "last");
+    assert!(cache.get(&0).is_none());
+    drop(cache);
+    fs::remove_file(&path).unwrap();
+    assert!(!compacted.exists());
+}
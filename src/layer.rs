@@ -1,4 +1,4 @@
-use std::{marker::PhantomData, ops::Deref};
+use std::{hash::Hash, marker::PhantomData, ops::Deref, time::Instant};
 
 use smallvec::SmallVec;
 
@@ -13,6 +13,12 @@ pub trait Layer<P: Deref> {
 
     fn new_shard(&self, capacity: usize) -> Self::Shard;
 
+    /// Number of entries a shard with `capacity` is expected to hold, used for preallocation.
+    #[inline]
+    fn expected_len(&self, capacity: usize) -> usize {
+        capacity
+    }
+
     fn and_then<N>(self, next: N) -> AndThen<Self, N>
     where
         Self: Sized,
@@ -49,6 +55,41 @@ pub trait Shard<P: Deref> {
 
     fn remove<R: Resolve<P, Self::Value>>(&mut self, pointer: &P);
 
+    /// Rebound the shard to `capacity`, passing anything that no longer fits to `remove`.
+    #[inline]
+    fn set_capacity<R: Resolve<P, Self::Value>>(&mut self, _capacity: usize, _remove: impl FnMut(&P)) {}
+
+    /// Whether `pointer` has expired, without counting as a read.
+    #[inline]
+    fn is_expired<R: Resolve<P, Self::Value>>(&self, _pointer: &P) -> bool {
+        false
+    }
+
+    /// Periodic housekeeping, passing anything the shard drops to `remove`.
+    #[inline]
+    fn maintain<R: Resolve<P, Self::Value>>(&mut self, _remove: impl FnMut(&P)) {}
+
+    /// Earliest deadline of anything in the shard, if the shard keeps track of them.
+    #[inline]
+    fn next_expiration<R: Resolve<P, Self::Value>>(&self) -> Option<Instant> {
+        None
+    }
+
+    /// Visit everything in the order it would be evicted, next to go first. Returns false
+    /// without visiting anything if the shard doesn't keep an order.
+    #[inline]
+    fn eviction_order<R: Resolve<P, Self::Value>>(&self, _f: impl FnMut(&P)) -> bool {
+        false
+    }
+
+    /// Whether reads that come up empty should be passed to [`miss`](Self::miss), which costs
+    /// the write lock.
+    const OBSERVES_MISSES: bool = false;
+
+    /// A read found nothing for `key`, or found it had expired.
+    #[inline]
+    fn miss<R: Resolve<P, Self::Value>>(&mut self, _key: &(impl Hash + ?Sized)) {}
+
     const READ_LOCK: ReadLock;
 
     /// If result is remove, remove() will be called after with the same pointer
@@ -75,6 +116,14 @@ pub trait Shard<P: Deref> {
     }
 }
 
+/// The sooner of two optional deadlines, where `None` means never.
+pub(crate) fn earliest(a: Option<Instant>, b: Option<Instant>) -> Option<Instant> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ReadLock {
     None,
@@ -115,6 +164,13 @@ pub trait Resolve<P, V> {
 pub trait Write<P: Deref, V> {
     fn target(&self) -> &P::Target;
     fn remove(&mut self, pointer: &P);
+
+    /// Like `remove`, but for something that expired rather than being evicted.
+    #[inline]
+    fn expire(&mut self, pointer: &P) {
+        self.remove(pointer)
+    }
+
     fn write(self, value: V) -> P;
 }
 
@@ -153,10 +209,96 @@ pub struct LayerCapacityFraction<L> {
 
 impl<P: Deref, L: Layer<P>> Layer<P> for LayerCapacityFraction<L> {
     type Value = L::Value;
-    type Shard = L::Shard;
+    type Shard = CapacityFractionShard<L::Shard>;
 
     fn new_shard(&self, capacity: usize) -> Self::Shard {
-        self.layer.new_shard(((capacity as f32 * self.fraction).round() as usize).max(1))
+        CapacityFractionShard {
+            shard: self.layer.new_shard(fraction_of(capacity, self.fraction)),
+            fraction: self.fraction,
+        }
+    }
+
+    fn expected_len(&self, capacity: usize) -> usize {
+        self.layer.expected_len(fraction_of(capacity, self.fraction))
+    }
+}
+
+fn fraction_of(capacity: usize, fraction: f32) -> usize {
+    ((capacity as f32 * fraction).round() as usize).max(1)
+}
+
+pub struct CapacityFractionShard<S> {
+    shard: S,
+    fraction: f32,
+}
+
+impl<P: Deref, S: Shard<P>> Shard<P> for CapacityFractionShard<S> {
+    type Value = S::Value;
+
+    #[inline]
+    fn write<R: Resolve<P, Self::Value>>(&mut self, write: impl Write<P, Self::Value>) -> P {
+        self.shard.write::<R>(write)
+    }
+
+    #[inline]
+    fn remove<R: Resolve<P, Self::Value>>(&mut self, pointer: &P) {
+        self.shard.remove::<R>(pointer)
+    }
+
+    #[inline]
+    fn set_capacity<R: Resolve<P, Self::Value>>(&mut self, capacity: usize, remove: impl FnMut(&P)) {
+        self.shard.set_capacity::<R>(fraction_of(capacity, self.fraction), remove)
+    }
+
+    #[inline]
+    fn is_expired<R: Resolve<P, Self::Value>>(&self, pointer: &P) -> bool {
+        self.shard.is_expired::<R>(pointer)
+    }
+
+    #[inline]
+    fn maintain<R: Resolve<P, Self::Value>>(&mut self, remove: impl FnMut(&P)) {
+        self.shard.maintain::<R>(remove)
+    }
+
+    #[inline]
+    fn next_expiration<R: Resolve<P, Self::Value>>(&self) -> Option<Instant> {
+        self.shard.next_expiration::<R>()
+    }
+
+    #[inline]
+    fn eviction_order<R: Resolve<P, Self::Value>>(&self, f: impl FnMut(&P)) -> bool {
+        self.shard.eviction_order::<R>(f)
+    }
+
+    const OBSERVES_MISSES: bool = S::OBSERVES_MISSES;
+
+    #[inline]
+    fn miss<R: Resolve<P, Self::Value>>(&mut self, key: &(impl Hash + ?Sized)) {
+        self.shard.miss::<R>(key)
+    }
+
+    const READ_LOCK: ReadLock = S::READ_LOCK;
+
+    #[inline]
+    fn read_ref<R: Resolve<P, Self::Value>>(&self, pointer: &P) -> ReadResult {
+        self.shard.read_ref::<R>(pointer)
+    }
+
+    #[inline]
+    fn read_mut<R: Resolve<P, Self::Value>>(&mut self, pointer: &P) -> ReadResult {
+        self.shard.read_mut::<R>(pointer)
+    }
+
+    const ITER_READ_LOCK: ReadLock = S::ITER_READ_LOCK;
+
+    #[inline]
+    fn iter_read_ref<R: Resolve<P, Self::Value>>(&self, pointer: &P) -> ReadResult {
+        self.shard.iter_read_ref::<R>(pointer)
+    }
+
+    #[inline]
+    fn iter_read_mut<R: Resolve<P, Self::Value>>(&mut self, pointer: &P) -> ReadResult {
+        self.shard.iter_read_mut::<R>(pointer)
     }
 }
 
@@ -182,6 +324,10 @@ where
     fn new_shard(&self, capacity: usize) -> Self::Shard {
         AndThenShard(self.0.new_shard(capacity), self.1.new_shard(capacity))
     }
+
+    fn expected_len(&self, capacity: usize) -> usize {
+        self.0.expected_len(capacity).min(self.1.expected_len(capacity))
+    }
 }
 
 struct ResolveA<R, A, B>(PhantomData<(R, A, B)>);
@@ -253,6 +399,11 @@ where
                 self.inner.remove(pointer);
             }
 
+            fn expire(&mut self, pointer: &P) {
+                self.a.remove::<ResolveA<R, _, _>>(pointer);
+                self.inner.expire(pointer);
+            }
+
             fn write(self, b: B::Value) -> P {
                 struct WriteA<'a, P, R, W, B> {
                     _resolve: PhantomData<R>,
@@ -279,6 +430,11 @@ where
                         self.inner.remove(pointer);
                     }
 
+                    fn expire(&mut self, pointer: &P) {
+                        self.removed_by_a.push(pointer.clone());
+                        self.inner.expire(pointer);
+                    }
+
                     fn write(self, a: A) -> P {
                         self.inner.write((a, self.b))
                     }
@@ -316,6 +472,50 @@ where
         self.1.remove::<ResolveB<R, _, _>>(pointer);
     }
 
+    fn set_capacity<R: Resolve<P, Self::Value>>(&mut self, capacity: usize, mut remove: impl FnMut(&P)) {
+        let Self(a, b) = self;
+        a.set_capacity::<ResolveA<R, _, _>>(capacity, |p| {
+            b.remove::<ResolveB<R, _, _>>(p);
+            remove(p);
+        });
+        b.set_capacity::<ResolveB<R, _, _>>(capacity, |p| {
+            a.remove::<ResolveA<R, _, _>>(p);
+            remove(p);
+        });
+    }
+
+    fn is_expired<R: Resolve<P, Self::Value>>(&self, pointer: &P) -> bool {
+        self.0.is_expired::<ResolveA<R, _, _>>(pointer) || self.1.is_expired::<ResolveB<R, _, _>>(pointer)
+    }
+
+    fn maintain<R: Resolve<P, Self::Value>>(&mut self, mut remove: impl FnMut(&P)) {
+        let Self(a, b) = self;
+        a.maintain::<ResolveA<R, _, _>>(|p| {
+            b.remove::<ResolveB<R, _, _>>(p);
+            remove(p);
+        });
+        b.maintain::<ResolveB<R, _, _>>(|p| {
+            a.remove::<ResolveA<R, _, _>>(p);
+            remove(p);
+        });
+    }
+
+    fn next_expiration<R: Resolve<P, Self::Value>>(&self) -> Option<Instant> {
+        earliest(self.0.next_expiration::<ResolveA<R, _, _>>(), self.1.next_expiration::<ResolveB<R, _, _>>())
+    }
+
+    fn eviction_order<R: Resolve<P, Self::Value>>(&self, mut f: impl FnMut(&P)) -> bool {
+        // The outermost layer that keeps an order gets the final say
+        self.1.eviction_order::<ResolveB<R, _, _>>(&mut f) || self.0.eviction_order::<ResolveA<R, _, _>>(&mut f)
+    }
+
+    const OBSERVES_MISSES: bool = A::OBSERVES_MISSES || B::OBSERVES_MISSES;
+
+    fn miss<R: Resolve<P, Self::Value>>(&mut self, key: &(impl Hash + ?Sized)) {
+        self.0.miss::<ResolveA<R, _, _>>(key);
+        self.1.miss::<ResolveB<R, _, _>>(key);
+    }
+
     const READ_LOCK: ReadLock = A::READ_LOCK.or(B::READ_LOCK);
 
     fn read_ref<R: Resolve<P, Self::Value>>(&self, pointer: &P) -> ReadResult {
@@ -1,4 +1,4 @@
-use std::{marker::PhantomData, ops::Deref};
+use std::{hash::Hash, marker::PhantomData, ops::Deref, time::Instant};
 
 pub struct MultiLayer<K, L0, L1> {
     key_fn: K,
@@ -44,6 +44,12 @@ where
             s1: self.l1.new_shard(capacity),
         }
     }
+
+    fn expected_len(&self, capacity: usize) -> usize {
+        self.l0
+            .expected_len(capacity)
+            .saturating_add(self.l1.expected_len(capacity))
+    }
 }
 
 impl<K, P, S0, S1> super::Shard<P> for Shard<K, S0, S1>
@@ -81,6 +87,10 @@ where
                     fn remove(&mut self, pointer: &P) {
                         self.inner.remove(pointer);
                     }
+
+                    fn expire(&mut self, pointer: &P) {
+                        self.inner.expire(pointer);
+                    }
         
                     fn write(self, value: S0::Value) -> P {
                         self.inner.write(Value::V0(value))
@@ -112,6 +122,10 @@ where
                     fn remove(&mut self, pointer: &P) {
                         self.inner.remove(pointer);
                     }
+
+                    fn expire(&mut self, pointer: &P) {
+                        self.inner.expire(pointer);
+                    }
         
                     fn write(self, value: S1::Value) -> P {
                         self.inner.write(Value::V1(value))
@@ -134,6 +148,47 @@ where
         }
     }
 
+    #[inline]
+    fn set_capacity<R: super::Resolve<P, Self::Value>>(
+        &mut self,
+        capacity: usize,
+        mut remove: impl FnMut(&P),
+    ) {
+        self.s0.set_capacity::<Resolve0<R, _, _>>(capacity, &mut remove);
+        self.s1.set_capacity::<Resolve1<R, _, _>>(capacity, &mut remove);
+    }
+
+    #[inline]
+    fn is_expired<R: super::Resolve<P, Self::Value>>(&self, pointer: &P) -> bool {
+        match R::resolve(pointer) {
+            Value::V0(_) => self.s0.is_expired::<Resolve0<R, _, _>>(pointer),
+            Value::V1(_) => self.s1.is_expired::<Resolve1<R, _, _>>(pointer),
+        }
+    }
+
+    #[inline]
+    fn maintain<R: super::Resolve<P, Self::Value>>(&mut self, mut remove: impl FnMut(&P)) {
+        self.s0.maintain::<Resolve0<R, _, _>>(&mut remove);
+        self.s1.maintain::<Resolve1<R, _, _>>(&mut remove);
+    }
+
+    #[inline]
+    fn next_expiration<R: super::Resolve<P, Self::Value>>(&self) -> Option<Instant> {
+        super::earliest(
+            self.s0.next_expiration::<Resolve0<R, _, _>>(),
+            self.s1.next_expiration::<Resolve1<R, _, _>>(),
+        )
+    }
+
+    const OBSERVES_MISSES: bool = S0::OBSERVES_MISSES || S1::OBSERVES_MISSES;
+
+    /// There's no value to tell which side a missing key would have gone to, so both hear it.
+    #[inline]
+    fn miss<R: super::Resolve<P, Self::Value>>(&mut self, key: &(impl Hash + ?Sized)) {
+        self.s0.miss::<Resolve0<R, _, _>>(key);
+        self.s1.miss::<Resolve1<R, _, _>>(key);
+    }
+
     const READ_LOCK: super::ReadLock = S0::READ_LOCK.or(S1::READ_LOCK);
 
     #[inline]
@@ -7,14 +7,21 @@ use std::{
 pub mod build;
 pub mod evict;
 pub mod expire;
+#[cfg(feature = "serde")]
+pub mod journal;
+pub mod listener;
 pub mod local;
 pub mod map;
+pub mod stats;
 pub mod sync;
 pub mod time;
 pub mod load;
+#[cfg(feature = "serde")]
+pub mod snapshot;
 mod wrap;
 mod layer;
 
+use stats::CacheStats;
 use time::{Clock, DefaultClock};
 
 pub trait Cache<T: Value> {
@@ -90,6 +97,29 @@ pub trait Cache<T: Value> {
         self.or_insert_with(key, Default::default)
     }
 
+    /// Decide what happens to `key` while holding its entry, so nothing else can change it in
+    /// between. Returns what's cached for `key` afterwards.
+    fn compute<K>(&self, key: &K, f: impl FnOnce(Option<&T>) -> Op<T>) -> Option<Self::Pointer>
+    where
+        T::Key: Borrow<K>,
+        K: ?Sized + Hash + Eq,
+    {
+        match self.entry(key) {
+            Entry::Occupied(o) => match f(Some(o.value())) {
+                Op::Keep => Some(o.into_pointer()),
+                Op::Put(value) => Some(o.replace(value)),
+                Op::Remove => {
+                    o.remove();
+                    None
+                }
+            },
+            Entry::Vacant(v) => match f(None) {
+                Op::Put(value) => Some(v.insert(value)),
+                Op::Keep | Op::Remove => None,
+            },
+        }
+    }
+
     fn remove_if<K: ?Sized>(&self, key: &K, f: impl FnOnce(&T) -> bool) -> Option<Self::Pointer>
     where
         T::Key: Borrow<K>,
@@ -109,6 +139,26 @@ pub trait Cache<T: Value> {
         self.remove_if(key, |_existing| true)
     }
 
+    fn retain(&self, mut f: impl FnMut(&T) -> bool) {
+        for pointer in self.iter() {
+            if !f(&pointer) {
+                self.remove_if(pointer.key(), |existing| std::ptr::eq(existing, &*pointer));
+            }
+        }
+    }
+
+    /// Values are removed as the iterator reaches them, so dropping it early leaves the rest
+    /// cached.
+    fn drain(&self) -> impl Iterator<Item = Self::Pointer> {
+        self.iter().filter_map(|pointer| {
+            self.remove_if(pointer.key(), |existing| std::ptr::eq(existing, &*pointer))
+        })
+    }
+
+    fn clear(&self) {
+        self.drain().for_each(drop);
+    }
+
     // XX good to override if can avoid write lock
     fn get<K: ?Sized>(&self, key: &K) -> Option<Self::Pointer>
     where
@@ -120,6 +170,32 @@ pub trait Cache<T: Value> {
             Entry::Vacant(_) => None,
         }
     }
+
+    /// Results line up with `keys`.
+    fn get_many<'k, K>(&self, keys: impl IntoIterator<Item = &'k K>) -> Vec<Option<Self::Pointer>>
+    where
+        T::Key: Borrow<K>,
+        K: ?Sized + Hash + Eq + 'k,
+    {
+        keys.into_iter().map(|key| self.get(key)).collect()
+    }
+
+    fn insert_many(&self, values: impl IntoIterator<Item = T>) -> Vec<Self::Pointer> {
+        values.into_iter().map(|value| self.insert(value)).collect()
+    }
+
+    fn remove_many<'k, K>(&self, keys: impl IntoIterator<Item = &'k K>) -> Vec<Option<Self::Pointer>>
+    where
+        T::Key: Borrow<K>,
+        K: ?Sized + Hash + Eq + 'k,
+    {
+        keys.into_iter().map(|key| self.remove(key)).collect()
+    }
+
+    /// `None` unless the cache was built to collect stats.
+    fn stats(&self) -> Option<CacheStats> {
+        None
+    }
 }
 
 #[derive(Debug)]
@@ -128,6 +204,54 @@ pub enum Entry<O, V> {
     Vacant(V),
 }
 
+/// What [`Cache::compute`] should do with an entry. `Put` values must have the same key.
+#[derive(Debug)]
+pub enum Op<T> {
+    Keep,
+    Put(T),
+    Remove,
+}
+
+/// An occupied entry after [`Entry::and_modify`] replaced its value. The entry's lock has
+/// already been released.
+#[derive(Debug)]
+pub struct Replaced<P>(P);
+
+// Kept out of scope everywhere so it never competes with `OccupiedEntry::into_pointer`
+mod occupied {
+    use std::ops::Deref;
+
+    /// The occupied side of an [`Entry`](crate::Entry), whether it's still held or has been
+    /// let go of by [`Entry::and_modify`](crate::Entry::and_modify).
+    pub trait IntoPointer {
+        type Pointer: Deref;
+
+        fn into_pointer(self) -> Self::Pointer;
+    }
+
+    impl<O: crate::OccupiedEntry> IntoPointer for O {
+        type Pointer = O::Pointer;
+
+        fn into_pointer(self) -> O::Pointer {
+            crate::OccupiedEntry::into_pointer(self)
+        }
+    }
+
+    impl<P: Deref> IntoPointer for crate::Replaced<P> {
+        type Pointer = P;
+
+        fn into_pointer(self) -> P {
+            self.0
+        }
+    }
+}
+
+impl<P> Replaced<P> {
+    pub fn into_pointer(self) -> P {
+        self.0
+    }
+}
+
 pub trait OccupiedEntry: Sized {
     type Pointer: Deref;
 
@@ -154,7 +278,7 @@ pub trait VacantEntry {
         <Self::Pointer as Deref>::Target: Sized;
 }
 
-impl<O: OccupiedEntry, V: VacantEntry<Pointer = O::Pointer>> Entry<O, V> {
+impl<O: occupied::IntoPointer, V: VacantEntry<Pointer = O::Pointer>> Entry<O, V> {
     pub fn or_insert_with(self, f: impl FnOnce() -> <O::Pointer as Deref>::Target) -> O::Pointer
     where
         <O::Pointer as Deref>::Target: Sized,
@@ -178,6 +302,38 @@ impl<O: OccupiedEntry, V: VacantEntry<Pointer = O::Pointer>> Entry<O, V> {
     {
         self.or_insert_with(Default::default)
     }
+
+    /// On error nothing is inserted and the entry is left vacant.
+    pub fn or_try_insert_with<E>(
+        self,
+        f: impl FnOnce() -> Result<<O::Pointer as Deref>::Target, E>,
+    ) -> Result<O::Pointer, E>
+    where
+        <O::Pointer as Deref>::Target: Sized,
+    {
+        match self {
+            Entry::Occupied(o) => Ok(o.into_pointer()),
+            Entry::Vacant(v) => Ok(v.insert(f()?)),
+        }
+    }
+}
+
+impl<O: OccupiedEntry, V: VacantEntry<Pointer = O::Pointer>> Entry<O, V> {
+    pub fn and_modify(
+        self,
+        f: impl FnOnce(&<O::Pointer as Deref>::Target) -> <O::Pointer as Deref>::Target,
+    ) -> Entry<Replaced<O::Pointer>, V>
+    where
+        <O::Pointer as Deref>::Target: Sized,
+    {
+        match self {
+            Entry::Occupied(o) => {
+                let value = f(o.value());
+                Entry::Occupied(Replaced(o.replace(value)))
+            }
+            Entry::Vacant(v) => Entry::Vacant(v),
+        }
+    }
 }
 
 pub trait Value {
@@ -252,3 +408,46 @@ fn test() {
     // }
     // let _ = use_cache(&cache);
 }
+
+#[test]
+fn entry_api() {
+    use build::BuildCache;
+
+    #[derive(Debug, PartialEq)]
+    struct Count(&'static str, u32);
+
+    impl Value for Count {
+        type Key = str;
+
+        fn key(&self) -> &str {
+            self.0
+        }
+    }
+
+    let cache = BuildCache::<Count>::default().build_sync();
+    let bump = |key| {
+        cache
+            .entry(key)
+            .and_modify(|count: &Count| Count(count.0, count.1 + 1))
+            .or_insert(Count(key, 1))
+    };
+    assert_eq!(bump("a").1, 1);
+    assert_eq!(bump("a").1, 2);
+
+    assert_eq!(cache.entry("b").or_try_insert_with(|| Err("nope")).err(), Some("nope"));
+    assert!(cache.get("b").is_none());
+    assert_eq!(cache.entry("b").or_try_insert_with(|| Ok::<_, ()>(Count("b", 7))).unwrap().1, 7);
+
+    let halve = |count: Option<&Count>| match count {
+        Some(Count(key, n)) if *n > 1 => Op::Put(Count(key, n / 2)),
+        Some(_) => Op::Remove,
+        None => Op::Keep,
+    };
+    assert_eq!(cache.compute("b", halve).unwrap().1, 3);
+    assert!(cache.compute("a", halve).is_some());
+    assert!(cache.compute("a", halve).is_none());
+    assert!(cache.get("a").is_none());
+    assert!(cache.compute("c", halve).is_none());
+    assert_eq!(cache.compute("c", |_| Op::Put(Count("c", 1))).unwrap().1, 1);
+    assert_eq!(cache.len(), 2);
+}
@@ -0,0 +1,117 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RemovalCause {
+    /// Pushed out by an eviction layer to make room.
+    Evicted,
+    /// Found expired by a layer on read.
+    Expired,
+    /// Overwritten by a new value for the same key.
+    Replaced,
+    /// Removed through the cache or one of its entries.
+    Explicit,
+    /// Removed by clearing the whole cache.
+    Cleared,
+}
+
+/// Notified of every pointer that leaves a cache, after the cache has released any locks it
+/// took, so it's safe to do I/O or call back into the cache.
+pub trait RemovalListener<P> {
+    fn on_removal(&self, pointer: P, cause: RemovalCause);
+}
+
+impl<P> RemovalListener<P> for () {
+    #[inline]
+    fn on_removal(&self, _pointer: P, _cause: RemovalCause) {}
+}
+
+impl<P, F: Fn(P, RemovalCause)> RemovalListener<P> for F {
+    #[inline]
+    fn on_removal(&self, pointer: P, cause: RemovalCause) {
+        self(pointer, cause)
+    }
+}
+
+#[test]
+fn removal_causes() {
+    use std::sync::{Arc, Mutex};
+
+    use crate::{
+        build::BuildCache, evict::write::EvictLeastRecentlyWritten, expire::Expire,
+        sync::{self, SyncCacheBuilder}, Cache,
+    };
+
+    struct Session {
+        id: u32,
+        expired: bool,
+    }
+
+    impl crate::Value for Session {
+        type Key = u32;
+
+        fn key(&self) -> &u32 {
+            &self.id
+        }
+    }
+
+    impl Expire for Session {
+        fn is_expired(&self) -> bool {
+            self.expired
+        }
+    }
+
+    fn exercise(cache: &impl Cache<Session>) {
+        let session = |id| Session { id, expired: false };
+        cache.insert(session(1));
+        cache.insert(session(1));
+        cache.insert(session(2));
+        cache.insert(session(3));
+        cache.remove(&2);
+        cache.insert(Session { id: 4, expired: true });
+        assert!(cache.get(&4).is_none());
+        cache.insert(session(5));
+        cache.retain(|session| session.id != 3);
+        assert_eq!(cache.drain().count(), 1);
+        cache.insert(session(6));
+        cache.clear();
+    }
+    let expected = [
+        (1, RemovalCause::Replaced),
+        (1, RemovalCause::Evicted),
+        (2, RemovalCause::Explicit),
+        (4, RemovalCause::Expired),
+        (3, RemovalCause::Explicit),
+        (5, RemovalCause::Cleared),
+        (6, RemovalCause::Cleared),
+    ];
+
+    let removed = Arc::new(Mutex::new(Vec::new()));
+    let listener = || {
+        let removed = Arc::clone(&removed);
+        move |p: sync::Pointer<Session, _>, cause| removed.lock().unwrap().push((p.id, cause))
+    };
+    let cache = BuildCache::<Session>::default()
+        .expire()
+        .layer(EvictLeastRecentlyWritten::default())
+        .build_custom(|layer| {
+            SyncCacheBuilder::new()
+                .exact_shards(1)
+                .capacity(2)
+                .removal_listener(listener())
+                .build_with_layer(layer)
+        });
+    exercise(&cache);
+    assert_eq!(*removed.lock().unwrap(), expected);
+
+    removed.lock().unwrap().clear();
+    let cache = BuildCache::<Session>::default()
+        .expire()
+        .layer(EvictLeastRecentlyWritten::default())
+        .build_custom(|layer| {
+            SyncCacheBuilder::new()
+                .exact_shards(1)
+                .capacity(2)
+                .removal_listener(listener())
+                .build_scalable_with_layer(layer)
+        });
+    exercise(&cache);
+    assert_eq!(*removed.lock().unwrap(), expected);
+}
@@ -2,9 +2,22 @@ use std::{borrow::Borrow, future::Future, hash::Hash};
 
 use crate::{Cache, Value};
 
+mod blocking;
 mod dedup;
+mod value;
+pub use blocking::{BlockingPointer, BlockingValue, DedupLoadBlocking};
 pub use dedup::DedupLoadIntrusive;
 
+/// Blocking counterpart to [`AsyncLoad`], for reading through from threads rather than tasks.
+pub trait Load<T: Value> {
+    type Output;
+
+    fn load<K>(&self, key: &K) -> Self::Output
+    where
+        K: ?Sized + ToOwned<Owned = T::Key> + Hash + Eq,
+        T::Key: Borrow<K>;
+}
+
 
 pub trait AsyncLoad<T: Value> {
     type Output;
@@ -36,4 +49,13 @@ where
     T: Value + Send,
     C: Cache<T> + AsyncLoad<T, Output = C::Pointer>,
 {
-}
\ No newline at end of file
+}
+
+pub trait LoadCache<T: Value>: Cache<T> + Load<T, Output = Self::Pointer> {}
+
+impl<T, C> LoadCache<T> for C
+where
+    T: Value,
+    C: Cache<T> + Load<T, Output = C::Pointer>,
+{
+}
@@ -0,0 +1,476 @@
+use std::{
+    borrow::Borrow,
+    hash::Hash,
+    marker::PhantomData,
+    ops::Deref,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use parking_lot::{Condvar, Mutex};
+
+use super::value::{LoadValue, ValueInner};
+use crate::{
+    load::Load,
+    stats::{CacheStats, StatsCounter},
+    Cache, Entry, OccupiedEntry, VacantEntry, Value as _,
+};
+
+/// Read through `cache` with `load`, like [`DedupLoadIntrusive`](super::DedupLoadIntrusive) but
+/// for threads instead of tasks: the first to miss a key loads it while everyone else asking for
+/// the same key parks until it's done.
+#[derive(Debug)]
+pub struct DedupLoadBlocking<L, C>(Arc<DedupInner<L, C>>);
+
+impl<L, C> DedupLoadBlocking<L, C> {
+    pub fn new(load: L, cache: C) -> Self {
+        Self(Arc::new(DedupInner {
+            load,
+            cache,
+            stats: StatsCounter::default(),
+        }))
+    }
+}
+
+impl<L, C> Clone for DedupLoadBlocking<L, C> {
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+}
+
+#[derive(Debug)]
+struct DedupInner<L, C> {
+    load: L,
+    cache: C,
+    stats: StatsCounter,
+}
+
+// Only public so that `BlockingValue` can name it, the module isn't
+#[derive(Default)]
+pub struct Waiters {
+    done: Mutex<bool>,
+    finished: Condvar,
+}
+
+impl Waiters {
+    fn wait(&self) {
+        let mut done = self.done.lock();
+        while !*done {
+            self.finished.wait(&mut done);
+        }
+    }
+
+    fn wake(&self) {
+        *self.done.lock() = true;
+        self.finished.notify_all();
+    }
+}
+
+/// What a [`DedupLoadBlocking`] keeps in its cache.
+pub type BlockingValue<T> = LoadValue<T, Arc<Waiters>>;
+
+#[derive(Debug)]
+pub struct BlockingPointer<P, T> {
+    inner: P,
+    _marker: PhantomData<T>,
+}
+
+impl<P: Clone, T> Clone for BlockingPointer<P, T> {
+    fn clone(&self) -> Self {
+        Self::new(self.inner.clone())
+    }
+}
+
+impl<P, T> BlockingPointer<P, T> {
+    fn new(inner: P) -> Self {
+        Self {
+            inner,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<P, T> Deref for BlockingPointer<P, T>
+where
+    P: Deref<Target = BlockingValue<T>>,
+    T: crate::Value,
+    T::Key: Sized,
+{
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        match &self.inner.0 {
+            ValueInner::Complete(v, _) => v,
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl<T, L, C> Load<T> for DedupLoadBlocking<L, C>
+where
+    T: crate::Value,
+    T::Key: Sized,
+    L: Load<T, Output = T>,
+    C: Cache<BlockingValue<T>>,
+{
+    type Output = BlockingPointer<C::Pointer, T>;
+
+    fn load<K>(&self, key: &K) -> Self::Output
+    where
+        K: ?Sized + ToOwned<Owned = T::Key> + Hash + Eq,
+        T::Key: Borrow<K>,
+    {
+        let this = &self.0;
+        loop {
+            let waiters = match this.cache.entry(key) {
+                Entry::Occupied(o) => match &o.value().0 {
+                    ValueInner::Waiting { waiters, .. } => Arc::clone(waiters),
+                    ValueInner::Complete(..) => return BlockingPointer::new(o.into_pointer()),
+                },
+                Entry::Vacant(v) => {
+                    let waiters = Arc::<Waiters>::default();
+                    let pointer =
+                        v.insert(BlockingValue::waiting(key.to_owned(), Arc::clone(&waiters)));
+                    let _guard = LoadGuard {
+                        cache: &this.cache,
+                        pointer: &pointer,
+                        waiters,
+                    };
+
+                    let start = Instant::now();
+                    let value = this.load.load(key);
+                    let load_time = start.elapsed();
+                    this.stats.loaded(load_time);
+                    return this.insert_loaded_value(value, load_time);
+                }
+            };
+
+            // Whatever the load left behind gets looked at again, so a failed or evicted load
+            // is retried by one of the waiters
+            this.stats.coalesced();
+            waiters.wait();
+        }
+    }
+}
+
+/// Wakes a load's waiters once it's done, first clearing out its placeholder if the load
+/// panicked before replacing it.
+struct LoadGuard<'a, C, T>
+where
+    T: crate::Value,
+    T::Key: Sized,
+    C: Cache<BlockingValue<T>>,
+{
+    cache: &'a C,
+    pointer: &'a C::Pointer,
+    waiters: Arc<Waiters>,
+}
+
+impl<C, T> Drop for LoadGuard<'_, C, T>
+where
+    T: crate::Value,
+    T::Key: Sized,
+    C: Cache<BlockingValue<T>>,
+{
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            self.cache.remove_if(self.pointer.key(), |existing| {
+                std::ptr::eq(existing, &**self.pointer)
+            });
+        }
+        self.waiters.wake();
+    }
+}
+
+impl<T, L, C> Cache<T> for DedupLoadBlocking<L, C>
+where
+    T: crate::Value,
+    T::Key: Sized,
+    C: Cache<BlockingValue<T>>,
+{
+    type Pointer = BlockingPointer<C::Pointer, T>;
+
+    fn len(&self) -> usize {
+        self.0.cache.len()
+    }
+
+    fn iter(&self) -> impl Iterator<Item = Self::Pointer> {
+        self.0
+            .cache
+            .iter()
+            .filter(|p| matches!(p.0, ValueInner::Complete(..)))
+            .map(BlockingPointer::new)
+    }
+
+    fn entry<'c, K>(
+        &'c self,
+        key: &K,
+    ) -> Entry<
+        impl OccupiedEntry<Pointer = Self::Pointer> + 'c,
+        impl VacantEntry<Pointer = Self::Pointer> + 'c,
+    >
+    where
+        <T as crate::Value>::Key: Borrow<K>,
+        K: ?Sized + Hash + Eq,
+    {
+        match self.0.cache.entry(key) {
+            Entry::Occupied(o) => match &o.value().0 {
+                ValueInner::Waiting { .. } => Entry::Vacant(Vacant(Some(VacantInner::Waiting(o)))),
+                ValueInner::Complete(..) => Entry::Occupied(Occupied(o)),
+            },
+            Entry::Vacant(v) => Entry::Vacant(Vacant(Some(VacantInner::Vacant(v)))),
+        }
+    }
+
+    fn retain(&self, mut f: impl FnMut(&T) -> bool) {
+        self.0.cache.retain(|v| match &v.0 {
+            ValueInner::Waiting { .. } => true,
+            ValueInner::Complete(v, _) => f(v),
+        })
+    }
+
+    fn drain(&self) -> impl Iterator<Item = Self::Pointer> {
+        self.iter().filter_map(|pointer| {
+            self.0
+                .cache
+                .remove_if(pointer.key(), |existing| {
+                    std::ptr::eq(existing, &*pointer.inner)
+                })
+                .map(BlockingPointer::new)
+        })
+    }
+
+    fn clear(&self) {
+        self.retain(|_| false)
+    }
+
+    fn stats(&self) -> Option<CacheStats> {
+        self.0
+            .cache
+            .stats()
+            .map(|stats| stats + self.0.stats.snapshot())
+    }
+}
+
+struct Occupied<O: OccupiedEntry>(O);
+
+impl<T, O> OccupiedEntry for Occupied<O>
+where
+    T: crate::Value,
+    T::Key: Sized,
+    O: OccupiedEntry,
+    O::Pointer: Deref<Target = BlockingValue<T>>,
+{
+    type Pointer = BlockingPointer<O::Pointer, T>;
+
+    fn value(&self) -> &<Self::Pointer as Deref>::Target {
+        match &self.0.value().0 {
+            ValueInner::Complete(v, _) => v,
+            _ => unreachable!(),
+        }
+    }
+
+    fn pointer(&self) -> Self::Pointer {
+        BlockingPointer::new(self.0.pointer())
+    }
+
+    fn into_pointer(self) -> Self::Pointer {
+        BlockingPointer::new(self.0.into_pointer())
+    }
+
+    fn replace(self, value: T) -> Self::Pointer {
+        BlockingPointer::new(self.0.replace(BlockingValue::complete(value, Duration::ZERO)))
+    }
+
+    fn remove(self) -> Self::Pointer {
+        BlockingPointer::new(self.0.remove())
+    }
+}
+
+struct Vacant<O: OccupiedEntry, V>(Option<VacantInner<O, V>>);
+
+enum VacantInner<O, V> {
+    Waiting(O),
+    Vacant(V),
+}
+
+impl<T, O, V> VacantEntry for Vacant<O, V>
+where
+    T: crate::Value,
+    T::Key: Sized,
+    O: OccupiedEntry,
+    O::Pointer: Deref<Target = BlockingValue<T>>,
+    V: VacantEntry<Pointer = O::Pointer>,
+{
+    type Pointer = BlockingPointer<O::Pointer, T>;
+
+    fn insert(mut self, value: <Self::Pointer as Deref>::Target) -> Self::Pointer
+    where
+        <Self::Pointer as Deref>::Target: Sized,
+    {
+        let value = BlockingValue::complete(value, Duration::ZERO);
+        match self.0.take().unwrap() {
+            VacantInner::Waiting(occupied) => {
+                let ValueInner::Waiting { waiters, .. } = &occupied.value().0 else {
+                    unreachable!()
+                };
+                let waiters = Arc::clone(waiters);
+                let pointer = occupied.replace(value);
+                waiters.wake();
+                BlockingPointer::new(pointer)
+            }
+            VacantInner::Vacant(v) => BlockingPointer::new(v.insert(value)),
+        }
+    }
+}
+
+impl<L, C> DedupInner<L, C> {
+    fn insert_loaded_value<T>(&self, value: T, load_time: Duration) -> BlockingPointer<C::Pointer, T>
+    where
+        T: crate::Value,
+        T::Key: Sized,
+        C: Cache<BlockingValue<T>>,
+    {
+        // Waiters are woken by the load's guard once this returns
+        BlockingPointer::new(self.cache.insert(BlockingValue::complete(value, load_time)))
+    }
+}
+
+#[test]
+fn dedup_load_blocking() {
+    use std::sync::{atomic::AtomicUsize, atomic::Ordering, Barrier};
+
+    use crate::{build::BuildCache, sync::SyncCacheBuilder};
+
+    #[derive(Debug)]
+    struct Entry(u32, usize);
+
+    impl crate::Value for Entry {
+        type Key = u32;
+
+        fn key(&self) -> &u32 {
+            &self.0
+        }
+    }
+
+    struct Counting {
+        loads: AtomicUsize,
+        started: Barrier,
+    }
+
+    impl Load<Entry> for Counting {
+        type Output = Entry;
+
+        fn load<K>(&self, key: &K) -> Entry
+        where
+            K: ?Sized + ToOwned<Owned = u32> + Hash + Eq,
+            u32: Borrow<K>,
+        {
+            let key = key.to_owned();
+            if key == 1 {
+                panic!("failed to load");
+            }
+            let loads = self.loads.fetch_add(1, Ordering::Relaxed);
+            // Hold the load until the other threads have had a chance to pile up behind it
+            self.started.wait();
+            std::thread::sleep(Duration::from_millis(50));
+            Entry(key, loads)
+        }
+    }
+
+    const THREADS: usize = 8;
+    let cache = DedupLoadBlocking::new(
+        Counting {
+            loads: AtomicUsize::new(0),
+            started: Barrier::new(2),
+        },
+        BuildCache::<BlockingValue<Entry>>::default().build_custom(|layer| {
+            SyncCacheBuilder::new().stats().build_with_layer(layer)
+        }),
+    );
+
+    let start = Barrier::new(THREADS);
+    std::thread::scope(|scope| {
+        let threads: Vec<_> = (0..THREADS)
+            .map(|_| {
+                scope.spawn(|| {
+                    start.wait();
+                    cache.load(&0).1
+                })
+            })
+            .collect();
+        cache.0.load.started.wait();
+        for thread in threads {
+            assert_eq!(thread.join().unwrap(), 0);
+        }
+    });
+    assert_eq!(cache.0.load.loads.load(Ordering::Relaxed), 1);
+    assert_eq!(cache.get(&0).unwrap().1, 0);
+
+    // A panicking load doesn't leave its placeholder behind
+    let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| cache.load(&1)));
+    assert!(panicked.is_err());
+    assert_eq!(cache.len(), 1);
+    assert!(cache.get(&1).is_none());
+
+    let stats = cache.stats().unwrap();
+    assert_eq!(stats.loads, 1);
+    assert!(stats.coalesced_loads > 0);
+}
+
+#[test]
+fn load_time_cost() {
+    use crate::{
+        build::BuildCache,
+        evict::{cost::CostIntrusive, greedy_dual::EvictGreedyDual},
+        sync::SyncCacheBuilder,
+    };
+
+    struct Entry(u32);
+
+    impl crate::Value for Entry {
+        type Key = u32;
+
+        fn key(&self) -> &u32 {
+            &self.0
+        }
+    }
+
+    struct SlowZero;
+
+    impl Load<Entry> for SlowZero {
+        type Output = Entry;
+
+        fn load<K>(&self, key: &K) -> Entry
+        where
+            K: ?Sized + ToOwned<Owned = u32> + Hash + Eq,
+            u32: Borrow<K>,
+        {
+            let key = key.to_owned();
+            if key == 0 {
+                std::thread::sleep(Duration::from_millis(20));
+            }
+            Entry(key)
+        }
+    }
+
+    let cache = DedupLoadBlocking::new(
+        SlowZero,
+        BuildCache::<BlockingValue<Entry>>::default()
+            .layer(EvictGreedyDual::with_coster(CostIntrusive))
+            .build_custom(|layer| {
+                SyncCacheBuilder::new()
+                    .exact_shards(1)
+                    .capacity(2)
+                    .build_with_layer(layer)
+            }),
+    );
+
+    // The quick to load go first, however recently loaded
+    for key in 0..4 {
+        cache.load(&key);
+    }
+    assert!(cache.get(&0).is_some());
+    assert!(cache.get(&3).is_some());
+    assert_eq!(cache.len(), 2);
+}
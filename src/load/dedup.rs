@@ -5,7 +5,7 @@ use std::{
     marker::PhantomData,
     ops::Deref,
     pin::Pin,
-    sync::{Arc, OnceLock},
+    sync::Arc,
     task::{Context, Poll, Waker},
     time::{Duration, Instant},
 };
@@ -14,9 +14,11 @@ use futures::{future::select, pin_mut};
 use parking_lot::Mutex;
 use slab::Slab;
 
+use super::value::{LoadValue, ValueInner};
 use crate::{
-    expire::{Expire, ExpireAt},
-    load::AsyncLoad, Cache, Entry, OccupiedEntry, VacantEntry, Value as _,
+    load::AsyncLoad,
+    stats::{CacheStats, StatsCounter},
+    Cache, Entry, OccupiedEntry, VacantEntry, Value as _,
 };
 
 #[derive(Debug)]
@@ -24,7 +26,11 @@ pub struct DedupLoadIntrusive<L, C>(Arc<DedupInner<L, C>>);
 
 impl<L, C> DedupLoadIntrusive<L, C> {
     pub(crate) fn new(load: L, cache: C) -> Self {
-        Self(Arc::new(DedupInner { load, cache }))
+        Self(Arc::new(DedupInner {
+            load,
+            cache,
+            stats: StatsCounter::default(),
+        }))
     }
 }
 
@@ -38,67 +44,13 @@ impl<L, C> Clone for DedupLoadIntrusive<L, C> {
 struct DedupInner<L, C> {
     load: L,
     cache: C,
-}
-
-enum ValueInner<T>
-where
-    T: crate::Value,
-    T::Key: Sized,
-{
-    Waiting { key: T::Key, wakers: Wakers },
-    Complete(T),
+    stats: StatsCounter,
 }
 
 // XX add drop type to ensure woke
 type Wakers = Arc<Mutex<Option<Slab<Waker>>>>;
 
-pub struct Value<T>(ValueInner<T>)
-where
-    T: crate::Value,
-    T::Key: Sized;
-
-impl<T> crate::Value for Value<T>
-where
-    T: crate::Value,
-    T::Key: Sized,
-{
-    type Key = T::Key;
-
-    fn key(&self) -> &Self::Key {
-        match &self.0 {
-            ValueInner::Waiting { key, .. } => key,
-            ValueInner::Complete(v) => v.key(),
-        }
-    }
-}
-
-impl<T> Expire for Value<T>
-where
-    T: crate::Value + Expire,
-    T::Key: Sized,
-{
-    fn is_expired(&self) -> bool {
-        match &self.0 {
-            ValueInner::Waiting { .. } => false,
-            ValueInner::Complete(v) => v.is_expired(),
-        }
-    }
-}
-
-impl<T> ExpireAt for Value<T>
-where
-    T: crate::Value + ExpireAt,
-    T::Key: Sized,
-{
-    fn expire_at(&self) -> Instant {
-        static FAR_FUTURE: OnceLock<Instant> = OnceLock::new();
-        match &self.0 {
-            ValueInner::Waiting { .. } => *FAR_FUTURE
-                .get_or_init(|| Instant::now() + Duration::from_secs(100 * 365 * 24 * 60 * 60)),
-            ValueInner::Complete(v) => v.expire_at(),
-        }
-    }
-}
+pub type Value<T> = LoadValue<T, Wakers>;
 
 #[derive(Debug)]
 pub struct IntrusivePointer<P, T> {
@@ -131,7 +83,7 @@ where
 
     fn deref(&self) -> &Self::Target {
         match &self.inner.0 {
-            ValueInner::Complete(p) => &p,
+            ValueInner::Complete(p, _) => &p,
             _ => unreachable!(),
         }
     }
@@ -160,24 +112,27 @@ where
                 let pointer = o.into_pointer();
                 Ok(async move {
                     match &pointer.0 {
-                        ValueInner::Waiting { wakers, .. } => {
+                        ValueInner::Waiting { waiters: wakers, .. } => {
+                            this.stats.coalesced();
                             let wakers = Arc::clone(wakers);
                             WaitIntrusiveFut::new(self.clone(), pointer, wakers).await
                         }
-                        ValueInner::Complete(_) => IntrusivePointer::new(pointer),
+                        ValueInner::Complete(..) => IntrusivePointer::new(pointer),
                     }
                 })
             }
             Entry::Vacant(v) => {
                 let wakers = Wakers::default();
-                let pointer = v.insert(Value(ValueInner::Waiting {
-                    key: key.to_owned(),
-                    wakers: Arc::clone(&wakers),
-                }));
+                let pointer = v.insert(Value::waiting(key.to_owned(), Arc::clone(&wakers)));
 
                 let key = pointer.key().clone();
-                let load =
-                    async move { this.insert_loaded_value(this.load.load::<T::Key>(&key).await) };
+                let load = async move {
+                    let start = Instant::now();
+                    let value = this.load.load::<T::Key>(&key).await;
+                    let load_time = start.elapsed();
+                    this.stats.loaded(load_time);
+                    this.insert_loaded_value(value, load_time)
+                };
                 let replace = WaitIntrusiveFut::new(self.clone(), pointer, wakers);
 
                 Err(async move {
@@ -213,7 +168,7 @@ where
         self.0
             .cache
             .iter()
-            .filter(|p| matches!(p.0, ValueInner::Complete(_)))
+            .filter(|p| matches!(p.0, ValueInner::Complete(..)))
             .map(IntrusivePointer::new)
     }
 
@@ -231,11 +186,42 @@ where
         match self.0.cache.entry(key) {
             Entry::Occupied(o) => match &o.value().0 {
                 ValueInner::Waiting { .. } => Entry::Vacant(Vacant(Some(VacantInner::Waiting(o)))),
-                ValueInner::Complete(_) => Entry::Occupied(Occupied(o)),
+                ValueInner::Complete(..) => Entry::Occupied(Occupied(o)),
             },
             Entry::Vacant(v) => Entry::Vacant(Vacant(Some(VacantInner::Vacant(v)))),
         }
     }
+
+    // In flight loads are left alone so their waiters still get woken
+
+    fn retain(&self, mut f: impl FnMut(&T) -> bool) {
+        self.0.cache.retain(|v| match &v.0 {
+            ValueInner::Waiting { .. } => true,
+            ValueInner::Complete(v, _) => f(v),
+        })
+    }
+
+    fn drain(&self) -> impl Iterator<Item = Self::Pointer> {
+        self.iter().filter_map(|pointer| {
+            self.0
+                .cache
+                .remove_if(pointer.key(), |existing| {
+                    std::ptr::eq(existing, &*pointer.inner)
+                })
+                .map(IntrusivePointer::new)
+        })
+    }
+
+    fn clear(&self) {
+        self.retain(|_| false)
+    }
+
+    fn stats(&self) -> Option<CacheStats> {
+        self.0
+            .cache
+            .stats()
+            .map(|stats| stats + self.0.stats.snapshot())
+    }
 }
 
 struct Occupied<O: OccupiedEntry>(O);
@@ -251,7 +237,7 @@ where
 
     fn value(&self) -> &<Self::Pointer as Deref>::Target {
         match &self.0.value().0 {
-            ValueInner::Complete(v) => &v,
+            ValueInner::Complete(v, _) => &v,
             _ => unreachable!(),
         }
     }
@@ -265,7 +251,7 @@ where
     }
 
     fn replace(self, value: T) -> Self::Pointer {
-        IntrusivePointer::new(self.0.replace(Value(ValueInner::Complete(value))))
+        IntrusivePointer::new(self.0.replace(Value::complete(value, Duration::ZERO)))
     }
 
     fn remove(self) -> Self::Pointer {
@@ -296,11 +282,11 @@ where
     {
         match self.0.take().unwrap() {
             VacantInner::Waiting(occupied) => {
-                let ValueInner::Waiting { wakers, .. } = &occupied.value().0 else {
+                let ValueInner::Waiting { waiters: wakers, .. } = &occupied.value().0 else {
                     unreachable!()
                 };
                 let wakers = Arc::clone(wakers);
-                let pointer = occupied.replace(Value(ValueInner::Complete(value)));
+                let pointer = occupied.replace(Value::complete(value, Duration::ZERO));
 
                 if let Some(mut wakers) = wakers.lock().take() {
                     wakers.drain().for_each(Waker::wake);
@@ -308,13 +294,13 @@ where
 
                 IntrusivePointer::new(pointer)
             }
-            VacantInner::Vacant(v) => IntrusivePointer::new(v.insert(Value(ValueInner::Complete(value)))),
+            VacantInner::Vacant(v) => IntrusivePointer::new(v.insert(Value::complete(value, Duration::ZERO))),
         }
     }
 }
 
 impl<L, C> DedupInner<L, C> {
-    fn insert_loaded_value<T>(&self, value: T) -> IntrusivePointer<C::Pointer, T>
+    fn insert_loaded_value<T>(&self, value: T, load_time: Duration) -> IntrusivePointer<C::Pointer, T>
     where
         T: crate::Value,
         T::Key: Sized,
@@ -322,19 +308,19 @@ impl<L, C> DedupInner<L, C> {
     {
         match self.cache.entry::<T::Key>(value.key()) {
             Entry::Occupied(occupied) => match &occupied.value().0 {
-                ValueInner::Waiting { wakers, .. } => {
+                ValueInner::Waiting { waiters: wakers, .. } => {
                     let wakers = Arc::clone(wakers);
-                    let pointer = IntrusivePointer::new(occupied.replace(Value(ValueInner::Complete(value))));
+                    let pointer = IntrusivePointer::new(occupied.replace(Value::complete(value, load_time)));
                     if let Some(mut wakers) = wakers.lock().take() {
                         wakers.drain().for_each(Waker::wake);
                     }
                     pointer
                 }
-                ValueInner::Complete(_) => {
-                    IntrusivePointer::new(occupied.replace(Value(ValueInner::Complete(value))))
+                ValueInner::Complete(..) => {
+                    IntrusivePointer::new(occupied.replace(Value::complete(value, load_time)))
                 }
             },
-            Entry::Vacant(v) => IntrusivePointer::new(v.insert(Value(ValueInner::Complete(value)))),
+            Entry::Vacant(v) => IntrusivePointer::new(v.insert(Value::complete(value, load_time))),
         }
     }
 }
@@ -408,7 +394,7 @@ where
                 Entry::Occupied(occupied) => {
                     let pointer = occupied.into_pointer(); // drop occupied lock
                     match &pointer.0 {
-                        ValueInner::Waiting { wakers, .. } => {
+                        ValueInner::Waiting { waiters: wakers, .. } => {
                             let Some(waker_key) = this.waker_key else {
                                 this.wakers = Arc::clone(wakers);
                                 continue;
@@ -427,7 +413,7 @@ where
                                 this.wakers = Arc::clone(&wakers);
                             }
                         }
-                        ValueInner::Complete(_) => {
+                        ValueInner::Complete(..) => {
                             this.waker_key = None;
                             return Poll::Ready(IntrusivePointer::new(pointer));
                         }
@@ -435,10 +421,7 @@ where
                 }
                 Entry::Vacant(v) => {
                     this.waker_key = None; // XX: do before key().clone() can panic
-                    let pointer = v.insert(Value(ValueInner::Waiting {
-                        key: this.pointer.key().clone(),
-                        wakers: Default::default(),
-                    }));
+                    let pointer = v.insert(Value::waiting(this.pointer.key().clone(), Default::default()));
 
                     let dedup = this.dedup.clone();
                     // XX: actually need to just do the insert, not re-call load() because that would hang forever
@@ -0,0 +1,99 @@
+use std::{
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    evict::cost::Cost,
+    expire::{Expire, ExpireAt},
+};
+
+pub(super) enum ValueInner<T, W>
+where
+    T: crate::Value,
+    T::Key: Sized,
+{
+    Waiting { key: T::Key, waiters: W },
+    // With how long it took to load, if it was loaded
+    Complete(T, Duration),
+}
+
+/// What a deduplicating loader keeps in its cache: either a value or a placeholder for one
+/// that's being loaded, which `W` wakes whoever's waiting on once it's done.
+pub struct LoadValue<T, W>(pub(super) ValueInner<T, W>)
+where
+    T: crate::Value,
+    T::Key: Sized;
+
+impl<T, W> LoadValue<T, W>
+where
+    T: crate::Value,
+    T::Key: Sized,
+{
+    pub(super) fn waiting(key: T::Key, waiters: W) -> Self {
+        Self(ValueInner::Waiting { key, waiters })
+    }
+
+    pub(super) fn complete(value: T, load_time: Duration) -> Self {
+        Self(ValueInner::Complete(value, load_time))
+    }
+}
+
+impl<T, W> crate::Value for LoadValue<T, W>
+where
+    T: crate::Value,
+    T::Key: Sized,
+{
+    type Key = T::Key;
+
+    fn key(&self) -> &Self::Key {
+        match &self.0 {
+            ValueInner::Waiting { key, .. } => key,
+            ValueInner::Complete(v, _) => v.key(),
+        }
+    }
+}
+
+impl<T, W> Expire for LoadValue<T, W>
+where
+    T: crate::Value + Expire,
+    T::Key: Sized,
+{
+    fn is_expired(&self) -> bool {
+        match &self.0 {
+            ValueInner::Waiting { .. } => false,
+            ValueInner::Complete(v, _) => v.is_expired(),
+        }
+    }
+}
+
+impl<T, W> ExpireAt for LoadValue<T, W>
+where
+    T: crate::Value + ExpireAt,
+    T::Key: Sized,
+{
+    fn expire_at(&self) -> Instant {
+        static FAR_FUTURE: OnceLock<Instant> = OnceLock::new();
+        match &self.0 {
+            ValueInner::Waiting { .. } => *FAR_FUTURE
+                .get_or_init(|| Instant::now() + Duration::from_secs(100 * 365 * 24 * 60 * 60)),
+            ValueInner::Complete(v, _) => v.expire_at(),
+        }
+    }
+}
+
+/// Seconds the value took to load, so that [`CostIntrusive`](crate::evict::cost::CostIntrusive)
+/// has eviction hold on to whatever's slowest to get back. Values inserted directly and loads
+/// still in flight cost nothing.
+impl<T, W> Cost for LoadValue<T, W>
+where
+    T: crate::Value,
+    T::Key: Sized,
+{
+    fn cost(&self) -> f64 {
+        match &self.0 {
+            ValueInner::Waiting { .. } => 0.0,
+            ValueInner::Complete(_, load_time) => load_time.as_secs_f64(),
+        }
+    }
+}
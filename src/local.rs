@@ -1,305 +1,624 @@
-use std::borrow::Borrow;
-use std::{cell::RefCell, hash::BuildHasher, marker::PhantomData, ops::Deref, rc::Rc};
+use std::{
+    borrow::Borrow,
+    cell::{RefCell, RefMut},
+    hash::{BuildHasher, Hash},
+    mem::ManuallyDrop,
+    ops::{Deref, DerefMut},
+    rc::Rc,
+};
 
-use hashbrown::raw::{Bucket, InsertSlot};
-use hashbrown::{hash_map::DefaultHashBuilder, raw::RawTable};
+use hashbrown::{
+    hash_map::DefaultHashBuilder,
+    raw::{Bucket, InsertSlot, RawTable},
+};
 use smallvec::SmallVec;
 use stable_deref_trait::{CloneStableDeref, StableDeref};
-use std::hash::Hash;
 
 use crate::{
-    build::BuildCache,
-    // evict::{Evict, EvictNone},
+    layer::{self, Layer, ReadLock, ReadResult, Resolve, Shard as ShardLayer},
+    listener::{RemovalCause, RemovalListener},
+    stats::{CacheStats, StatsCounter},
+    Cache,
 };
 
-// #[derive(Debug, Clone)]
-// pub struct LocalCacheBuilder<E = EvictNone, Ev = (), Eq = (), S = DefaultHashBuilder> {
-//     eviction: E,
-//     hash_builder: S,
-//     capacity: Option<usize>,
-//     _marker: PhantomData<(Ev, Eq)>,
-// }
-
-// impl<E: Default, Ev, Eq, S: Default> Default for LocalCacheBuilder<E, Ev, Eq, S> {
-//     fn default() -> Self {
-//         Self {
-//             eviction: Default::default(),
-//             hash_builder: Default::default(),
-//             capacity: None,
-//             _marker: PhantomData,
-//         }
-//     }
-// }
-
-// // impl<T, E, Ev, Eq, S> BuildCache<T> for LocalCacheBuilder<E, Ev, Eq, S>
-// // where
-// //     T: crate::Value + 'static,
-// //     E: Evict<Pointer<T, Ev>, Value = Ev, Queue = Eq>,
-// //     S: BuildHasher,
-// // {
-// //     type Cache = LocalCache<T, E, Ev, Eq, S>;
-
-// //     fn build(mut self) -> Self::Cache {
-// //         let capacity = self.capacity.unwrap_or(16);
-// //         let queue = self.eviction.new_queue(capacity).into();
-// //         let table = RawTable::with_capacity(capacity).into();
-
-// //         LocalCache {
-// //             table,
-// //             queue,
-// //             hash_builder: self.hash_builder,
-// //             eviction: self.eviction,
-// //         }
-// //     }
-// // }
-
-// pub struct LocalCache<T, E, Ev, Eq, S> {
-//     table: RefCell<RawTable<Pointer<T, Ev>>>,
-//     queue: RefCell<Eq>,
-//     hash_builder: S,
-//     eviction: E,
-// }
-
-// struct Value<T, Ev> {
-//     inner: T,
-//     eviction: Ev,
-// }
-
-// pub struct Pointer<T, Ev>(Rc<Value<T, Ev>>);
-
-// impl<T, E> Clone for Pointer<T, E> {
-//     fn clone(&self) -> Self {
-//         Self(self.0.clone())
-//     }
-// }
-
-// impl<T, E> Deref for Pointer<T, E> {
-//     type Target = T;
-
-//     fn deref(&self) -> &Self::Target {
-//         &self.0.inner
-//     }
-// }
-
-// // XX: just a wrapper around Rc<> that does impl Stable/Clone
-// unsafe impl<T, E> StableDeref for Pointer<T, E> {}
-// unsafe impl<T, E> CloneStableDeref for Pointer<T, E> {}
-
-// fn deref_eviction<T, E>(pointer: &Pointer<T, E>) -> &E {
-//     &pointer.0.eviction
-// }
-
-// impl<T, E, Ev, Eq, S> crate::Cache<T> for LocalCache<T, E, Ev, Eq, S>
-// where
-//     T: crate::Value + 'static,
-//     T::Key: Hash + std::cmp::Eq,
-//     E: Evict<Pointer<T, Ev>, Value = Ev, Queue = Eq>,
-//     S: BuildHasher,
-// {
-//     type Pointer = Pointer<T, Ev>;
-
-//     fn len(&self) -> usize {
-//         self.table.borrow().len()
-//     }
-
-//     fn iter(&self) -> impl Iterator<Item = Self::Pointer> {
-//         let pointers: Vec<_> = unsafe {
-//             self.table.borrow().iter().map(|b| b.as_ref().clone()).collect()
-//         };
-//         pointers.into_iter()
-//     }
-
-//     fn entry<'c, 'k, K>(
-//         &'c self,
-//         key: &'k K,
-//     ) -> crate::Entry<
-//         impl crate::OccupiedEntry<Pointer = Self::Pointer> + 'c,
-//         impl crate::VacantEntry<Pointer = Self::Pointer> + 'c,
-//     >
-//     where
-//         T::Key: Borrow<K>,
-//         K: ?Sized + std::cmp::Eq + Hash,
-//     {
-//         let hash = self.hash_builder.hash_one(key);
-//         let found = self.table.borrow_mut().find_or_find_insert_slot(
-//             hash,
-//             |p| p.0.inner.key().borrow() == key,
-//             |p| self.hash_builder.hash_one(p.key()),
-//         );
-
-//         match found {
-//             Ok(bucket) => crate::Entry::Occupied(OccupiedEntry(Some(OccupiedEntryInner {
-//                 cache: self,
-//                 bucket,
-//             }))),
-//             Err(slot) => crate::Entry::Vacant(VacantEntry {
-//                 cache: self,
-//                 slot,
-//                 hash,
-//             }),
-//         }
-//     }
-// }
-
-// struct OccupiedEntry<'a, T: crate::Value, E, Ev, Eq, S>(
-//     Option<OccupiedEntryInner<'a, T, E, Ev, Eq, S>>,
-// )
-// where
-//     T: crate::Value + 'static,
-//     E: Evict<Pointer<T, Ev>, Value = Ev, Queue = Eq>;
-
-// struct OccupiedEntryInner<'a, T: crate::Value, E, Ev, Eq, S> {
-//     cache: &'a LocalCache<T, E, Ev, Eq, S>,
-//     bucket: Bucket<Pointer<T, Ev>>,
-// }
-
-// impl<T, E, Ev, Eq, S> Drop for OccupiedEntry<'_, T, E, Ev, Eq, S>
-// where
-//     T: crate::Value + 'static,
-//     E: Evict<Pointer<T, Ev>, Value = Ev, Queue = Eq>,
-// {
-//     fn drop(&mut self) {
-//         if let Some(inner) = self.0.take() {
-//             // XX Safety
-//             let pointer = unsafe { inner.bucket.as_ref() };
-//             inner.cache.eviction.touch(
-//                 &mut *inner.cache.queue.borrow_mut(),
-//                 &pointer,
-//                 deref_eviction,
-//             );
-//         }
-//     }
-// }
-
-// impl<T: crate::Value, E, Ev, Es, S> OccupiedEntryInner<'_, T, E, Ev, Es, S> {
-//     fn pointer(&self) -> &Pointer<T, Ev> {
-//         // XX Safety
-//         unsafe { self.bucket.as_ref() }
-//     }
-// }
-
-// impl<T, E, Ev, Es, S> crate::OccupiedEntry for OccupiedEntry<'_, T, E, Ev, Es, S>
-// where
-//     T: crate::Value + 'static,
-//     E: Evict<Pointer<T, Ev>, Value = Ev, Queue = Es>,
-//     S: BuildHasher,
-// {
-//     type Pointer = Pointer<T, Ev>;
-
-//     fn pointer(&self) -> Pointer<T, Ev> {
-//         self.0.as_ref().unwrap().pointer().clone()
-//     }
-
-//     fn value(&self) -> &T {
-//         &self.0.as_ref().unwrap().pointer()
-//     }
-
-//     fn replace(mut self, value: T) -> Pointer<T, Ev> {
-//         let inner = self.0.take().unwrap();
-
-//         // XX Safety
-//         let pointer = unsafe { inner.bucket.as_mut() };
-
-//         debug_assert!(value.key() == pointer.key());
-
-//         let (replace, evict) = {
-//             let mut queue = inner.cache.queue.borrow_mut();
-//             inner
-//                 .cache
-//                 .eviction
-//                 .remove(&mut queue, &pointer, deref_eviction);
-//             let (replace, evict) = inner.cache.eviction.insert(
-//                 &mut queue,
-//                 |eviction| {
-//                     Pointer(Rc::new(Value {
-//                         inner: value,
-//                         eviction,
-//                     }))
-//                 },
-//                 deref_eviction,
-//             );
-//             let evict = evict.collect::<SmallVec<[_; 8]>>();
-//             (replace, evict)
-//         };
-//         *pointer = replace.clone();
-
-//         for evicted in evict {
-//             let key = evicted.key();
-//             let hash = inner.cache.hash_builder.hash_one(key);
-//             inner
-//                 .cache
-//                 .table
-//                 .borrow_mut()
-//                 .remove_entry(hash, |p| Rc::ptr_eq(&p.0, &evicted.0));
-//         }
-
-//         replace
-//     }
-
-//     fn remove(mut self) -> Pointer<T, Ev> {
-//         let inner = self.0.take().unwrap();
-
-//         // XX Safety
-//         let (removed, _slot) = unsafe { inner.cache.table.borrow_mut().remove(inner.bucket) };
-//         inner.cache.eviction.remove(
-//             &mut inner.cache.queue.borrow_mut(),
-//             &removed,
-//             deref_eviction,
-//         );
-//         removed
-//     }
-// }
-
-// struct VacantEntry<'a, T, E, Ev, Eq, S> {
-//     cache: &'a LocalCache<T, E, Ev, Eq, S>,
-//     slot: InsertSlot,
-//     hash: u64,
-// }
-
-// impl<T, E, Ev, Eq, S> crate::VacantEntry for VacantEntry<'_, T, E, Ev, Eq, S>
-// where
-//     T: crate::Value + 'static,
-//     E: Evict<Pointer<T, Ev>, Value = Ev, Queue = Eq>,
-//     S: BuildHasher,
-// {
-//     type Pointer = Pointer<T, Ev>;
-
-//     fn insert(self, value: T) -> Pointer<T, Ev> {
-//         debug_assert_eq!(self.hash, self.cache.hash_builder.hash_one(value.key()));
-
-//         let (insert, evict) = {
-//             let mut queue = self.cache.queue.borrow_mut();
-//             let (insert, evict) = self.cache.eviction.insert(
-//                 &mut queue,
-//                 |eviction| {
-//                     Pointer(Rc::new(Value {
-//                         inner: value,
-//                         eviction,
-//                     }))
-//                 },
-//                 deref_eviction,
-//             );
-//             let evict = evict.collect::<SmallVec<[_; 8]>>();
-//             (insert, evict)
-//         };
-
-//         for evicted in evict {
-//             let key = evicted.key();
-//             let hash = self.cache.hash_builder.hash_one(key);
-//             self.cache
-//                 .table
-//                 .borrow_mut()
-//                 .remove_entry(hash, |p| Rc::ptr_eq(&p.0, &evicted.0));
-//         }
-
-//         // XX: Safety
-//         unsafe {
-//             self.cache
-//                 .table
-//                 .borrow_mut()
-//                 .insert_in_slot(self.hash, self.slot, insert.clone());
-//         }
-
-//         insert
-//     }
-// }
+#[derive(Debug, Clone)]
+pub struct LocalCacheBuilder<S = DefaultHashBuilder, N = ()> {
+    hash_builder: S,
+    capacity: Option<usize>,
+    listener: Option<N>,
+    stats: bool,
+}
+
+impl<S: Default, N> Default for LocalCacheBuilder<S, N> {
+    fn default() -> Self {
+        Self {
+            hash_builder: Default::default(),
+            capacity: None,
+            listener: None,
+            stats: false,
+        }
+    }
+}
+
+impl LocalCacheBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<S, N> LocalCacheBuilder<S, N> {
+    pub fn hasher<S2>(self, hasher: S2) -> LocalCacheBuilder<S2, N> {
+        LocalCacheBuilder {
+            hash_builder: hasher,
+            capacity: self.capacity,
+            listener: self.listener,
+            stats: self.stats,
+        }
+    }
+
+    pub fn removal_listener<N2>(self, listener: N2) -> LocalCacheBuilder<S, N2> {
+        LocalCacheBuilder {
+            hash_builder: self.hash_builder,
+            capacity: self.capacity,
+            listener: Some(listener),
+            stats: self.stats,
+        }
+    }
+
+    pub fn capacity(self, capacity: usize) -> Self {
+        Self {
+            capacity: Some(capacity),
+            ..self
+        }
+    }
+
+    pub fn stats(self) -> Self {
+        Self {
+            stats: true,
+            ..self
+        }
+    }
+
+    pub fn build_with_layer<T, L, Lv, Ls>(self, layer: L) -> LocalCache<T, Lv, Ls, S>
+    where
+        T: crate::Value,
+        L: Layer<Pointer<T, Lv>, Value = Lv, Shard = Ls>,
+        N: RemovalListener<Pointer<T, Lv>> + 'static,
+    {
+        let capacity = self.capacity.unwrap_or(16);
+
+        LocalCache {
+            inner: RefCell::new(Inner {
+                values: RawTable::with_capacity(layer.expected_len(capacity)),
+                layer: layer.new_shard(capacity),
+            }),
+            hash_builder: self.hash_builder,
+            listener: self
+                .listener
+                .map(|l| Box::new(l) as Box<dyn RemovalListener<_>>),
+            stats: self.stats.then(StatsCounter::default),
+        }
+    }
+}
+
+/// Single threaded cache. Entries hold the cache borrowed, so calling back into the cache while
+/// holding one panics, the same way it would deadlock a [`SyncCache`](crate::sync::SyncCache).
+pub struct LocalCache<T, Lv, Ls, S = DefaultHashBuilder> {
+    inner: RefCell<Inner<T, Lv, Ls>>,
+    hash_builder: S,
+    listener: Option<Box<dyn RemovalListener<Pointer<T, Lv>>>>,
+    stats: Option<StatsCounter>,
+}
+
+struct Inner<T, Lv, Ls> {
+    values: RawTable<Pointer<T, Lv>>,
+    layer: Ls,
+}
+
+struct Value<T, L> {
+    value: T,
+    layer: L,
+}
+
+pub struct Pointer<T, L>(Rc<Value<T, L>>);
+
+impl<T, L> Clone for Pointer<T, L> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T, L> Deref for Pointer<T, L> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0.value
+    }
+}
+
+// XX: just a wrapper around Rc<> that does impl Stable/Clone
+unsafe impl<T, L> StableDeref for Pointer<T, L> {}
+unsafe impl<T, L> CloneStableDeref for Pointer<T, L> {}
+
+struct ResolveLayer;
+
+impl<T, L> Resolve<Pointer<T, L>, L> for ResolveLayer {
+    fn resolve(pointer: &Pointer<T, L>) -> &L {
+        &pointer.0.layer
+    }
+}
+
+impl<T, Lv, Ls, S> Cache<T> for LocalCache<T, Lv, Ls, S>
+where
+    T: crate::Value + 'static,
+    Ls: ShardLayer<Pointer<T, Lv>, Value = Lv>,
+    S: BuildHasher,
+{
+    type Pointer = Pointer<T, Lv>;
+
+    fn len(&self) -> usize {
+        self.inner.borrow().values.len()
+    }
+
+    fn iter(&self) -> impl Iterator<Item = Self::Pointer> {
+        let pointers: Vec<_> = match Ls::ITER_READ_LOCK {
+            // XX safety
+            ReadLock::None => unsafe {
+                let inner = self.inner.borrow();
+                inner.values.iter().map(|b| b.as_ref().clone()).collect()
+            },
+            ReadLock::Ref | ReadLock::Mut => {
+                let mut pointers = Vec::new();
+                let mut inner = self.borrow_mut();
+                let (Inner { values, layer }, removed) = inner.split();
+                // XX safety: buckets are only erased after the iterator has moved past them
+                unsafe {
+                    for bucket in values.iter() {
+                        match layer.iter_read_mut::<ResolveLayer>(bucket.as_ref()) {
+                            ReadResult::Retain => pointers.push(bucket.as_ref().clone()),
+                            ReadResult::Remove => {
+                                layer.remove::<ResolveLayer>(bucket.as_ref());
+                                let (pointer, _slot) = values.remove(bucket);
+                                self.record_removal(removed, pointer, RemovalCause::Expired);
+                            }
+                        }
+                    }
+                }
+                pointers
+            }
+        };
+        pointers.into_iter()
+    }
+
+    fn get<K>(&self, key: &K) -> Option<Self::Pointer>
+    where
+        T::Key: Borrow<K>,
+        K: ?Sized + Hash + Eq,
+    {
+        match Ls::READ_LOCK {
+            ReadLock::None => {
+                let hash = self.hash_builder.hash_one(key);
+                let found = self
+                    .inner
+                    .borrow()
+                    .values
+                    .get(hash, |p| p.0.value.key().borrow() == key)
+                    .cloned();
+                self.record_read(found.is_some());
+                if found.is_none() && Ls::OBSERVES_MISSES {
+                    self.borrow_mut().layer.miss::<ResolveLayer>(key);
+                }
+                found
+            }
+            // Nothing to gain from a shared borrow when no one else can be reading
+            ReadLock::Ref | ReadLock::Mut => match self.lookup(key, true) {
+                crate::Entry::Occupied(o) => Some(crate::OccupiedEntry::into_pointer(o)),
+                crate::Entry::Vacant(_) => None,
+            },
+        }
+    }
+
+    fn entry<'c, K>(
+        &'c self,
+        key: &K,
+    ) -> crate::Entry<
+        impl crate::OccupiedEntry<Pointer = Self::Pointer> + 'c,
+        impl crate::VacantEntry<Pointer = Self::Pointer> + 'c,
+    >
+    where
+        T::Key: Borrow<K>,
+        K: ?Sized + Hash + Eq,
+    {
+        self.lookup(key, true)
+    }
+
+    fn insert(&self, value: T) -> Self::Pointer {
+        match self.lookup(value.key(), false) {
+            crate::Entry::Occupied(o) => crate::OccupiedEntry::replace(o, value),
+            crate::Entry::Vacant(v) => crate::VacantEntry::insert(v, value),
+        }
+    }
+
+    fn remove_if<K>(&self, key: &K, f: impl FnOnce(&T) -> bool) -> Option<Self::Pointer>
+    where
+        T::Key: Borrow<K>,
+        K: ?Sized + Hash + Eq,
+    {
+        match self.lookup(key, false) {
+            crate::Entry::Occupied(o) if f(crate::OccupiedEntry::value(&o)) => {
+                Some(crate::OccupiedEntry::remove(o))
+            }
+            _ => None,
+        }
+    }
+
+    fn retain(&self, mut f: impl FnMut(&T) -> bool) {
+        self.retain_pointers(RemovalCause::Explicit, |p| f(p), drop);
+    }
+
+    fn drain(&self) -> impl Iterator<Item = Self::Pointer> {
+        let mut drained = Vec::new();
+        self.retain_pointers(RemovalCause::Cleared, |_| false, |p| drained.push(p));
+        drained.into_iter()
+    }
+
+    fn clear(&self) {
+        self.retain_pointers(RemovalCause::Cleared, |_| false, drop);
+    }
+
+    fn stats(&self) -> Option<CacheStats> {
+        self.stats.as_ref().map(StatsCounter::snapshot)
+    }
+}
+
+impl<T, Lv, Ls, S> LocalCache<T, Lv, Ls, S>
+where
+    T: crate::Value + 'static,
+    Ls: ShardLayer<Pointer<T, Lv>, Value = Lv>,
+    S: BuildHasher,
+{
+    fn lookup<K>(&self, key: &K, record_stats: bool) -> Entry<'_, T, Lv, Ls, S>
+    where
+        T::Key: Borrow<K>,
+        K: ?Sized + Eq + Hash,
+    {
+        let hash = self.hash_builder.hash_one(key);
+
+        let mut inner = self.borrow_mut();
+        let found = inner.values.find_or_find_insert_slot(
+            hash,
+            |p| p.0.value.key().borrow() == key,
+            |p| self.hash_builder.hash_one(p.key()),
+        );
+        match found {
+            Ok(bucket) => {
+                // XX safety
+                let pointer = unsafe { bucket.as_ref() };
+                let read = inner.layer.read_mut::<ResolveLayer>(pointer);
+                if record_stats {
+                    self.record_read(read == ReadResult::Retain);
+                }
+                match read {
+                    ReadResult::Retain => crate::Entry::Occupied(OccupiedEntry {
+                        cache: self,
+                        inner,
+                        bucket,
+                    }),
+                    ReadResult::Remove => {
+                        inner.layer.remove::<ResolveLayer>(pointer);
+                        // XX safety
+                        let (removed, slot) = unsafe { inner.values.remove(bucket) };
+                        inner.removed(removed, RemovalCause::Expired);
+                        if record_stats {
+                            inner.layer.miss::<ResolveLayer>(key);
+                        }
+                        crate::Entry::Vacant(VacantEntry {
+                            cache: self,
+                            inner,
+                            slot,
+                            hash,
+                        })
+                    }
+                }
+            }
+            Err(slot) => {
+                if record_stats {
+                    self.record_read(false);
+                    inner.layer.miss::<ResolveLayer>(key);
+                }
+                crate::Entry::Vacant(VacantEntry {
+                    cache: self,
+                    inner,
+                    slot,
+                    hash,
+                })
+            }
+        }
+    }
+
+    fn retain_pointers(
+        &self,
+        cause: RemovalCause,
+        mut f: impl FnMut(&Pointer<T, Lv>) -> bool,
+        mut on_removed: impl FnMut(Pointer<T, Lv>),
+    ) {
+        let mut inner = self.borrow_mut();
+        let (Inner { values, layer }, removed) = inner.split();
+        // XX safety: buckets are only erased after the iterator has moved past them
+        unsafe {
+            for bucket in values.iter() {
+                if !f(bucket.as_ref()) {
+                    layer.remove::<ResolveLayer>(bucket.as_ref());
+                    let (pointer, _slot) = values.remove(bucket);
+                    self.record_removal(removed, pointer.clone(), cause);
+                    on_removed(pointer);
+                }
+            }
+        }
+    }
+}
+
+impl<T, Lv, Ls, S> LocalCache<T, Lv, Ls, S> {
+    fn borrow_mut(&self) -> InnerGuard<'_, T, Lv, Ls, S> {
+        InnerGuard {
+            cache: self,
+            inner: ManuallyDrop::new(self.inner.borrow_mut()),
+            removed: SmallVec::new(),
+        }
+    }
+
+    fn record_read(&self, hit: bool) {
+        if let Some(stats) = &self.stats {
+            stats.read(hit);
+        }
+    }
+
+    fn record_removal(
+        &self,
+        removed: &mut Removed<T, Lv>,
+        pointer: Pointer<T, Lv>,
+        cause: RemovalCause,
+    ) {
+        if let Some(stats) = &self.stats {
+            stats.removed(cause);
+        }
+        if self.listener.is_some() {
+            removed.push((pointer, cause));
+        }
+    }
+}
+
+type Removed<T, Lv> = SmallVec<[(Pointer<T, Lv>, RemovalCause); 2]>;
+
+/// Borrow of the cache that notifies the removal listener of anything removed while it was
+/// held, but only once it's been released so the listener can use the cache.
+struct InnerGuard<'a, T, Lv, Ls, S> {
+    cache: &'a LocalCache<T, Lv, Ls, S>,
+    inner: ManuallyDrop<RefMut<'a, Inner<T, Lv, Ls>>>,
+    removed: Removed<T, Lv>,
+}
+
+impl<T, Lv, Ls, S> InnerGuard<'_, T, Lv, Ls, S> {
+    fn removed(&mut self, pointer: Pointer<T, Lv>, cause: RemovalCause) {
+        self.cache.record_removal(&mut self.removed, pointer, cause);
+    }
+
+    fn split(&mut self) -> (&mut Inner<T, Lv, Ls>, &mut Removed<T, Lv>) {
+        (&mut self.inner, &mut self.removed)
+    }
+}
+
+impl<T, Lv, Ls, S> Deref for InnerGuard<'_, T, Lv, Ls, S> {
+    type Target = Inner<T, Lv, Ls>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl<T, Lv, Ls, S> DerefMut for InnerGuard<'_, T, Lv, Ls, S> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
+
+impl<T, Lv, Ls, S> Drop for InnerGuard<'_, T, Lv, Ls, S> {
+    fn drop(&mut self) {
+        // Safety: never touched again
+        unsafe { ManuallyDrop::drop(&mut self.inner) };
+
+        if let Some(listener) = &self.cache.listener {
+            for (pointer, cause) in self.removed.drain(..) {
+                listener.on_removal(pointer, cause);
+            }
+        }
+    }
+}
+
+type Entry<'a, T, Lv, Ls, S> =
+    crate::Entry<OccupiedEntry<'a, T, Lv, Ls, S>, VacantEntry<'a, T, Lv, Ls, S>>;
+
+struct OccupiedEntry<'a, T, Lv, Ls, S> {
+    cache: &'a LocalCache<T, Lv, Ls, S>,
+    inner: InnerGuard<'a, T, Lv, Ls, S>,
+    bucket: Bucket<Pointer<T, Lv>>,
+}
+
+impl<T, Lv, Ls, S> OccupiedEntry<'_, T, Lv, Ls, S> {
+    fn pointer_ref(&self) -> &Pointer<T, Lv> {
+        // XX Safety
+        unsafe { self.bucket.as_ref() }
+    }
+}
+
+impl<T, Lv, Ls, S> crate::OccupiedEntry for OccupiedEntry<'_, T, Lv, Ls, S>
+where
+    T: crate::Value + 'static,
+    Ls: ShardLayer<Pointer<T, Lv>, Value = Lv>,
+    S: BuildHasher,
+{
+    type Pointer = Pointer<T, Lv>;
+
+    fn pointer(&self) -> Pointer<T, Lv> {
+        self.pointer_ref().clone()
+    }
+
+    fn value(&self) -> &T {
+        self.pointer_ref()
+    }
+
+    fn replace(mut self, value: T) -> Pointer<T, Lv> {
+        // XX Safety
+        let pointer = unsafe { self.bucket.as_mut() };
+        debug_assert!(value.key() == pointer.key());
+
+        self.inner.layer.remove::<ResolveLayer>(pointer);
+        let (inner, removed) = self.inner.split();
+        let replace = inner.layer.write::<ResolveLayer>(Write {
+            cache: self.cache,
+            values: &mut inner.values,
+            removed,
+            target: value,
+        });
+        let replaced = std::mem::replace(pointer, replace.clone());
+        self.inner.removed(replaced, RemovalCause::Replaced);
+
+        replace
+    }
+
+    fn remove(mut self) -> Pointer<T, Lv> {
+        // XX Safety
+        let (removed, _slot) = unsafe { self.inner.values.remove(self.bucket) };
+        self.inner.layer.remove::<ResolveLayer>(&removed);
+        self.inner.removed(removed.clone(), RemovalCause::Explicit);
+        removed
+    }
+}
+
+struct VacantEntry<'a, T, Lv, Ls, S> {
+    cache: &'a LocalCache<T, Lv, Ls, S>,
+    inner: InnerGuard<'a, T, Lv, Ls, S>,
+    slot: InsertSlot,
+    hash: u64,
+}
+
+impl<T, Lv, Ls, S> crate::VacantEntry for VacantEntry<'_, T, Lv, Ls, S>
+where
+    T: crate::Value + 'static,
+    Ls: ShardLayer<Pointer<T, Lv>, Value = Lv>,
+    S: BuildHasher,
+{
+    type Pointer = Pointer<T, Lv>;
+
+    fn insert(mut self, value: T) -> Pointer<T, Lv> {
+        debug_assert_eq!(self.hash, self.cache.hash_builder.hash_one(value.key()));
+
+        let (inner, removed) = self.inner.split();
+        let insert = inner.layer.write::<ResolveLayer>(Write {
+            cache: self.cache,
+            values: &mut inner.values,
+            removed,
+            target: value,
+        });
+
+        // XX: Safety
+        unsafe {
+            self.inner
+                .values
+                .insert_in_slot(self.hash, self.slot, insert.clone());
+        }
+
+        insert
+    }
+}
+
+struct Write<'a, T, Lv, Ls, S> {
+    cache: &'a LocalCache<T, Lv, Ls, S>,
+    values: &'a mut RawTable<Pointer<T, Lv>>,
+    removed: &'a mut Removed<T, Lv>,
+    target: T,
+}
+
+impl<T: crate::Value, Lv, Ls, S: BuildHasher> Write<'_, T, Lv, Ls, S> {
+    fn unlink(&mut self, pointer: &Pointer<T, Lv>, cause: RemovalCause) {
+        let hash = self.cache.hash_builder.hash_one(pointer.key());
+        let removed = self
+            .values
+            .remove_entry(hash, |p| Rc::ptr_eq(&p.0, &pointer.0))
+            .expect("layer shard and map out of sync");
+        self.cache.record_removal(self.removed, removed, cause);
+    }
+}
+
+impl<T, Lv, Ls, S> layer::Write<Pointer<T, Lv>, Lv> for Write<'_, T, Lv, Ls, S>
+where
+    T: crate::Value,
+    S: BuildHasher,
+{
+    fn target(&self) -> &T {
+        &self.target
+    }
+
+    fn remove(&mut self, pointer: &Pointer<T, Lv>) {
+        self.unlink(pointer, RemovalCause::Evicted);
+    }
+
+    fn expire(&mut self, pointer: &Pointer<T, Lv>) {
+        self.unlink(pointer, RemovalCause::Expired);
+    }
+
+    fn write(self, layer: Lv) -> Pointer<T, Lv> {
+        Pointer(Rc::new(Value {
+            value: self.target,
+            layer,
+        }))
+    }
+}
+
+#[test]
+fn local() {
+    use std::cell::Cell;
+
+    use crate::{build::BuildCache, evict::read::EvictLeastRecentlyRead};
+
+    struct Entry(u32);
+
+    impl crate::Value for Entry {
+        type Key = u32;
+
+        fn key(&self) -> &u32 {
+            &self.0
+        }
+    }
+
+    let evicted = Rc::new(Cell::new(0));
+    let cache = BuildCache::<Entry>::default()
+        .layer(EvictLeastRecentlyRead::default())
+        .build_custom(|layer| {
+            let evicted = Rc::clone(&evicted);
+            LocalCacheBuilder::new()
+                .capacity(2)
+                .removal_listener(move |_p: Pointer<Entry, _>, cause| {
+                    if cause == RemovalCause::Evicted {
+                        evicted.set(evicted.get() + 1);
+                    }
+                })
+                .build_with_layer(layer)
+        });
+
+    cache.insert(Entry(1));
+    cache.insert(Entry(2));
+    assert!(cache.get(&1).is_some());
+    cache.insert(Entry(3));
+    assert_eq!(evicted.get(), 1);
+    assert!(cache.get(&2).is_none());
+    assert!(cache.get(&1).is_some());
+    assert_eq!(cache.iter().count(), 2);
+
+    cache.clear();
+    assert_eq!(cache.len(), 0);
+
+    let cache = BuildCache::<Entry>::default().build_local();
+    cache.insert(Entry(1));
+    assert_eq!(cache.get(&1).map(|p| (*p).0), Some(1));
+}
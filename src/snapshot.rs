@@ -0,0 +1,307 @@
+use std::{
+    fmt,
+    io::{self, BufReader, BufWriter, Read, Write},
+    ops::Deref,
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{Cache, Value};
+
+// A snapshot is the magic bytes and a little endian u32 format version, then each value bincode
+// encoded behind a 1 byte, a 0 byte, the u64 number of values and finally a CRC32 of everything
+// after the version.
+const MAGIC: &[u8; 8] = b"CACHSNAP";
+
+pub const FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    Encoding(bincode::Error),
+    NotASnapshot,
+    UnsupportedVersion(u32),
+    Corrupt,
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "snapshot io failed: {err}"),
+            Self::Encoding(err) => write!(f, "snapshot value encoding failed: {err}"),
+            Self::NotASnapshot => write!(f, "not a cache snapshot"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported snapshot format version {version}")
+            }
+            Self::Corrupt => write!(f, "snapshot checksum or length mismatch"),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::Encoding(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<bincode::Error> for SnapshotError {
+    fn from(err: bincode::Error) -> Self {
+        Self::Encoding(err)
+    }
+}
+
+/// Write `values` to `writer` in order, returning how many were written.
+pub fn dump<P>(values: impl IntoIterator<Item = P>, writer: impl Write) -> Result<u64, SnapshotError>
+where
+    P: Deref,
+    P::Target: Serialize,
+{
+    let mut writer = BufWriter::new(writer);
+    writer.write_all(MAGIC)?;
+    writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+
+    let mut writer = Checksummed::new(writer);
+    let mut count = 0u64;
+    for value in values {
+        writer.write_all(&[1])?;
+        bincode::serialize_into(&mut writer, &*value)?;
+        count += 1;
+    }
+    writer.write_all(&[0])?;
+    writer.write_all(&count.to_le_bytes())?;
+
+    let checksum = writer.hasher.finalize();
+    let mut writer = writer.inner;
+    writer.write_all(&checksum.to_le_bytes())?;
+    writer.flush()?;
+    Ok(count)
+}
+
+/// Read back everything a snapshot holds, in the order it was written. Nothing is returned
+/// unless the whole snapshot checks out.
+pub fn read<T: DeserializeOwned>(reader: impl Read) -> Result<Vec<T>, SnapshotError> {
+    let mut reader = BufReader::new(reader);
+    let mut magic = [0; MAGIC.len()];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(SnapshotError::NotASnapshot);
+    }
+    let version = u32::from_le_bytes(read_array(&mut reader)?);
+    if version != FORMAT_VERSION {
+        return Err(SnapshotError::UnsupportedVersion(version));
+    }
+
+    let mut reader = Checksummed::new(reader);
+    let mut values = Vec::new();
+    loop {
+        match read_array(&mut reader)? {
+            [0] => break,
+            [1] => values.push(bincode::deserialize_from(&mut reader)?),
+            _ => return Err(SnapshotError::Corrupt),
+        }
+    }
+    let count = u64::from_le_bytes(read_array(&mut reader)?);
+
+    let checksum = reader.hasher.finalize();
+    let expected = u32::from_le_bytes(read_array(&mut reader.inner)?);
+    if checksum != expected || count != values.len() as u64 {
+        return Err(SnapshotError::Corrupt);
+    }
+    Ok(values)
+}
+
+/// Insert everything from a snapshot into `cache`, returning how many values it held.
+pub fn restore<T, C>(cache: &C, reader: impl Read) -> Result<usize, SnapshotError>
+where
+    T: Value + DeserializeOwned,
+    C: Cache<T>,
+{
+    let values = read(reader)?;
+    let count = values.len();
+    cache.insert_many(values);
+    Ok(count)
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+struct Checksummed<I> {
+    inner: I,
+    hasher: crc32fast::Hasher,
+}
+
+impl<I> Checksummed<I> {
+    fn new(inner: I) -> Self {
+        Self {
+            inner,
+            hasher: crc32fast::Hasher::new(),
+        }
+    }
+}
+
+impl<W: Write> Write for Checksummed<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<R: Read> Read for Checksummed<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+#[test]
+fn dump_restore() {
+    use serde::Deserialize;
+
+    use crate::{build::BuildCache, evict::read::EvictLeastRecentlyRead, sync::SyncCacheBuilder};
+
+    #[derive(Serialize, Deserialize)]
+    struct Entry(u32, String);
+
+    impl Value for Entry {
+        type Key = u32;
+
+        fn key(&self) -> &u32 {
+            &self.0
+        }
+    }
+
+    let build = |capacity| {
+        BuildCache::<Entry>::default()
+            .layer(EvictLeastRecentlyRead::default())
+            .build_custom(|layer| {
+                SyncCacheBuilder::new()
+                    .exact_shards(1)
+                    .capacity(capacity)
+                    .build_with_layer(layer)
+            })
+    };
+
+    let cache = build(4);
+    for i in 0..4 {
+        cache.insert(Entry(i, i.to_string()));
+    }
+    cache.get(&0);
+
+    let mut snapshot = Vec::new();
+    assert_eq!(cache.dump(&mut snapshot).unwrap(), 4);
+
+    // Only the two most recently read make it into the smaller cache
+    let smaller = build(2);
+    assert_eq!(smaller.restore(&snapshot[..]).unwrap(), 4);
+    assert_eq!(smaller.len(), 2);
+    assert_eq!(smaller.get(&0).unwrap().1, "0");
+    assert!(smaller.get(&3).is_some());
+
+    let mut corrupt = snapshot.clone();
+    // The first value's string
+    corrupt[25] ^= 1;
+    assert!(matches!(
+        build(4).restore(&corrupt[..]),
+        Err(SnapshotError::Corrupt)
+    ));
+
+    let mut future = snapshot.clone();
+    future[8] = 2;
+    assert!(matches!(
+        build(4).restore(&future[..]),
+        Err(SnapshotError::UnsupportedVersion(2))
+    ));
+    assert!(matches!(
+        read::<Entry>(&b"not a snapshot"[..]),
+        Err(SnapshotError::NotASnapshot)
+    ));
+}
+
+#[test]
+fn restore_across_shards() {
+    use std::hash::{BuildHasherDefault, Hasher};
+
+    use serde::Deserialize;
+
+    use crate::{build::BuildCache, evict::read::EvictLeastRecentlyRead, sync::SyncCacheBuilder};
+
+    #[derive(Serialize, Deserialize)]
+    struct Entry(u32);
+
+    impl Value for Entry {
+        type Key = u32;
+
+        fn key(&self) -> &u32 {
+            &self.0
+        }
+    }
+
+    // Puts each key in shard key % 4
+    #[derive(Default)]
+    struct Identity(u64);
+
+    impl Hasher for Identity {
+        fn finish(&self) -> u64 {
+            self.0
+        }
+
+        fn write(&mut self, _bytes: &[u8]) {
+            unreachable!("only u32 keys are hashed")
+        }
+
+        fn write_u32(&mut self, i: u32) {
+            self.0 = i.into();
+        }
+    }
+
+    let cache = BuildCache::<Entry>::default()
+        .layer(EvictLeastRecentlyRead::default())
+        .build_custom(|layer| {
+            SyncCacheBuilder::new()
+                .hasher(BuildHasherDefault::<Identity>::default())
+                .exact_shards(4)
+                .capacity(16)
+                .build_with_layer(layer)
+        });
+    for i in 0..16 {
+        cache.insert(Entry(i));
+    }
+    // The most recently read of each shard
+    for i in [1, 6, 11, 12] {
+        cache.get(&i);
+    }
+    let mut snapshot = Vec::new();
+    cache.dump(&mut snapshot).unwrap();
+
+    let smaller = BuildCache::<Entry>::default()
+        .layer(EvictLeastRecentlyRead::default())
+        .build_custom(|layer| {
+            SyncCacheBuilder::new()
+                .exact_shards(1)
+                .capacity(4)
+                .build_with_layer(layer)
+        });
+    smaller.restore(&snapshot[..]).unwrap();
+    let mut restored: Vec<_> = smaller.iter().map(|p| p.0).collect();
+    restored.sort();
+    assert_eq!(restored, [1, 6, 11, 12]);
+}
@@ -0,0 +1,194 @@
+use std::{
+    ops::{Add, Sub},
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use crate::listener::RemovalCause;
+
+/// Point in time snapshot of a cache's counters. Counters only ever go up, so subtract an
+/// earlier snapshot to get the activity in between.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub expirations: u64,
+    pub loads: u64,
+    pub load_time: Duration,
+    /// Loads that waited on another in flight load for the same key instead of starting their own.
+    pub coalesced_loads: u64,
+}
+
+impl CacheStats {
+    pub fn requests(&self) -> u64 {
+        self.hits.saturating_add(self.misses)
+    }
+
+    pub fn hit_rate(&self) -> Option<f64> {
+        match self.requests() {
+            0 => None,
+            requests => Some(self.hits as f64 / requests as f64),
+        }
+    }
+
+    pub fn average_load_time(&self) -> Option<Duration> {
+        match self.loads {
+            0 => None,
+            loads => Some(self.load_time / loads.try_into().unwrap_or(u32::MAX)),
+        }
+    }
+
+    pub fn delta(&self, earlier: &Self) -> Self {
+        Self {
+            hits: self.hits.saturating_sub(earlier.hits),
+            misses: self.misses.saturating_sub(earlier.misses),
+            evictions: self.evictions.saturating_sub(earlier.evictions),
+            expirations: self.expirations.saturating_sub(earlier.expirations),
+            loads: self.loads.saturating_sub(earlier.loads),
+            load_time: self.load_time.saturating_sub(earlier.load_time),
+            coalesced_loads: self.coalesced_loads.saturating_sub(earlier.coalesced_loads),
+        }
+    }
+}
+
+impl Sub for CacheStats {
+    type Output = Self;
+
+    fn sub(self, earlier: Self) -> Self {
+        self.delta(&earlier)
+    }
+}
+
+impl Add for CacheStats {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            hits: self.hits.saturating_add(other.hits),
+            misses: self.misses.saturating_add(other.misses),
+            evictions: self.evictions.saturating_add(other.evictions),
+            expirations: self.expirations.saturating_add(other.expirations),
+            loads: self.loads.saturating_add(other.loads),
+            load_time: self.load_time.saturating_add(other.load_time),
+            coalesced_loads: self.coalesced_loads.saturating_add(other.coalesced_loads),
+        }
+    }
+}
+
+impl std::iter::Sum for CacheStats {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::default(), Add::add)
+    }
+}
+
+/// Live counters, kept per shard so concurrent readers don't fight over the same cache line.
+#[derive(Debug, Default)]
+pub(crate) struct StatsCounter {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    expirations: AtomicU64,
+    loads: AtomicU64,
+    load_nanos: AtomicU64,
+    coalesced_loads: AtomicU64,
+}
+
+impl StatsCounter {
+    pub fn read(&self, hit: bool) {
+        match hit {
+            true => &self.hits,
+            false => &self.misses,
+        }
+        .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn removed(&self, cause: RemovalCause) {
+        match cause {
+            RemovalCause::Evicted => &self.evictions,
+            RemovalCause::Expired => &self.expirations,
+            _ => return,
+        }
+        .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn loaded(&self, elapsed: Duration) {
+        self.loads.fetch_add(1, Ordering::Relaxed);
+        self.load_nanos.fetch_add(
+            elapsed.as_nanos().try_into().unwrap_or(u64::MAX),
+            Ordering::Relaxed,
+        );
+    }
+
+    pub fn coalesced(&self) {
+        self.coalesced_loads.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            expirations: self.expirations.load(Ordering::Relaxed),
+            loads: self.loads.load(Ordering::Relaxed),
+            load_time: Duration::from_nanos(self.load_nanos.load(Ordering::Relaxed)),
+            coalesced_loads: self.coalesced_loads.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[test]
+fn counts_reads_and_removals() {
+    use crate::{
+        build::BuildCache, evict::write::EvictLeastRecentlyWritten, expire::Expire,
+        sync::SyncCacheBuilder, Cache,
+    };
+
+    struct Entry(u32, bool);
+
+    impl crate::Value for Entry {
+        type Key = u32;
+
+        fn key(&self) -> &u32 {
+            &self.0
+        }
+    }
+
+    impl Expire for Entry {
+        fn is_expired(&self) -> bool {
+            self.1
+        }
+    }
+
+    let cache = BuildCache::<Entry>::default()
+        .expire()
+        .layer(EvictLeastRecentlyWritten::default())
+        .build_custom(|layer| {
+            SyncCacheBuilder::new()
+                .exact_shards(1)
+                .capacity(2)
+                .stats()
+                .build_with_layer(layer)
+        });
+
+    cache.insert(Entry(1, false));
+    assert!(cache.get(&1).is_some());
+    assert!(cache.get(&2).is_none());
+    let before = cache.stats().unwrap();
+    assert_eq!((before.hits, before.misses), (1, 1));
+
+    cache.insert(Entry(2, true));
+    cache.insert(Entry(3, false));
+    assert!(cache.get(&2).is_none());
+
+    let delta = cache.stats().unwrap() - before;
+    assert_eq!(
+        delta,
+        CacheStats {
+            misses: 1,
+            evictions: 1,
+            expirations: 1,
+            ..Default::default()
+        }
+    );
+}
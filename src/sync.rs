@@ -1,34 +1,53 @@
 use std::{
     borrow::Borrow,
     hash::{BuildHasher, Hash},
-    ops::Deref,
-    sync::Arc,
+    mem::ManuallyDrop,
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Weak,
+    },
+    thread::ThreadId,
+    time::{Duration, Instant},
     usize,
 };
 
 use crossbeam_utils::CachePadded;
 use hashbrown::{
-    hash_map::DefaultHashBuilder,
+    hash_map::{self, DefaultHashBuilder},
     raw::{Bucket, InsertSlot, RawTable},
+    HashMap,
 };
-use parking_lot::{RwLock, RwLockWriteGuard};
+use parking_lot::{Condvar, Mutex, MutexGuard, RwLock, RwLockWriteGuard};
+use smallvec::SmallVec;
 use stable_deref_trait::{CloneStableDeref, StableDeref};
 
 use crate::{
     layer::{self, Layer, ReadResult, Resolve, Shard as ShardLayer},
+    listener::{RemovalCause, RemovalListener},
+    stats::{CacheStats, StatsCounter},
     Cache,
 };
 
+mod scalable;
+pub use scalable::ScalableCache;
+
 pub const MAX_SHARDS: usize = 2048;
 
+/// Buckets looked at per shard lock taken while sweeping.
+const SWEEP_CHUNK: usize = 64;
+
 #[derive(Debug, Clone)]
-pub struct SyncCacheBuilder<S = DefaultHashBuilder> {
+pub struct SyncCacheBuilder<S = DefaultHashBuilder, N = (), M = ()> {
     hash_builder: S,
     shards: usize,
     capacity: Option<usize>,
+    listener: Option<N>,
+    stats: bool,
+    maintenance: M,
 }
 
-impl<S: Default> Default for SyncCacheBuilder<S> {
+impl<S: Default, N> Default for SyncCacheBuilder<S, N> {
     fn default() -> Self {
         let target = std::thread::available_parallelism()
             .map(|p| p.get() * 4)
@@ -39,6 +58,9 @@ impl<S: Default> Default for SyncCacheBuilder<S> {
             hash_builder: Default::default(),
             shards,
             capacity: None,
+            listener: None,
+            stats: false,
+            maintenance: (),
         }
     }
 }
@@ -49,7 +71,7 @@ impl SyncCacheBuilder {
     }
 }
 
-impl<S> SyncCacheBuilder<S> {
+impl<S, N, M> SyncCacheBuilder<S, N, M> {
     // pub fn evict<E2, Ev2, Eq2>(self, eviction: E2) -> SyncCacheBuilder<E2, Ev2, Eq2, S> {
     //     SyncCacheBuilder {
     //         layer: eviction,
@@ -60,11 +82,42 @@ impl<S> SyncCacheBuilder<S> {
     //     }
     // }
 
-    pub fn hasher<S2>(self, hasher: S2) -> SyncCacheBuilder<S2> {
+    pub fn hasher<S2>(self, hasher: S2) -> SyncCacheBuilder<S2, N, M> {
         SyncCacheBuilder {
             hash_builder: hasher,
             shards: self.shards,
             capacity: self.capacity,
+            listener: self.listener,
+            stats: self.stats,
+            maintenance: self.maintenance,
+        }
+    }
+
+    pub fn removal_listener<N2>(self, listener: N2) -> SyncCacheBuilder<S, N2, M> {
+        SyncCacheBuilder {
+            hash_builder: self.hash_builder,
+            shards: self.shards,
+            capacity: self.capacity,
+            listener: Some(listener),
+            stats: self.stats,
+            maintenance: self.maintenance,
+        }
+    }
+
+    /// Run [`SyncCache::run_maintenance`] with `budget` on a background thread `every` so often,
+    /// until the cache is dropped.
+    pub fn background_maintenance(
+        self,
+        every: Duration,
+        budget: Duration,
+    ) -> SyncCacheBuilder<S, N, BackgroundMaintenance> {
+        SyncCacheBuilder {
+            hash_builder: self.hash_builder,
+            shards: self.shards,
+            capacity: self.capacity,
+            listener: self.listener,
+            stats: self.stats,
+            maintenance: BackgroundMaintenance { every, budget },
         }
     }
 
@@ -85,31 +138,113 @@ impl<S> SyncCacheBuilder<S> {
         }
     }
 
+    pub fn stats(self) -> Self {
+        Self {
+            stats: true,
+            ..self
+        }
+    }
+
     pub fn build_with_layer<T, L, Lv, Ls>(self, layer: L) -> SyncCache<T, Lv, Ls, S>
     where
         T: crate::Value,
         L: Layer<Pointer<T, Lv>, Value = Lv, Shard = Ls>,
+        N: RemovalListener<Pointer<T, Lv>> + Send + Sync + 'static,
+        M: Maintenance<SyncCache<T, Lv, Ls, S>>,
     {
         let capacity = self
             .capacity
             .unwrap_or_else(|| self.shards.saturating_mul(16));
-        let capacity_per_shard = self.shards.div_ceil(capacity);
+        let capacity_per_shard = capacity.div_ceil(self.shards);
+        let expected_len = layer.expected_len(capacity_per_shard);
 
         let shards = std::iter::repeat_with(|| {
             CachePadded::new(RwLock::new(Shard {
-                values: RawTable::with_capacity(capacity_per_shard),
+                values: RawTable::with_capacity(expected_len),
                 layer: layer.new_shard(capacity_per_shard),
             }))
         })
         .take(self.shards)
         .collect();
 
-        SyncCache {
-            shards,
-            hash_builder: self.hash_builder,
-            mask: self.shards - 1,
-            capacity_per_shard,
-        }
+        let mut cache = SyncCache {
+            shared: Arc::new(Shared {
+                shards,
+                hash_builder: self.hash_builder,
+                mask: self.shards - 1,
+                capacity: AtomicUsize::new(capacity),
+                observers: Observers::new(self.listener, self.stats, self.shards),
+                sweep: Mutex::new(SweepCursor::default()),
+                computing: Mutex::default(),
+            }),
+            maintenance: None,
+        };
+        self.maintenance.start(&mut cache);
+        cache
+    }
+}
+
+/// How a freshly built cache gets its maintenance run, if at all.
+pub trait Maintenance<C> {
+    fn start(self, cache: &mut C);
+}
+
+/// Left to the caller to run [`SyncCache::run_maintenance`] when it suits them.
+impl<C> Maintenance<C> for () {
+    fn start(self, _cache: &mut C) {}
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BackgroundMaintenance {
+    every: Duration,
+    budget: Duration,
+}
+
+impl<T, Lv, Ls, S> Maintenance<SyncCache<T, Lv, Ls, S>> for BackgroundMaintenance
+where
+    T: crate::Value + Send + Sync + 'static,
+    Lv: Send + Sync + 'static,
+    Ls: ShardLayer<Pointer<T, Lv>, Value = Lv> + Send + Sync + 'static,
+    S: BuildHasher + Send + Sync + 'static,
+{
+    fn start(self, cache: &mut SyncCache<T, Lv, Ls, S>) {
+        let shared = Arc::downgrade(&cache.shared);
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let thread_stop = Arc::clone(&stop);
+
+        std::thread::Builder::new()
+            .name("cach-maintenance".into())
+            .spawn(move || {
+                let (stopped, wake) = &*thread_stop;
+                let mut guard = stopped.lock();
+                while !*guard {
+                    wake.wait_for(&mut guard, self.every);
+                    if *guard {
+                        break;
+                    }
+                    // Only hold on to the cache while sweeping so dropping it isn't held up
+                    let Some(shared) = Weak::upgrade(&shared) else {
+                        break;
+                    };
+                    MutexGuard::unlocked(&mut guard, || shared.run_maintenance(self.budget));
+                }
+            })
+            .expect("failed to spawn maintenance thread");
+
+        cache.maintenance = Some(MaintenanceThread { stop });
+    }
+}
+
+/// Tells the background maintenance thread to stop once the cache is dropped.
+struct MaintenanceThread {
+    stop: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl Drop for MaintenanceThread {
+    fn drop(&mut self) {
+        let (stopped, wake) = &*self.stop;
+        *stopped.lock() = true;
+        wake.notify_one();
     }
 }
 
@@ -145,6 +280,11 @@ impl<S> SyncCacheBuilder<S> {
 //     }
 // }
 
+fn shard_for_hash(hash: u64, mask: usize) -> usize {
+    let shard = hash ^ hash.rotate_right(u64::BITS / 2);
+    (shard as usize) & mask
+}
+
 fn target_shards_to_exact(target: usize) -> usize {
     target
         .checked_next_power_of_two()
@@ -154,10 +294,48 @@ fn target_shards_to_exact(target: usize) -> usize {
 
 // XX: Can remove L!
 pub struct SyncCache<T, Lv, Ls, S = DefaultHashBuilder> {
+    shared: Arc<Shared<T, Lv, Ls, S>>,
+    maintenance: Option<MaintenanceThread>,
+}
+
+struct Shared<T, Lv, Ls, S> {
     shards: Vec<CachePadded<RwLock<Shard<T, Lv, Ls>>>>,
     hash_builder: S,
     mask: usize,
-    capacity_per_shard: usize,
+    capacity: AtomicUsize,
+    observers: Observers<T, Lv>,
+    sweep: Mutex<SweepCursor>,
+    // Keys being computed by `get_or_compute`, by hash
+    computing: Mutex<HashMap<u64, Arc<Computing>>>,
+}
+
+/// Where the last call to `run_maintenance` stopped.
+#[derive(Default)]
+struct SweepCursor {
+    shard: usize,
+    bucket: usize,
+}
+
+/// Placeholder that callers of `get_or_compute` wait on while another computes the value.
+struct Computing {
+    thread: ThreadId,
+    done: Mutex<bool>,
+    finished: Condvar,
+}
+
+/// Clears a placeholder and wakes its waiters once the computation finishes, even by panicking.
+struct ComputingGuard<'a> {
+    computing: &'a Mutex<HashMap<u64, Arc<Computing>>>,
+    hash: u64,
+    placeholder: Arc<Computing>,
+}
+
+impl Drop for ComputingGuard<'_> {
+    fn drop(&mut self) {
+        self.computing.lock().remove(&self.hash);
+        *self.placeholder.done.lock() = true;
+        self.placeholder.finished.notify_all();
+    }
 }
 
 struct Shard<T, Lv, Ls> {
@@ -207,6 +385,176 @@ where
 {
     type Pointer = Pointer<T, Lv>;
 
+    fn len(&self) -> usize {
+        self.shared.len()
+    }
+
+    fn iter(&self) -> impl Iterator<Item = Self::Pointer> {
+        self.shared.iter()
+    }
+
+    fn get<K>(&self, key: &K) -> Option<Self::Pointer>
+    where
+        T::Key: Borrow<K>,
+        K: ?Sized + Hash + std::cmp::Eq,
+    {
+        self.shared.get(key)
+    }
+
+    /// Goes through [`get_or_compute`](SyncCache::get_or_compute), so `f` runs without holding
+    /// the shard lock, and with the same caveats.
+    fn or_insert_with<K>(&self, key: &K, f: impl FnOnce() -> T) -> Self::Pointer
+    where
+        T::Key: Borrow<K>,
+        K: ?Sized + Hash + std::cmp::Eq,
+    {
+        self.shared.get_or_compute(key, f)
+    }
+
+    fn entry<'c, K>(
+        &'c self,
+        key: &K,
+    ) -> crate::Entry<
+        impl crate::OccupiedEntry<Pointer = Self::Pointer> + 'c,
+        impl crate::VacantEntry<Pointer = Self::Pointer> + 'c,
+    >
+    where
+        T::Key: Borrow<K>,
+        K: ?Sized + std::cmp::Eq + Hash,
+    {
+        self.shared.entry(key)
+    }
+
+    fn insert(&self, value: T) -> Self::Pointer {
+        self.shared.insert(value)
+    }
+
+    fn upsert(&self, value: T, f: impl FnOnce(T, &T) -> Option<T>) -> Self::Pointer {
+        self.shared.upsert(value, f)
+    }
+
+    fn remove_if<K>(&self, key: &K, f: impl FnOnce(&T) -> bool) -> Option<Self::Pointer>
+    where
+        T::Key: Borrow<K>,
+        K: ?Sized + Hash + std::cmp::Eq,
+    {
+        self.shared.remove_if(key, f)
+    }
+
+    fn get_many<'k, K>(&self, keys: impl IntoIterator<Item = &'k K>) -> Vec<Option<Self::Pointer>>
+    where
+        T::Key: Borrow<K>,
+        K: ?Sized + Hash + std::cmp::Eq + 'k,
+    {
+        self.shared.get_many(keys)
+    }
+
+    fn insert_many(&self, values: impl IntoIterator<Item = T>) -> Vec<Self::Pointer> {
+        self.shared.insert_many(values)
+    }
+
+    fn remove_many<'k, K>(&self, keys: impl IntoIterator<Item = &'k K>) -> Vec<Option<Self::Pointer>>
+    where
+        T::Key: Borrow<K>,
+        K: ?Sized + Hash + std::cmp::Eq + 'k,
+    {
+        self.shared.remove_many(keys)
+    }
+
+    fn retain(&self, f: impl FnMut(&T) -> bool) {
+        self.shared.retain(f)
+    }
+
+    fn drain(&self) -> impl Iterator<Item = Self::Pointer> {
+        self.shared.drain()
+    }
+
+    fn clear(&self) {
+        self.shared.clear()
+    }
+
+    fn stats(&self) -> Option<CacheStats> {
+        self.shared.stats()
+    }
+}
+
+impl<T, Lv, Ls, S> SyncCache<T, Lv, Ls, S>
+where
+    T: crate::Value + 'static,
+    Ls: ShardLayer<Pointer<T, Lv>, Value = Lv>,
+    S: BuildHasher,
+{
+    pub fn capacity(&self) -> usize {
+        self.shared.capacity()
+    }
+
+    /// Spread `capacity` across the shards, evicting whatever no longer fits.
+    pub fn set_capacity(&self, capacity: usize) {
+        self.shared.set_capacity(capacity)
+    }
+
+    /// Remove expired entries that haven't been read since they expired, working through the
+    /// shards a chunk at a time from wherever the last call stopped. Gives up once `budget` has
+    /// passed or every shard has been swept once, and returns straight away if another call is
+    /// already sweeping.
+    pub fn run_maintenance(&self, budget: Duration) {
+        self.shared.run_maintenance(budget)
+    }
+
+    /// Get `key`, or compute and insert it if it's missing. Unlike going through
+    /// [`entry`](Cache::entry), `f` runs without holding the shard lock: other callers asking for
+    /// the same key wait for it to finish instead, while every other key stays available. Should
+    /// something else insert `key` while `f` runs, that value is kept and `f`'s is dropped.
+    ///
+    /// `f` mustn't ask for `key` itself, through this or
+    /// [`or_insert_with`](Cache::or_insert_with), since it would be waiting on itself. That
+    /// panics rather than deadlocking.
+    pub fn get_or_compute<K>(&self, key: &K, f: impl FnOnce() -> T) -> Pointer<T, Lv>
+    where
+        T::Key: Borrow<K>,
+        K: ?Sized + Hash + std::cmp::Eq,
+    {
+        self.shared.get_or_compute(key, f)
+    }
+
+    /// When the next entry is due to expire, for layers that keep track of deadlines.
+    pub fn next_expiration(&self) -> Option<Instant> {
+        self.shared.next_expiration()
+    }
+
+    /// Write a [`snapshot`](crate::snapshot) of every unexpired entry to `writer`, next to be
+    /// evicted first where the layer keeps an order. Shards are interleaved by how far through
+    /// its own shard's order each entry is, so restoring into a smaller cache keeps the ones
+    /// that would have lasted longest whatever its sharding.
+    #[cfg(feature = "serde")]
+    pub fn dump(&self, writer: impl std::io::Write) -> Result<u64, crate::snapshot::SnapshotError>
+    where
+        T: serde::Serialize,
+    {
+        crate::snapshot::dump(self.shared.eviction_ordered(), writer)
+    }
+
+    /// Insert everything from a snapshot written by [`dump`](Self::dump), returning how many
+    /// entries it held.
+    #[cfg(feature = "serde")]
+    pub fn restore(&self, reader: impl std::io::Read) -> Result<usize, crate::snapshot::SnapshotError>
+    where
+        T: serde::de::DeserializeOwned,
+        T::Key: Hash + std::cmp::Eq,
+    {
+        crate::snapshot::restore(self, reader)
+    }
+}
+
+impl<T, Lv, Ls, S> Cache<T> for Shared<T, Lv, Ls, S>
+where
+    T: crate::Value + 'static,
+    T::Key: Hash + std::cmp::Eq,
+    Ls: ShardLayer<Pointer<T, Lv>, Value = Lv>,
+    S: BuildHasher,
+{
+    type Pointer = Pointer<T, Lv>;
+
     fn len(&self) -> usize {
         self.shards
             .iter()
@@ -215,7 +563,7 @@ where
     }
 
     fn iter(&self) -> impl Iterator<Item = Self::Pointer> {
-        self.shards.iter().flat_map(|shard| {
+        self.shards.iter().enumerate().flat_map(move |(shard_index, shard)| {
             let mut pointers = Vec::new();
             loop {
                 pointers.clear();
@@ -245,7 +593,7 @@ where
                             }
                         }
                         layer::ReadLock::Ref | layer::ReadLock::Mut => {
-                            let mut shard = shard.write(); // don't try to upgrade later to a write lock on ::Remove
+                            let mut shard = self.write_shard(shard_index); // don't try to upgrade later to a write lock on ::Remove
                             for bucket in i..buckets_len.min(i + CHUNK) {
                                 // XX safety
                                 if unsafe { shard.values.is_bucket_full(bucket) } {
@@ -257,9 +605,9 @@ where
                                         ReadResult::Retain => pointers.push(pointer.clone()),
                                         ReadResult::Remove => {
                                             shard.layer.remove::<ResolveLayer>(pointer);
-                                            unsafe {
-                                                shard.values.remove(bucket);
-                                            }
+                                            let (removed, _slot) =
+                                                unsafe { shard.values.remove(bucket) };
+                                            shard.removed(removed, RemovalCause::Expired);
                                         }
                                     }
                                 }
@@ -283,26 +631,39 @@ where
         match Ls::READ_LOCK {
             layer::ReadLock::None => {
                 let (hash, shard_index) = self.hash_and_shard(key);
-                Some(
-                    self.shards[shard_index]
-                        .read()
-                        .values
-                        .get(hash, |p| p.0.value.key().borrow() == key)?
-                        .clone(),
-                )
+                let found = self.shards[shard_index]
+                    .read()
+                    .values
+                    .get(hash, |p| p.0.value.key().borrow() == key)
+                    .cloned();
+                self.record_read(shard_index, found.is_some());
+                if found.is_none() {
+                    self.missed(shard_index, key);
+                }
+                found
             }
             layer::ReadLock::Ref => {
                 let (hash, shard_index) = self.hash_and_shard(key);
                 let shard = self.shards[shard_index].read();
-                let bucket = shard
+                let Some(bucket) = shard
                     .values
-                    .find(hash, |p| p.0.value.key().borrow() == key)?;
+                    .find(hash, |p| p.0.value.key().borrow() == key)
+                else {
+                    self.record_read(shard_index, false);
+                    drop(shard);
+                    self.missed(shard_index, key);
+                    return None;
+                };
                 // XX: safety
                 let pointer = unsafe { bucket.as_ref() }.clone();
 
                 match shard.layer.read_ref::<ResolveLayer>(&pointer) {
-                    ReadResult::Retain => Some(pointer),
+                    ReadResult::Retain => {
+                        self.record_read(shard_index, true);
+                        Some(pointer)
+                    }
                     ReadResult::Remove => {
+                        self.record_read(shard_index, false);
                         // need to look it up again in case someone else deleted it first!
                         // XX safety
                         let bucket_index = unsafe { shard.values.bucket_index(&bucket) };
@@ -310,15 +671,15 @@ where
                         drop(shard);
 
                         // XX: bucket not safe to read
-                        let mut shard = self.shards[shard_index].write();
+                        let mut shard = self.write_shard(shard_index);
                         if shard.values.buckets() > buckets_len {
                             // we grew in between
-                            if shard
+                            if let Some(removed) = shard
                                 .values
                                 .remove_entry(hash, |p| Arc::ptr_eq(&p.0, &pointer.0))
-                                .is_some()
                             {
-                                shard.layer.remove::<ResolveLayer>(&pointer);
+                                shard.layer.remove::<ResolveLayer>(&removed);
+                                shard.removed(removed, RemovalCause::Expired);
                             }
                         } else if shard.values.buckets() == buckets_len {
                             // XX safety
@@ -327,15 +688,15 @@ where
                                 let bucket = unsafe { shard.values.bucket(bucket_index) };
                                 // XX safety
                                 if Arc::ptr_eq(&unsafe { bucket.as_ref() }.0, &pointer.0) {
-                                    unsafe {
-                                        shard.values.remove(bucket);
-                                    }
-                                    shard.layer.remove::<ResolveLayer>(&pointer);
+                                    let (removed, _slot) = unsafe { shard.values.remove(bucket) };
+                                    shard.layer.remove::<ResolveLayer>(&removed);
+                                    shard.removed(removed, RemovalCause::Expired);
                                 }
                             }
                         } else {
                             unreachable!("map should never shrink");
                         }
+                        shard.layer.miss::<ResolveLayer>(key);
 
                         None
                     }
@@ -355,13 +716,494 @@ where
         impl crate::OccupiedEntry<Pointer = Self::Pointer> + 'c,
         impl crate::VacantEntry<Pointer = Self::Pointer> + 'c,
     >
+    where
+        T::Key: Borrow<K>,
+        K: ?Sized + std::cmp::Eq + Hash,
+    {
+        self.lookup(key, true)
+    }
+
+    // Writes aren't reads, so keep them out of the hit/miss counts
+
+    fn insert(&self, value: T) -> Self::Pointer {
+        match self.lookup(value.key(), false) {
+            crate::Entry::Occupied(o) => crate::OccupiedEntry::replace(o, value),
+            crate::Entry::Vacant(v) => crate::VacantEntry::insert(v, value),
+        }
+    }
+
+    fn upsert(&self, value: T, f: impl FnOnce(T, &T) -> Option<T>) -> Self::Pointer {
+        match self.lookup(value.key(), false) {
+            crate::Entry::Occupied(o) => {
+                if let Some(replacement) = f(value, crate::OccupiedEntry::value(&o)) {
+                    crate::OccupiedEntry::replace(o, replacement)
+                } else {
+                    crate::OccupiedEntry::into_pointer(o)
+                }
+            }
+            crate::Entry::Vacant(v) => crate::VacantEntry::insert(v, value),
+        }
+    }
+
+    fn remove_if<K>(&self, key: &K, f: impl FnOnce(&T) -> bool) -> Option<Self::Pointer>
+    where
+        T::Key: Borrow<K>,
+        K: ?Sized + Hash + std::cmp::Eq,
+    {
+        match self.lookup(key, false) {
+            crate::Entry::Occupied(o) if f(crate::OccupiedEntry::value(&o)) => {
+                Some(crate::OccupiedEntry::remove(o))
+            }
+            _ => None,
+        }
+    }
+
+    fn get_many<'k, K>(&self, keys: impl IntoIterator<Item = &'k K>) -> Vec<Option<Self::Pointer>>
+    where
+        T::Key: Borrow<K>,
+        K: ?Sized + Hash + std::cmp::Eq + 'k,
+    {
+        let keys: Vec<_> = keys.into_iter().collect();
+        let mut found: Vec<_> = keys.iter().map(|_| None).collect();
+
+        let hashed = self.by_shard(keys.iter().copied());
+        for group in hashed.chunk_by(|a, b| a.0 == b.0) {
+            let shard_index = group[0].0;
+            match Ls::READ_LOCK {
+                layer::ReadLock::None => {
+                    let shard = self.shards[shard_index].read();
+                    for &(_, hash, index) in group {
+                        found[index] = shard
+                            .values
+                            .get(hash, |p| p.0.value.key().borrow() == keys[index])
+                            .cloned();
+                        self.record_read(shard_index, found[index].is_some());
+                    }
+                    drop(shard);
+                    for &(_, _, index) in group {
+                        if found[index].is_none() {
+                            self.missed(shard_index, keys[index]);
+                        }
+                    }
+                }
+                // A Ref layer could get away with a read lock, but then an expired read means
+                // coming back for the write lock anyway
+                layer::ReadLock::Ref | layer::ReadLock::Mut => {
+                    let mut shard = self.write_shard(shard_index);
+                    let (shard, removed) = shard.split();
+                    for &(_, hash, index) in group {
+                        let Some(bucket) = shard
+                            .values
+                            .find(hash, |p| p.0.value.key().borrow() == keys[index])
+                        else {
+                            self.record_read(shard_index, false);
+                            shard.layer.miss::<ResolveLayer>(keys[index]);
+                            continue;
+                        };
+                        // XX safety
+                        let pointer = unsafe { bucket.as_ref() };
+                        match shard.layer.read_mut::<ResolveLayer>(pointer) {
+                            ReadResult::Retain => {
+                                self.record_read(shard_index, true);
+                                found[index] = Some(pointer.clone());
+                            }
+                            ReadResult::Remove => {
+                                self.record_read(shard_index, false);
+                                shard.layer.remove::<ResolveLayer>(pointer);
+                                shard.layer.miss::<ResolveLayer>(keys[index]);
+                                // XX safety
+                                let (pointer, _slot) = unsafe { shard.values.remove(bucket) };
+                                self.record_removal(
+                                    shard_index,
+                                    removed,
+                                    pointer,
+                                    RemovalCause::Expired,
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        found
+    }
+
+    fn insert_many(&self, values: impl IntoIterator<Item = T>) -> Vec<Self::Pointer> {
+        let mut values: Vec<_> = values.into_iter().map(Some).collect();
+        let mut inserted: Vec<_> = values.iter().map(|_| None).collect();
+
+        let hashed = self.by_shard(values.iter().map(|v| v.as_ref().unwrap().key()));
+        for group in hashed.chunk_by(|a, b| a.0 == b.0) {
+            let shard_index = group[0].0;
+            let mut shard = self.write_shard(shard_index);
+            let (shard, removed) = shard.split();
+            for &(_, hash, index) in group {
+                let value = values[index].take().unwrap();
+                let found = shard.values.find_or_find_insert_slot(
+                    hash,
+                    |p| p.0.value.key() == value.key(),
+                    |p| self.hash_builder.hash_one(p.key()),
+                );
+                // XX safety
+                inserted[index] = Some(unsafe {
+                    match found {
+                        Ok(bucket) => self.replace_in(shard, removed, shard_index, &bucket, value),
+                        Err(slot) => self.insert_in(shard, removed, shard_index, hash, slot, value),
+                    }
+                });
+            }
+        }
+
+        inserted.into_iter().map(Option::unwrap).collect()
+    }
+
+    fn remove_many<'k, K>(&self, keys: impl IntoIterator<Item = &'k K>) -> Vec<Option<Self::Pointer>>
+    where
+        T::Key: Borrow<K>,
+        K: ?Sized + Hash + std::cmp::Eq + 'k,
+    {
+        let keys: Vec<_> = keys.into_iter().collect();
+        let mut removed_pointers: Vec<_> = keys.iter().map(|_| None).collect();
+
+        let hashed = self.by_shard(keys.iter().copied());
+        for group in hashed.chunk_by(|a, b| a.0 == b.0) {
+            let shard_index = group[0].0;
+            let mut shard = self.write_shard(shard_index);
+            let (shard, removed) = shard.split();
+            for &(_, hash, index) in group {
+                let Some(pointer) = shard
+                    .values
+                    .remove_entry(hash, |p| p.0.value.key().borrow() == keys[index])
+                else {
+                    continue;
+                };
+                shard.layer.remove::<ResolveLayer>(&pointer);
+                self.record_removal(
+                    shard_index,
+                    removed,
+                    pointer.clone(),
+                    RemovalCause::Explicit,
+                );
+                removed_pointers[index] = Some(pointer);
+            }
+        }
+
+        removed_pointers
+    }
+
+    fn retain(&self, mut f: impl FnMut(&T) -> bool) {
+        for shard_index in 0..self.shards.len() {
+            self.retain_shard(shard_index, RemovalCause::Explicit, |p| f(p), drop);
+        }
+    }
+
+    fn drain(&self) -> impl Iterator<Item = Self::Pointer> {
+        (0..self.shards.len()).flat_map(|shard_index| {
+            let mut drained = Vec::new();
+            self.retain_shard(shard_index, RemovalCause::Cleared, |_| false, |p| {
+                drained.push(p)
+            });
+            drained
+        })
+    }
+
+    fn clear(&self) {
+        for shard_index in 0..self.shards.len() {
+            self.retain_shard(shard_index, RemovalCause::Cleared, |_| false, drop);
+        }
+    }
+
+    fn stats(&self) -> Option<CacheStats> {
+        self.observers.stats()
+    }
+}
+
+impl<T, Lv, Ls, S> Shared<T, Lv, Ls, S>
+where
+    T: crate::Value + 'static,
+    Ls: ShardLayer<Pointer<T, Lv>, Value = Lv>,
+    S: BuildHasher,
+{
+    fn capacity(&self) -> usize {
+        self.capacity.load(Ordering::Relaxed)
+    }
+
+    /// # Safety
+    /// `bucket` must be full and belong to `shard`.
+    unsafe fn replace_in(
+        &self,
+        shard: &mut Shard<T, Lv, Ls>,
+        removed: &mut Removed<T, Lv>,
+        shard_index: usize,
+        bucket: &Bucket<Pointer<T, Lv>>,
+        value: T,
+    ) -> Pointer<T, Lv> {
+        let pointer = bucket.as_mut();
+        debug_assert!(value.key() == pointer.key());
+
+        shard.layer.remove::<ResolveLayer>(pointer);
+        let replace = shard.layer.write::<ResolveLayer>(Write {
+            cache: self,
+            shard_values: &mut shard.values,
+            removed,
+            shard_index,
+            target: value,
+        });
+        let replaced = std::mem::replace(pointer, replace.clone());
+        self.record_removal(shard_index, removed, replaced, RemovalCause::Replaced);
+
+        replace
+    }
+
+    /// # Safety
+    /// `slot` must have come from `shard` for `hash` with no inserts since.
+    unsafe fn insert_in(
+        &self,
+        shard: &mut Shard<T, Lv, Ls>,
+        removed: &mut Removed<T, Lv>,
+        shard_index: usize,
+        hash: u64,
+        slot: InsertSlot,
+        value: T,
+    ) -> Pointer<T, Lv> {
+        let insert = shard.layer.write::<ResolveLayer>(Write {
+            cache: self,
+            shard_values: &mut shard.values,
+            removed,
+            shard_index,
+            target: value,
+        });
+        // Evictions only erase buckets, so the slot is still good
+        shard.values.insert_in_slot(hash, slot, insert.clone());
+        insert
+    }
+
+    /// Hash each key and order them so keys for the same shard are next to each other, keeping
+    /// the caller's order within a shard. Yields `(shard_index, hash, index)`.
+    fn by_shard<'k, K>(&self, keys: impl IntoIterator<Item = &'k K>) -> Vec<(usize, u64, usize)>
+    where
+        K: ?Sized + Hash + 'k,
+    {
+        let mut hashed: Vec<_> = keys
+            .into_iter()
+            .enumerate()
+            .map(|(index, key)| {
+                let (hash, shard_index) = self.hash_and_shard(key);
+                (shard_index, hash, index)
+            })
+            .collect();
+        hashed.sort_by_key(|&(shard_index, _, _)| shard_index);
+        hashed
+    }
+
+    /// Remove everything in a shard that `f` rejects under a single write lock.
+    fn retain_shard(
+        &self,
+        shard_index: usize,
+        cause: RemovalCause,
+        mut f: impl FnMut(&Pointer<T, Lv>) -> bool,
+        mut on_removed: impl FnMut(Pointer<T, Lv>),
+    ) {
+        let mut shard = self.write_shard(shard_index);
+        let (shard, removed) = shard.split();
+        // XX safety: buckets are only erased after the iterator has moved past them
+        unsafe {
+            for bucket in shard.values.iter() {
+                if !f(bucket.as_ref()) {
+                    shard.layer.remove::<ResolveLayer>(bucket.as_ref());
+                    let (pointer, _slot) = shard.values.remove(bucket);
+                    self.record_removal(shard_index, removed, pointer.clone(), cause);
+                    on_removed(pointer);
+                }
+            }
+        }
+    }
+
+    fn set_capacity(&self, capacity: usize) {
+        self.capacity.store(capacity, Ordering::Relaxed);
+
+        for shard_index in 0..self.shards.len() {
+            let mut shard = self.write_shard(shard_index);
+            // Re-read under the lock so racing calls settle on whichever stored last
+            let capacity_per_shard = self.capacity().div_ceil(self.shards.len());
+            let (shard, removed) = shard.split();
+            let Shard { values, layer } = shard;
+            layer.set_capacity::<ResolveLayer>(capacity_per_shard, |pointer| {
+                self.unlink(shard_index, values, removed, pointer, RemovalCause::Evicted)
+            });
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    fn eviction_ordered(&self) -> Vec<Pointer<T, Lv>> {
+        let mut ranked = Vec::new();
+        for shard in &self.shards {
+            let shard = shard.read();
+            let mut ordered = Vec::new();
+            let mut push = |pointer: &Pointer<T, Lv>| {
+                if !shard.layer.is_expired::<ResolveLayer>(pointer) {
+                    ordered.push(pointer.clone());
+                }
+            };
+            if !shard.layer.eviction_order::<ResolveLayer>(&mut push) {
+                // XX safety: buckets only live as long as the read lock
+                unsafe { shard.values.iter().for_each(|bucket| push(bucket.as_ref())) };
+            }
+
+            // Each shard's last to be evicted ranks 1, whatever its length
+            let len = ordered.len() as f64;
+            ranked.extend(
+                ordered
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, pointer)| ((i + 1) as f64 / len, pointer)),
+            );
+        }
+        ranked.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+        ranked.into_iter().map(|(_rank, pointer)| pointer).collect()
+    }
+
+    fn next_expiration(&self) -> Option<Instant> {
+        self.shards.iter().fold(None, |next, shard| {
+            layer::earliest(next, shard.read().layer.next_expiration::<ResolveLayer>())
+        })
+    }
+
+    fn run_maintenance(&self, budget: Duration) {
+        let start = Instant::now();
+        let Some(mut cursor) = self.sweep.try_lock() else {
+            return;
+        };
+
+        let mut swept = 0;
+        while swept < self.shards.len() {
+            let shard_index = cursor.shard;
+            let mut shard = self.write_shard(shard_index);
+            let (shard, removed) = shard.split();
+            let Shard { values, layer } = shard;
+
+            if cursor.bucket == 0 {
+                layer.maintain::<ResolveLayer>(|pointer| {
+                    self.unlink(shard_index, values, removed, pointer, RemovalCause::Expired)
+                });
+            }
+
+            // The table may have been resized since we last held the lock, in which case some
+            // buckets get looked at twice or not at all until the next pass
+            let end = cursor.bucket.saturating_add(SWEEP_CHUNK).min(values.buckets());
+            for index in cursor.bucket..end {
+                // XX safety: index is in bounds and erasing a bucket doesn't move the others
+                unsafe {
+                    if !values.is_bucket_full(index) {
+                        continue;
+                    }
+                    let bucket = values.bucket(index);
+                    if layer.is_expired::<ResolveLayer>(bucket.as_ref()) {
+                        layer.remove::<ResolveLayer>(bucket.as_ref());
+                        let (pointer, _slot) = values.remove(bucket);
+                        self.record_removal(shard_index, removed, pointer, RemovalCause::Expired);
+                    }
+                }
+            }
+
+            if end == values.buckets() {
+                cursor.shard = (shard_index + 1) & self.mask;
+                cursor.bucket = 0;
+                swept += 1;
+            } else {
+                cursor.bucket = end;
+            }
+
+            if start.elapsed() >= budget {
+                break;
+            }
+        }
+    }
+
+    fn get_or_compute<K>(&self, key: &K, f: impl FnOnce() -> T) -> Pointer<T, Lv>
+    where
+        T::Key: Borrow<K>,
+        K: ?Sized + Hash + std::cmp::Eq,
+    {
+        if let Some(pointer) = self.get(key) {
+            return pointer;
+        }
+
+        let (hash, shard_index) = self.hash_and_shard(key);
+        let _guard = loop {
+            // XX: keys that share a hash wait on each other, which is fine since they retry
+            let placeholder = match self.computing.lock().entry(hash) {
+                hash_map::Entry::Occupied(o) => Arc::clone(o.get()),
+                hash_map::Entry::Vacant(v) => {
+                    let placeholder = Computing {
+                        thread: std::thread::current().id(),
+                        done: Mutex::new(false),
+                        finished: Condvar::new(),
+                    };
+                    break ComputingGuard {
+                        computing: &self.computing,
+                        hash,
+                        placeholder: Arc::clone(v.insert(Arc::new(placeholder))),
+                    };
+                }
+            };
+            assert!(
+                placeholder.thread != std::thread::current().id(),
+                "get_or_compute called for a key from within its own computation"
+            );
+            self.observers.record_coalesced(shard_index);
+            let mut done = placeholder.done.lock();
+            while !*done {
+                placeholder.finished.wait(&mut done);
+            }
+            drop(done);
+
+            // Missing still means the computation panicked or the value's already gone
+            if let Some(pointer) = self.peek(key) {
+                return pointer;
+            }
+        };
+        // Another computation may have finished between the miss and claiming the key
+        if let Some(pointer) = self.peek(key) {
+            return pointer;
+        }
+
+        let start = Instant::now();
+        let value = f();
+        self.observers.record_load(shard_index, start.elapsed());
+        debug_assert!(value.key().borrow() == key);
+
+        match self.lookup(key, false) {
+            crate::Entry::Occupied(o) => crate::OccupiedEntry::into_pointer(o),
+            crate::Entry::Vacant(v) => crate::VacantEntry::insert(v, value),
+        }
+    }
+
+    /// Like `get`, but left out of the hit and miss counts.
+    fn peek<K>(&self, key: &K) -> Option<Pointer<T, Lv>>
+    where
+        T::Key: Borrow<K>,
+        K: ?Sized + Hash + std::cmp::Eq,
+    {
+        match self.lookup(key, false) {
+            crate::Entry::Occupied(o) => Some(crate::OccupiedEntry::into_pointer(o)),
+            crate::Entry::Vacant(_) => None,
+        }
+    }
+
+    fn lookup<K>(
+        &self,
+        key: &K,
+        record_stats: bool,
+    ) -> Entry<'_, T, Lv, Ls, S>
     where
         T::Key: Borrow<K>,
         K: ?Sized + std::cmp::Eq + Hash,
     {
         let (hash, shard_index) = self.hash_and_shard(key);
 
-        let mut shard = self.shards[shard_index].write();
+        let mut shard = self.write_shard(shard_index);
         let found = shard.values.find_or_find_insert_slot(
             hash,
             |p| p.0.value.key().borrow() == key,
@@ -371,7 +1213,11 @@ where
             Ok(bucket) => {
                 // XX safety
                 let pointer = unsafe { bucket.as_ref() };
-                match Ls::read_mut::<ResolveLayer>(&mut shard.layer, pointer) {
+                let read = Ls::read_mut::<ResolveLayer>(&mut shard.layer, pointer);
+                if record_stats {
+                    self.record_read(shard_index, read == ReadResult::Retain);
+                }
+                match read {
                     ReadResult::Retain => crate::Entry::Occupied(OccupiedEntry {
                         cache: self,
                         shard,
@@ -381,7 +1227,11 @@ where
                     ReadResult::Remove => {
                         shard.layer.remove::<ResolveLayer>(pointer);
                         // XX safety
-                        let (_pointer, slot) = unsafe { shard.values.remove(bucket) };
+                        let (removed, slot) = unsafe { shard.values.remove(bucket) };
+                        shard.removed(removed, RemovalCause::Expired);
+                        if record_stats {
+                            shard.layer.miss::<ResolveLayer>(key);
+                        }
                         crate::Entry::Vacant(VacantEntry {
                             cache: self,
                             shard,
@@ -392,33 +1242,206 @@ where
                     }
                 }
             }
-            Err(slot) => crate::Entry::Vacant(VacantEntry {
-                cache: self,
-                shard,
-                slot,
-                hash,
-                shard_index,
-            }),
+            Err(slot) => {
+                if record_stats {
+                    self.record_read(shard_index, false);
+                    shard.layer.miss::<ResolveLayer>(key);
+                }
+                crate::Entry::Vacant(VacantEntry {
+                    cache: self,
+                    shard,
+                    slot,
+                    hash,
+                    shard_index,
+                })
+            }
         }
     }
 }
 
-impl<T, Lv, Ls, S: BuildHasher> SyncCache<T, Lv, Ls, S>
+impl<T, Lv, Ls, S: BuildHasher> Shared<T, Lv, Ls, S>
 where
     Ls: ShardLayer<Pointer<T, Lv>, Value = Lv>,
     S: BuildHasher,
 {
     fn hash_and_shard(&self, key: &(impl Hash + ?Sized)) -> (u64, usize) {
         let hash = self.hash_builder.hash_one(key);
-        let shard = hash ^ hash.rotate_right(u64::BITS / 2);
-        let shard = (shard as usize) & self.mask;
-        (hash, shard)
+        (hash, shard_for_hash(hash, self.mask))
+    }
+
+    /// Pass on a read that missed to layers that want to know, for reads that don't already
+    /// hold the write lock.
+    fn missed(&self, shard_index: usize, key: &(impl Hash + ?Sized)) {
+        if Ls::OBSERVES_MISSES {
+            self.write_shard(shard_index).layer.miss::<ResolveLayer>(key);
+        }
+    }
+
+    /// Drop a pointer the layer has already let go of from the shard's map.
+    fn unlink(
+        &self,
+        shard_index: usize,
+        values: &mut RawTable<Pointer<T, Lv>>,
+        removed: &mut Removed<T, Lv>,
+        pointer: &Pointer<T, Lv>,
+        cause: RemovalCause,
+    ) where
+        T: crate::Value,
+    {
+        let (hash, _shard_index) = self.hash_and_shard(pointer.key());
+        debug_assert_eq!(_shard_index, shard_index);
+
+        let pointer = values
+            .remove_entry(hash, |p| Arc::ptr_eq(&p.0, &pointer.0))
+            .expect("layer shard and map out of sync");
+        self.record_removal(shard_index, removed, pointer, cause);
+    }
+}
+
+impl<T, Lv, Ls, S> Shared<T, Lv, Ls, S> {
+    fn write_shard(&self, shard_index: usize) -> ShardWriteGuard<'_, Shard<T, Lv, Ls>, T, Lv> {
+        ShardWriteGuard::new(&self.observers, &self.shards[shard_index], shard_index)
+    }
+
+    fn record_read(&self, shard_index: usize, hit: bool) {
+        self.observers.record_read(shard_index, hit)
     }
+
+    fn record_removal(
+        &self,
+        shard_index: usize,
+        removed: &mut Removed<T, Lv>,
+        pointer: Pointer<T, Lv>,
+        cause: RemovalCause,
+    ) {
+        self.observers
+            .record_removal(shard_index, removed, pointer, cause)
+    }
+}
+
+/// The removal listener and per shard stats, if the cache was built with them.
+struct Observers<T, Lv> {
+    listener: Option<Box<dyn RemovalListener<Pointer<T, Lv>> + Send + Sync>>,
+    stats: Option<Box<[CachePadded<StatsCounter>]>>,
+}
+
+impl<T, Lv> Observers<T, Lv> {
+    fn new<N>(listener: Option<N>, stats: bool, shards: usize) -> Self
+    where
+        N: RemovalListener<Pointer<T, Lv>> + Send + Sync + 'static,
+    {
+        Self {
+            listener: listener.map(|l| Box::new(l) as Box<dyn RemovalListener<_> + Send + Sync>),
+            stats: stats.then(|| {
+                std::iter::repeat_with(Default::default)
+                    .take(shards)
+                    .collect()
+            }),
+        }
+    }
+
+    fn record_read(&self, shard_index: usize, hit: bool) {
+        if let Some(stats) = &self.stats {
+            stats[shard_index].read(hit);
+        }
+    }
+
+    fn record_load(&self, shard_index: usize, elapsed: Duration) {
+        if let Some(stats) = &self.stats {
+            stats[shard_index].loaded(elapsed);
+        }
+    }
+
+    fn record_coalesced(&self, shard_index: usize) {
+        if let Some(stats) = &self.stats {
+            stats[shard_index].coalesced();
+        }
+    }
+
+    fn record_removal(
+        &self,
+        shard_index: usize,
+        removed: &mut Removed<T, Lv>,
+        pointer: Pointer<T, Lv>,
+        cause: RemovalCause,
+    ) {
+        if let Some(stats) = &self.stats {
+            stats[shard_index].removed(cause);
+        }
+        if self.listener.is_some() {
+            removed.push((pointer, cause));
+        }
+    }
+
+    fn stats(&self) -> Option<CacheStats> {
+        Some(self.stats.as_ref()?.iter().map(|s| s.snapshot()).sum())
+    }
+}
+
+type Removed<T, Lv> = SmallVec<[(Pointer<T, Lv>, RemovalCause); 2]>;
+
+/// Write lock on a shard that notifies the removal listener of anything removed while it was
+/// held, but only once the lock has been released.
+struct ShardWriteGuard<'a, G, T, Lv> {
+    observers: &'a Observers<T, Lv>,
+    shard: ManuallyDrop<RwLockWriteGuard<'a, G>>,
+    shard_index: usize,
+    removed: Removed<T, Lv>,
 }
 
+impl<'a, G, T, Lv> ShardWriteGuard<'a, G, T, Lv> {
+    fn new(observers: &'a Observers<T, Lv>, shard: &'a RwLock<G>, shard_index: usize) -> Self {
+        Self {
+            observers,
+            shard: ManuallyDrop::new(shard.write()),
+            shard_index,
+            removed: SmallVec::new(),
+        }
+    }
+
+    fn removed(&mut self, pointer: Pointer<T, Lv>, cause: RemovalCause) {
+        self.observers
+            .record_removal(self.shard_index, &mut self.removed, pointer, cause);
+    }
+
+    fn split(&mut self) -> (&mut G, &mut Removed<T, Lv>) {
+        (&mut self.shard, &mut self.removed)
+    }
+}
+
+impl<G, T, Lv> Deref for ShardWriteGuard<'_, G, T, Lv> {
+    type Target = G;
+
+    fn deref(&self) -> &Self::Target {
+        &self.shard
+    }
+}
+
+impl<G, T, Lv> DerefMut for ShardWriteGuard<'_, G, T, Lv> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.shard
+    }
+}
+
+impl<G, T, Lv> Drop for ShardWriteGuard<'_, G, T, Lv> {
+    fn drop(&mut self) {
+        // Safety: never touched again
+        unsafe { ManuallyDrop::drop(&mut self.shard) };
+
+        if let Some(listener) = &self.observers.listener {
+            for (pointer, cause) in self.removed.drain(..) {
+                listener.on_removal(pointer, cause);
+            }
+        }
+    }
+}
+
+type Entry<'a, T, Lv, Ls, S> =
+    crate::Entry<OccupiedEntry<'a, T, Lv, Ls, S>, VacantEntry<'a, T, Lv, Ls, S>>;
+
 struct OccupiedEntry<'a, T: crate::Value, Lv, Ls, S> {
-    cache: &'a SyncCache<T, Lv, Ls, S>,
-    shard: RwLockWriteGuard<'a, Shard<T, Lv, Ls>>,
+    cache: &'a Shared<T, Lv, Ls, S>,
+    shard: ShardWriteGuard<'a, Shard<T, Lv, Ls>, T, Lv>,
     shard_index: usize,
     bucket: Bucket<Pointer<T, Lv>>,
 }
@@ -447,34 +1470,27 @@ where
     }
 
     fn replace(mut self, value: T) -> Pointer<T, Lv> {
+        let (shard, removed) = self.shard.split();
         // XX Safety
-        let pointer = unsafe { self.bucket.as_mut() };
-        debug_assert!(value.key() == pointer.key());
-
-        self.shard.layer.remove::<ResolveLayer>(pointer);
-        let shard = &mut *self.shard;
-        let replace = shard.layer.write::<ResolveLayer>(Write {
-            cache: self.cache,
-            shard_values: &mut shard.values,
-            shard_index: self.shard_index,
-            target: value,
-        });
-        *pointer = replace.clone();
-
-        replace
+        unsafe {
+            self.cache
+                .replace_in(shard, removed, self.shard_index, &self.bucket, value)
+        }
     }
 
     fn remove(mut self) -> Pointer<T, Lv> {
         // XX Safety
         let (removed, _slot) = unsafe { self.shard.values.remove(self.bucket) };
         self.shard.layer.remove::<ResolveLayer>(&removed);
+        self.shard.removed(removed.clone(), RemovalCause::Explicit);
         removed
     }
 }
 
 struct Write<'a, T, Lv, Ls, S> {
-    cache: &'a SyncCache<T, Lv, Ls, S>,
+    cache: &'a Shared<T, Lv, Ls, S>,
     shard_values: &'a mut RawTable<Pointer<T, Lv>>,
+    removed: &'a mut Removed<T, Lv>,
     shard_index: usize,
     target: T,
 }
@@ -490,12 +1506,23 @@ where
     }
 
     fn remove(&mut self, pointer: &Pointer<T, Lv>) {
-        let (hash, shard_index) = self.cache.hash_and_shard(pointer.key());
-        debug_assert_eq!(shard_index, self.shard_index);
+        self.cache.unlink(
+            self.shard_index,
+            self.shard_values,
+            self.removed,
+            pointer,
+            RemovalCause::Evicted,
+        );
+    }
 
-        self.shard_values
-            .remove_entry(hash, |p| Arc::ptr_eq(&p.0, &pointer.0))
-            .expect("layer shard and map out of sync");
+    fn expire(&mut self, pointer: &Pointer<T, Lv>) {
+        self.cache.unlink(
+            self.shard_index,
+            self.shard_values,
+            self.removed,
+            pointer,
+            RemovalCause::Expired,
+        );
     }
 
     fn write(self, layer: Lv) -> Pointer<T, Lv> {
@@ -507,8 +1534,8 @@ where
 }
 
 struct VacantEntry<'a, T, Lv, Ls, S> {
-    cache: &'a SyncCache<T, Lv, Ls, S>,
-    shard: RwLockWriteGuard<'a, Shard<T, Lv, Ls>>,
+    cache: &'a Shared<T, Lv, Ls, S>,
+    shard: ShardWriteGuard<'a, Shard<T, Lv, Ls>, T, Lv>,
     shard_index: usize,
     slot: InsertSlot,
     hash: u64,
@@ -525,21 +1552,290 @@ where
     fn insert(mut self, value: T) -> Pointer<T, Lv> {
         debug_assert_eq!(self.hash, self.cache.hash_builder.hash_one(value.key()));
 
-        let shard = &mut *self.shard;
-        let insert = shard.layer.write::<ResolveLayer>(Write {
-            cache: self.cache,
-            shard_values: &mut shard.values,
-            shard_index: self.shard_index,
-            target: value,
-        });
-
+        let (shard, removed) = self.shard.split();
         // XX: Safety
         unsafe {
-            self.shard
-                .values
-                .insert_in_slot(self.hash, self.slot, insert.clone());
+            self.cache
+                .insert_in(shard, removed, self.shard_index, self.hash, self.slot, value)
         }
+    }
+}
 
-        insert
+#[test]
+fn set_capacity() {
+    use crate::{build::BuildCache, evict::write::EvictLeastRecentlyWritten};
+
+    struct Entry(u32);
+
+    impl crate::Value for Entry {
+        type Key = u32;
+
+        fn key(&self) -> &u32 {
+            &self.0
+        }
+    }
+
+    let cache = BuildCache::<Entry>::default()
+        .layer(EvictLeastRecentlyWritten::default())
+        .build_custom(|layer| {
+            SyncCacheBuilder::new()
+                .exact_shards(1)
+                .capacity(4)
+                .stats()
+                .build_with_layer(layer)
+        });
+
+    for i in 0..4 {
+        cache.insert(Entry(i));
+    }
+    cache.set_capacity(2);
+    assert_eq!(cache.capacity(), 2);
+    assert_eq!(cache.len(), 2);
+    assert!(cache.get(&0).is_none());
+    assert!(cache.get(&3).is_some());
+    assert_eq!(cache.stats().unwrap().evictions, 2);
+
+    cache.set_capacity(8);
+    for i in 4..10 {
+        cache.insert(Entry(i));
+    }
+    assert_eq!(cache.len(), 8);
+}
+
+#[test]
+fn retain_drain_clear() {
+    use std::sync::Mutex;
+
+    use crate::{build::BuildCache, evict::read::EvictLeastRecentlyRead};
+
+    struct Entry(u32);
+
+    impl crate::Value for Entry {
+        type Key = u32;
+
+        fn key(&self) -> &u32 {
+            &self.0
+        }
+    }
+
+    let removed = Arc::new(Mutex::new(Vec::new()));
+    let cache = BuildCache::<Entry>::default()
+        .layer(EvictLeastRecentlyRead::default())
+        .build_custom(|layer| {
+            let removed = Arc::clone(&removed);
+            SyncCacheBuilder::new()
+                .shards(4)
+                .capacity(64)
+                .removal_listener(move |p: Pointer<Entry, _>, cause| {
+                    removed.lock().unwrap().push(((*p).0, cause))
+                })
+                .build_with_layer(layer)
+        });
+
+    for i in 0..10 {
+        cache.insert(Entry(i));
+    }
+    cache.retain(|e| e.0 % 2 == 0);
+    assert_eq!(cache.len(), 5);
+    assert!(cache.get(&1).is_none());
+    assert!(cache.get(&2).is_some());
+
+    let mut drained: Vec<_> = cache.drain().map(|p| (*p).0).collect();
+    drained.sort();
+    assert_eq!(drained, [0, 2, 4, 6, 8]);
+    assert_eq!(cache.len(), 0);
+
+    let removed = std::mem::take(&mut *removed.lock().unwrap());
+    assert_eq!(removed.len(), 10);
+    assert!(removed.iter().all(|&(k, cause)| match k % 2 {
+        0 => cause == RemovalCause::Cleared,
+        _ => cause == RemovalCause::Explicit,
+    }));
+
+    // the layer forgot everything too, so nothing gets evicted early
+    for i in 0..10 {
+        cache.insert(Entry(i));
+    }
+    assert_eq!(cache.len(), 10);
+    cache.clear();
+    assert_eq!(cache.len(), 0);
+    assert_eq!(cache.iter().count(), 0);
+}
+
+#[test]
+fn batches() {
+    use crate::{build::BuildCache, evict::read::EvictLeastRecentlyRead};
+
+    #[derive(Debug, PartialEq)]
+    struct Entry(u32, &'static str);
+
+    impl crate::Value for Entry {
+        type Key = u32;
+
+        fn key(&self) -> &u32 {
+            &self.0
+        }
+    }
+
+    let cache = BuildCache::<Entry>::default()
+        .layer(EvictLeastRecentlyRead::default())
+        .build_custom(|layer| {
+            SyncCacheBuilder::new()
+                .shards(4)
+                .capacity(64)
+                .stats()
+                .build_with_layer(layer)
+        });
+
+    let inserted = cache.insert_many((0..8).map(|i| Entry(i, "a")).chain([Entry(3, "b")]));
+    assert_eq!(inserted.len(), 9);
+    assert_eq!(*inserted[3], Entry(3, "a"));
+    assert_eq!(*inserted[8], Entry(3, "b"));
+    assert_eq!(cache.len(), 8);
+
+    let found = cache.get_many(&[3, 100, 0]);
+    assert_eq!(found[0].as_deref(), Some(&Entry(3, "b")));
+    assert!(found[1].is_none());
+    assert_eq!(found[2].as_deref(), Some(&Entry(0, "a")));
+    let stats = cache.stats().unwrap();
+    assert_eq!((stats.hits, stats.misses), (2, 1));
+
+    let removed = cache.remove_many(&[1, 1, 2]);
+    assert_eq!(removed[0].as_deref(), Some(&Entry(1, "a")));
+    assert!(removed[1].is_none());
+    assert_eq!(removed[2].as_deref(), Some(&Entry(2, "a")));
+    assert_eq!(cache.len(), 6);
+}
+
+#[test]
+fn run_maintenance() {
+    use std::sync::atomic::AtomicBool;
+
+    use crate::{build::BuildCache, expire::Expire, Value as _};
+
+    struct Entry(u32, AtomicBool);
+
+    impl crate::Value for Entry {
+        type Key = u32;
+
+        fn key(&self) -> &u32 {
+            &self.0
+        }
     }
+
+    impl Expire for Entry {
+        fn is_expired(&self) -> bool {
+            self.1.load(Ordering::Relaxed)
+        }
+    }
+
+    let cache = BuildCache::<Entry>::default()
+        .expire()
+        .build_custom(|layer| {
+            SyncCacheBuilder::new()
+                .exact_shards(4)
+                .stats()
+                .build_with_layer(layer)
+        });
+
+    let entries: Vec<_> = (0..200)
+        .map(|i| cache.insert(Entry(i, AtomicBool::new(false))))
+        .collect();
+    for entry in entries.iter().filter(|p| p.key() % 2 == 1) {
+        entry.1.store(true, Ordering::Relaxed);
+    }
+
+    cache.run_maintenance(Duration::MAX);
+    assert_eq!(cache.len(), 100);
+    assert!(cache.iter().all(|p| p.key() % 2 == 0));
+    let stats = cache.stats().unwrap();
+    assert_eq!(stats.expirations, 100);
+    assert_eq!(stats.requests(), 0);
+
+    let cache = BuildCache::<Entry>::default()
+        .expire()
+        .build_custom(|layer| {
+            SyncCacheBuilder::new()
+                .exact_shards(4)
+                .background_maintenance(Duration::from_millis(1), Duration::from_millis(10))
+                .build_with_layer(layer)
+        });
+    cache.insert(Entry(0, AtomicBool::new(true)));
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while cache.len() > 0 {
+        assert!(Instant::now() < deadline, "background maintenance never ran");
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
+
+#[test]
+fn get_or_compute() {
+    use std::sync::mpsc;
+
+    use crate::build::BuildCache;
+
+    struct Entry(u32, &'static str);
+
+    impl crate::Value for Entry {
+        type Key = u32;
+
+        fn key(&self) -> &u32 {
+            &self.0
+        }
+    }
+
+    let cache = BuildCache::<Entry>::default().build_custom(|layer| {
+        SyncCacheBuilder::new()
+            .exact_shards(1)
+            .stats()
+            .build_with_layer(layer)
+    });
+
+    let (started, computing) = mpsc::channel();
+    let (finish, waiting) = mpsc::channel::<()>();
+    let cache = &cache;
+    std::thread::scope(|scope| {
+        let slow = scope.spawn(move || {
+            cache.get_or_compute(&0, || {
+                started.send(()).unwrap();
+                waiting.recv().unwrap();
+                Entry(0, "slow")
+            })
+        });
+        computing.recv().unwrap();
+
+        // The shard isn't locked while computing
+        cache.insert(Entry(1, "other"));
+        assert_eq!(cache.get(&1).unwrap().1, "other");
+
+        let waiter = scope.spawn(|| cache.or_insert_with(&0, || Entry(0, "waiter")));
+        while cache.stats().unwrap().coalesced_loads == 0 {
+            std::thread::yield_now();
+        }
+        finish.send(()).unwrap();
+
+        assert_eq!(slow.join().unwrap().1, "slow");
+        assert_eq!(waiter.join().unwrap().1, "slow");
+    });
+
+    // A panicking computation lets the next caller have a go
+    let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        cache.get_or_compute(&2, || panic!("failed to compute"))
+    }));
+    assert!(panicked.is_err());
+    assert_eq!(cache.get_or_compute(&2, || Entry(2, "retried")).1, "retried");
+
+    // As does one that asks for its own key
+    let reentered = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        cache.get_or_compute(&3, || {
+            cache.or_insert_with(&3, || Entry(3, "inner"));
+            Entry(3, "outer")
+        })
+    }));
+    assert!(reentered.is_err());
+    assert_eq!(cache.get_or_compute(&3, || Entry(3, "retried")).1, "retried");
+
+    let stats = cache.stats().unwrap();
+    assert_eq!(stats.loads, 3);
+    assert_eq!(stats.coalesced_loads, 1);
 }
@@ -0,0 +1,530 @@
+use std::{
+    borrow::Borrow,
+    hash::{BuildHasher, Hash},
+    ops::Deref,
+    sync::Arc,
+};
+
+use crossbeam_utils::CachePadded;
+use hashbrown::hash_map::DefaultHashBuilder;
+use parking_lot::RwLock;
+use scc::{ebr::Guard, HashIndex};
+
+use crate::{
+    layer::{self, Layer, ReadLock, ReadResult, Shard as ShardLayer},
+    listener::{RemovalCause, RemovalListener},
+    stats::CacheStats,
+    Cache,
+};
+
+use super::{
+    shard_for_hash, Observers, Pointer, Removed, ResolveLayer, ShardWriteGuard, SyncCacheBuilder,
+    Value,
+};
+
+/// Like [`SyncCache`](super::SyncCache), but values live in an [`scc::HashIndex`] rather than
+/// behind the shard locks. The locks only guard the layer shards, so reads through layers with
+/// [`ReadLock::None`] never block, at the cost of owned, cloneable keys.
+pub struct ScalableCache<T, Lv, Ls, S = DefaultHashBuilder>
+where
+    T: crate::Value,
+    T::Key: Sized,
+    S: BuildHasher,
+{
+    index: HashIndex<T::Key, Pointer<T, Lv>, S>,
+    shards: Box<[CachePadded<RwLock<Ls>>]>,
+    hash_builder: S,
+    mask: usize,
+    observers: Observers<T, Lv>,
+}
+
+impl<S, N> SyncCacheBuilder<S, N> {
+    pub fn build_scalable_with_layer<T, L, Lv, Ls>(self, layer: L) -> ScalableCache<T, Lv, Ls, S>
+    where
+        T: crate::Value,
+        T::Key: Sized,
+        L: Layer<Pointer<T, Lv>, Value = Lv, Shard = Ls>,
+        N: RemovalListener<Pointer<T, Lv>> + Send + Sync + 'static,
+        S: BuildHasher + Clone,
+    {
+        let capacity = self
+            .capacity
+            .unwrap_or_else(|| self.shards.saturating_mul(16));
+        let capacity_per_shard = capacity.div_ceil(self.shards);
+        let expected_len = layer
+            .expected_len(capacity_per_shard)
+            .saturating_mul(self.shards);
+
+        let shards = std::iter::repeat_with(|| {
+            CachePadded::new(RwLock::new(layer.new_shard(capacity_per_shard)))
+        })
+        .take(self.shards)
+        .collect();
+
+        ScalableCache {
+            index: HashIndex::with_capacity_and_hasher(expected_len, self.hash_builder.clone()),
+            shards,
+            hash_builder: self.hash_builder,
+            mask: self.shards - 1,
+            observers: Observers::new(self.listener, self.stats, self.shards),
+        }
+    }
+}
+
+impl<T, Lv, Ls, S> Cache<T> for ScalableCache<T, Lv, Ls, S>
+where
+    T: crate::Value + 'static,
+    T::Key: Sized + Clone,
+    Lv: 'static,
+    Ls: ShardLayer<Pointer<T, Lv>, Value = Lv>,
+    S: BuildHasher,
+{
+    type Pointer = Pointer<T, Lv>;
+
+    fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    fn iter(&self) -> impl Iterator<Item = Self::Pointer> {
+        let pointers: Vec<_> = self
+            .index
+            .iter(&Guard::new())
+            .map(|(_key, pointer)| pointer.clone())
+            .collect();
+
+        pointers
+            .into_iter()
+            .filter(move |pointer| match Ls::ITER_READ_LOCK {
+                ReadLock::None => true,
+                ReadLock::Ref | ReadLock::Mut => self.iter_read(pointer),
+            })
+    }
+
+    fn get<K>(&self, key: &K) -> Option<Self::Pointer>
+    where
+        T::Key: Borrow<K>,
+        K: ?Sized + Hash + Eq,
+    {
+        let shard_index = self.shard_index(key);
+        let found = match Ls::READ_LOCK {
+            ReadLock::None => self.index.peek_with(key, |_key, p| p.clone()),
+            ReadLock::Ref => {
+                // Hold the shard lock before looking so the layer can't drop the pointer under us
+                let shard = self.shards[shard_index].read();
+                match self.index.peek_with(key, |_key, p| p.clone()) {
+                    Some(pointer) => match shard.read_ref::<ResolveLayer>(&pointer) {
+                        ReadResult::Retain => Some(pointer),
+                        ReadResult::Remove => {
+                            drop(shard);
+                            self.expire(shard_index, &pointer);
+                            None
+                        }
+                    },
+                    None => None,
+                }
+            }
+            ReadLock::Mut => {
+                return match self.entry(key) {
+                    crate::Entry::Occupied(o) => Some(crate::OccupiedEntry::into_pointer(o)),
+                    crate::Entry::Vacant(_) => None,
+                }
+            }
+        };
+        self.observers.record_read(shard_index, found.is_some());
+        if found.is_none() && Ls::OBSERVES_MISSES {
+            self.write_shard(shard_index).miss::<ResolveLayer>(key);
+        }
+        found
+    }
+
+    fn entry<'c, K>(
+        &'c self,
+        key: &K,
+    ) -> crate::Entry<
+        impl crate::OccupiedEntry<Pointer = Self::Pointer> + 'c,
+        impl crate::VacantEntry<Pointer = Self::Pointer> + 'c,
+    >
+    where
+        T::Key: Borrow<K>,
+        K: ?Sized + Hash + Eq,
+    {
+        self.lookup(key, true)
+    }
+
+    fn insert(&self, value: T) -> Self::Pointer {
+        match self.lookup(value.key(), false) {
+            crate::Entry::Occupied(o) => crate::OccupiedEntry::replace(o, value),
+            crate::Entry::Vacant(v) => crate::VacantEntry::insert(v, value),
+        }
+    }
+
+    fn remove_if<K>(&self, key: &K, f: impl FnOnce(&T) -> bool) -> Option<Self::Pointer>
+    where
+        T::Key: Borrow<K>,
+        K: ?Sized + Hash + Eq,
+    {
+        match self.lookup(key, false) {
+            crate::Entry::Occupied(o) if f(crate::OccupiedEntry::value(&o)) => {
+                Some(crate::OccupiedEntry::remove(o))
+            }
+            _ => None,
+        }
+    }
+
+    fn retain(&self, mut f: impl FnMut(&T) -> bool) {
+        self.retain_pointers(RemovalCause::Explicit, |p| f(p), drop);
+    }
+
+    fn drain(&self) -> impl Iterator<Item = Self::Pointer> {
+        let mut drained = Vec::new();
+        self.retain_pointers(RemovalCause::Cleared, |_| false, |p| drained.push(p));
+        drained.into_iter()
+    }
+
+    fn clear(&self) {
+        self.retain_pointers(RemovalCause::Cleared, |_| false, drop);
+    }
+
+    fn stats(&self) -> Option<CacheStats> {
+        self.observers.stats()
+    }
+}
+
+impl<T, Lv, Ls, S> ScalableCache<T, Lv, Ls, S>
+where
+    T: crate::Value + 'static,
+    T::Key: Sized + Clone,
+    Lv: 'static,
+    Ls: ShardLayer<Pointer<T, Lv>, Value = Lv>,
+    S: BuildHasher,
+{
+    fn shard_index(&self, key: &(impl Hash + ?Sized)) -> usize {
+        shard_for_hash(self.hash_builder.hash_one(key), self.mask)
+    }
+
+    fn write_shard(&self, shard_index: usize) -> ShardWriteGuard<'_, Ls, T, Lv> {
+        ShardWriteGuard::new(&self.observers, &self.shards[shard_index], shard_index)
+    }
+
+    fn lookup<K>(&self, key: &K, record_stats: bool) -> Entry<'_, T, Lv, Ls, S>
+    where
+        T::Key: Borrow<K>,
+        K: ?Sized + Hash + Eq,
+    {
+        let shard_index = self.shard_index(key);
+        let mut shard = self.write_shard(shard_index);
+
+        let Some(pointer) = self.index.peek_with(key, |_key, p| p.clone()) else {
+            if record_stats {
+                self.observers.record_read(shard_index, false);
+                shard.miss::<ResolveLayer>(key);
+            }
+            return crate::Entry::Vacant(VacantEntry { cache: self, shard });
+        };
+
+        let read = shard.read_mut::<ResolveLayer>(&pointer);
+        if record_stats {
+            self.observers
+                .record_read(shard_index, read == ReadResult::Retain);
+        }
+        match read {
+            ReadResult::Retain => crate::Entry::Occupied(OccupiedEntry {
+                cache: self,
+                shard,
+                pointer,
+            }),
+            ReadResult::Remove => {
+                shard.remove::<ResolveLayer>(&pointer);
+                self.index.remove(pointer.key());
+                shard.removed(pointer, RemovalCause::Expired);
+                if record_stats {
+                    shard.miss::<ResolveLayer>(key);
+                }
+                crate::Entry::Vacant(VacantEntry { cache: self, shard })
+            }
+        }
+    }
+
+    /// Remove `pointer` if it's still the cached value for its key.
+    fn expire(&self, shard_index: usize, pointer: &Pointer<T, Lv>) {
+        let mut shard = self.write_shard(shard_index);
+        if self.is_current(pointer) {
+            shard.remove::<ResolveLayer>(pointer);
+            self.index.remove(pointer.key());
+            shard.removed(pointer.clone(), RemovalCause::Expired);
+        }
+    }
+
+    fn iter_read(&self, pointer: &Pointer<T, Lv>) -> bool {
+        let shard_index = self.shard_index(pointer.key());
+        let mut shard = self.write_shard(shard_index);
+        if !self.is_current(pointer) {
+            return false;
+        }
+
+        match shard.iter_read_mut::<ResolveLayer>(pointer) {
+            ReadResult::Retain => true,
+            ReadResult::Remove => {
+                shard.remove::<ResolveLayer>(pointer);
+                self.index.remove(pointer.key());
+                shard.removed(pointer.clone(), RemovalCause::Expired);
+                false
+            }
+        }
+    }
+
+    /// Only goes through what was cached when it started, so values written meanwhile are kept
+    /// whatever `f` says.
+    fn retain_pointers(
+        &self,
+        cause: RemovalCause,
+        mut f: impl FnMut(&Pointer<T, Lv>) -> bool,
+        mut on_removed: impl FnMut(Pointer<T, Lv>),
+    ) {
+        let mut by_shard = vec![Vec::new(); self.shards.len()];
+        for (key, pointer) in self.index.iter(&Guard::new()) {
+            by_shard[self.shard_index(key)].push(pointer.clone());
+        }
+
+        for (shard_index, pointers) in by_shard.into_iter().enumerate() {
+            let mut shard = self.write_shard(shard_index);
+            for pointer in pointers {
+                if self.is_current(&pointer) && !f(&pointer) {
+                    shard.remove::<ResolveLayer>(&pointer);
+                    self.index.remove(pointer.key());
+                    shard.removed(pointer.clone(), cause);
+                    on_removed(pointer);
+                }
+            }
+        }
+    }
+
+    fn is_current(&self, pointer: &Pointer<T, Lv>) -> bool {
+        self.index
+            .peek_with(pointer.key(), |_key, p| Arc::ptr_eq(&p.0, &pointer.0))
+            .unwrap_or(false)
+    }
+}
+
+type Entry<'a, T, Lv, Ls, S> =
+    crate::Entry<OccupiedEntry<'a, T, Lv, Ls, S>, VacantEntry<'a, T, Lv, Ls, S>>;
+
+struct OccupiedEntry<'a, T, Lv, Ls, S>
+where
+    T: crate::Value,
+    T::Key: Sized,
+    S: BuildHasher,
+{
+    cache: &'a ScalableCache<T, Lv, Ls, S>,
+    shard: ShardWriteGuard<'a, Ls, T, Lv>,
+    pointer: Pointer<T, Lv>,
+}
+
+impl<T, Lv, Ls, S> crate::OccupiedEntry for OccupiedEntry<'_, T, Lv, Ls, S>
+where
+    T: crate::Value + 'static,
+    T::Key: Sized + Clone,
+    Lv: 'static,
+    Ls: ShardLayer<Pointer<T, Lv>, Value = Lv>,
+    S: BuildHasher,
+{
+    type Pointer = Pointer<T, Lv>;
+
+    fn pointer(&self) -> Pointer<T, Lv> {
+        self.pointer.clone()
+    }
+
+    fn into_pointer(self) -> Pointer<T, Lv> {
+        self.pointer
+    }
+
+    fn value(&self) -> &T {
+        &self.pointer
+    }
+
+    fn replace(mut self, value: T) -> Pointer<T, Lv> {
+        debug_assert!(value.key() == self.pointer.key());
+
+        self.shard.remove::<ResolveLayer>(&self.pointer);
+        let shard_index = self.shard.shard_index;
+        let (shard, removed) = self.shard.split();
+        let replace = shard.write::<ResolveLayer>(Write {
+            cache: self.cache,
+            removed,
+            shard_index,
+            target: value,
+        });
+        self.cache
+            .index
+            .get(replace.key())
+            .expect("shard lock keeps the key cached")
+            .update(replace.clone());
+        self.shard.removed(self.pointer, RemovalCause::Replaced);
+
+        replace
+    }
+
+    fn remove(mut self) -> Pointer<T, Lv> {
+        self.shard.remove::<ResolveLayer>(&self.pointer);
+        self.cache.index.remove(self.pointer.key());
+        self.shard
+            .removed(self.pointer.clone(), RemovalCause::Explicit);
+        self.pointer
+    }
+}
+
+struct VacantEntry<'a, T, Lv, Ls, S>
+where
+    T: crate::Value,
+    T::Key: Sized,
+    S: BuildHasher,
+{
+    cache: &'a ScalableCache<T, Lv, Ls, S>,
+    shard: ShardWriteGuard<'a, Ls, T, Lv>,
+}
+
+impl<T, Lv, Ls, S> crate::VacantEntry for VacantEntry<'_, T, Lv, Ls, S>
+where
+    T: crate::Value + 'static,
+    T::Key: Sized + Clone,
+    Lv: 'static,
+    Ls: ShardLayer<Pointer<T, Lv>, Value = Lv>,
+    S: BuildHasher,
+{
+    type Pointer = Pointer<T, Lv>;
+
+    fn insert(mut self, value: T) -> Pointer<T, Lv> {
+        let shard_index = self.shard.shard_index;
+        let (shard, removed) = self.shard.split();
+        let insert = shard.write::<ResolveLayer>(Write {
+            cache: self.cache,
+            removed,
+            shard_index,
+            target: value,
+        });
+
+        let inserted = self
+            .cache
+            .index
+            .insert(insert.key().clone(), insert.clone());
+        debug_assert!(inserted.is_ok(), "shard lock keeps the key vacant");
+
+        insert
+    }
+}
+
+struct Write<'a, T, Lv, Ls, S>
+where
+    T: crate::Value,
+    T::Key: Sized,
+    S: BuildHasher,
+{
+    cache: &'a ScalableCache<T, Lv, Ls, S>,
+    removed: &'a mut Removed<T, Lv>,
+    shard_index: usize,
+    target: T,
+}
+
+impl<T, Lv, Ls, S> Write<'_, T, Lv, Ls, S>
+where
+    T: crate::Value + 'static,
+    T::Key: Sized + Clone,
+    Lv: 'static,
+    Ls: ShardLayer<Pointer<T, Lv>, Value = Lv>,
+    S: BuildHasher,
+{
+    fn unlink(&mut self, pointer: &Pointer<T, Lv>, cause: RemovalCause) {
+        debug_assert_eq!(self.cache.shard_index(pointer.key()), self.shard_index);
+
+        let removed = self.cache.index.remove(pointer.key());
+        debug_assert!(removed, "layer shard and index out of sync");
+        self.cache.observers.record_removal(
+            self.shard_index,
+            self.removed,
+            pointer.clone(),
+            cause,
+        );
+    }
+}
+
+impl<T, Lv, Ls, S> layer::Write<Pointer<T, Lv>, Lv> for Write<'_, T, Lv, Ls, S>
+where
+    T: crate::Value + 'static,
+    T::Key: Sized + Clone,
+    Lv: 'static,
+    Ls: ShardLayer<Pointer<T, Lv>, Value = Lv>,
+    S: BuildHasher,
+{
+    fn target(&self) -> &<Pointer<T, Lv> as Deref>::Target {
+        &self.target
+    }
+
+    fn remove(&mut self, pointer: &Pointer<T, Lv>) {
+        self.unlink(pointer, RemovalCause::Evicted);
+    }
+
+    fn expire(&mut self, pointer: &Pointer<T, Lv>) {
+        self.unlink(pointer, RemovalCause::Expired);
+    }
+
+    fn write(self, layer: Lv) -> Pointer<T, Lv> {
+        Pointer(Arc::new(Value {
+            value: self.target,
+            layer,
+        }))
+    }
+}
+
+#[test]
+fn scalable() {
+    use crate::{build::BuildCache, evict::write::EvictLeastRecentlyWritten, expire::Expire};
+
+    struct Entry(u32, bool);
+
+    impl crate::Value for Entry {
+        type Key = u32;
+
+        fn key(&self) -> &u32 {
+            &self.0
+        }
+    }
+
+    impl Expire for Entry {
+        fn is_expired(&self) -> bool {
+            self.1
+        }
+    }
+
+    let cache = BuildCache::<Entry>::default()
+        .expire()
+        .layer(EvictLeastRecentlyWritten::default())
+        .build_custom(|layer| {
+            SyncCacheBuilder::new()
+                .exact_shards(1)
+                .capacity(2)
+                .stats()
+                .build_scalable_with_layer(layer)
+        });
+
+    cache.insert(Entry(1, false));
+    cache.insert(Entry(2, false));
+    cache.insert(Entry(2, false));
+    cache.insert(Entry(3, false));
+    assert_eq!(cache.len(), 2);
+    assert!(cache.get(&1).is_none());
+    assert!(cache.get(&2).is_some());
+
+    cache.insert(Entry(4, true));
+    assert!(cache.get(&4).is_none());
+    assert_eq!(cache.iter().count(), 1);
+    assert_eq!(cache.remove(&3).map(|p| (*p).0), Some(3));
+    assert_eq!(cache.len(), 0);
+
+    let stats = cache.stats().unwrap();
+    assert_eq!((stats.hits, stats.misses), (1, 2));
+    assert_eq!((stats.evictions, stats.expirations), (2, 1));
+
+    let cache = BuildCache::<Entry>::default().expire().build_scalable();
+    cache.insert(Entry(1, false));
+    assert!(cache.get(&1).is_some());
+}
@@ -1,6 +1,6 @@
 use std::{borrow::Borrow, marker::PhantomData, ops::Deref, hash::Hash};
 
-use crate::{Cache, Entry, OccupiedEntry, VacantEntry, Value};
+use crate::{stats::CacheStats, Cache, Entry, OccupiedEntry, VacantEntry, Value};
 
 
 pub(crate) struct CacheWrapper<C, W, F> {
@@ -151,4 +151,43 @@ where
     {
         self.cache.get(key).map(WrappedPointer)
     }
-}
\ No newline at end of file
+
+    fn get_many<'k, K>(&self, keys: impl IntoIterator<Item = &'k K>) -> Vec<Option<Self::Pointer>>
+    where
+        <T as Value>::Key: Borrow<K>,
+        K: ?Sized + Hash + Eq + 'k,
+    {
+        let found = self.cache.get_many(keys);
+        found.into_iter().map(|p| p.map(WrappedPointer)).collect()
+    }
+
+    fn insert_many(&self, values: impl IntoIterator<Item = T>) -> Vec<Self::Pointer> {
+        let inserted = self.cache.insert_many(values.into_iter().map(&self.wrap_fn));
+        inserted.into_iter().map(WrappedPointer).collect()
+    }
+
+    fn remove_many<'k, K>(&self, keys: impl IntoIterator<Item = &'k K>) -> Vec<Option<Self::Pointer>>
+    where
+        <T as Value>::Key: Borrow<K>,
+        K: ?Sized + Hash + Eq + 'k,
+    {
+        let removed = self.cache.remove_many(keys);
+        removed.into_iter().map(|p| p.map(WrappedPointer)).collect()
+    }
+
+    fn retain(&self, mut f: impl FnMut(&T) -> bool) {
+        self.cache.retain(|wrapped| f(wrapped))
+    }
+
+    fn drain(&self) -> impl Iterator<Item = Self::Pointer> {
+        self.cache.drain().map(WrappedPointer)
+    }
+
+    fn clear(&self) {
+        self.cache.clear()
+    }
+
+    fn stats(&self) -> Option<CacheStats> {
+        self.cache.stats()
+    }
+}